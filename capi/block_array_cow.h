@@ -0,0 +1,85 @@
+/* Apache License, Version 2.0
+ * (c) Campbell Barton, 2017 */
+
+/**
+ * C API for `block-array-cow`, an array store which de-duplicates
+ * blocks of data between arrays (copy-on-write).
+ *
+ * Build the shared & static libraries (the `block-array-cow-capi` package) using:
+ *
+ *     cargo build --release -p block-array-cow-capi
+ *
+ * This header is written by hand, keep in sync with `src/capi.rs`.
+ *
+ * All functions taking a store require a pointer returned by `barray_store_new`
+ * (which hasn't been freed). States which have been removed (or belong to another store)
+ * must not be used, errors abort.
+ */
+
+#ifndef __BLOCK_ARRAY_COW_H__
+#define __BLOCK_ARRAY_COW_H__
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/** Opaque array store. */
+typedef struct BArrayStore BArrayStore;
+
+/** Handle for a state in a store, zero for no state. */
+typedef uint64_t BArrayStateId;
+
+#define BARRAY_STATE_NONE ((BArrayStateId)0)
+
+/* Store */
+
+/**
+ * Create a new store.
+ *
+ * \param stride: The size of each element in bytes (1 for plain bytes).
+ * \param chunk_count: The number of elements in each chunk.
+ * \return The new store or NULL when `stride` or `chunk_count` are zero.
+ */
+BArrayStore *barray_store_new(size_t stride, size_t chunk_count);
+/** Free the store and all its states (NULL is ignored). */
+void barray_store_free(BArrayStore *bs);
+/** Remove all states. */
+void barray_store_clear(BArrayStore *bs);
+
+/** The size of all states as if they were stored separately. */
+size_t barray_store_calc_size_expanded_get(const BArrayStore *bs);
+/** The size of all chunks used by the store. */
+size_t barray_store_calc_size_compacted_get(const BArrayStore *bs);
+/** Check the internal state of the store is consistent (for testing). */
+bool barray_store_is_valid(const BArrayStore *bs);
+
+/* State */
+
+/**
+ * Add a state to the store.
+ *
+ * \param data: The array, `data_len` must be a multiple of the stride
+ * (may be NULL when `data_len` is zero).
+ * \param state_reference: The state to de-duplicate against,
+ * typically the previous state (may be `BARRAY_STATE_NONE`).
+ */
+BArrayStateId barray_store_state_add(BArrayStore *bs,
+                                     const void *data,
+                                     size_t data_len,
+                                     BArrayStateId state_reference);
+void barray_store_state_remove(BArrayStore *bs, BArrayStateId state);
+
+/** The size of the state's array in bytes. */
+size_t barray_store_state_size_get(const BArrayStore *bs, BArrayStateId state);
+/** Fill in `data`, which must be `barray_store_state_size_get` bytes. */
+void barray_store_state_data_get(const BArrayStore *bs, BArrayStateId state, void *data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* __BLOCK_ARRAY_COW_H__ */
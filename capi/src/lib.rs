@@ -0,0 +1,8 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+//! Shared & static libraries exporting the C API, see `block_array_cow.h`.
+
+extern crate block_array_cow as block_array_cow_rs;
+
+pub use block_array_cow_rs::capi::*;
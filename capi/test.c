@@ -0,0 +1,74 @@
+/* Apache License, Version 2.0
+ * (c) Campbell Barton, 2017 */
+
+/* Test the C API, see `Makefile`. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "block_array_cow.h"
+
+#define CHECK(expr) \
+  if (!(expr)) { \
+    fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #expr); \
+    exit(1); \
+  } ((void)0)
+
+int main(void)
+{
+  const size_t stride = 4;
+  const size_t data_len = stride * 4096;
+  unsigned char *data_a = malloc(data_len);
+  unsigned char *data_b = malloc(data_len);
+  unsigned char *data_dst = malloc(data_len);
+  size_t i;
+
+  for (i = 0; i < data_len; i++) {
+    data_a[i] = (unsigned char)(i % 251);
+  }
+  memcpy(data_b, data_a, data_len);
+  /* A small change, most chunks are shared. */
+  data_b[data_len / 2] ^= 0xff;
+
+  /* Invalid sizes. */
+  CHECK(barray_store_new(0, 64) == NULL);
+  CHECK(barray_store_new(stride, 0) == NULL);
+
+  BArrayStore *bs = barray_store_new(stride, 64);
+  CHECK(bs != NULL);
+  BArrayStateId state_a = barray_store_state_add(bs, data_a, data_len, BARRAY_STATE_NONE);
+  BArrayStateId state_b = barray_store_state_add(bs, data_b, data_len, state_a);
+  BArrayStateId state_empty = barray_store_state_add(bs, NULL, 0, state_b);
+  CHECK(state_a != BARRAY_STATE_NONE);
+  CHECK(state_b != BARRAY_STATE_NONE);
+  CHECK(barray_store_is_valid(bs));
+
+  CHECK(barray_store_calc_size_expanded_get(bs) == data_len * 2);
+  CHECK(barray_store_calc_size_compacted_get(bs) < data_len + (data_len / 4));
+
+  CHECK(barray_store_state_size_get(bs, state_a) == data_len);
+  barray_store_state_data_get(bs, state_a, data_dst);
+  CHECK(memcmp(data_a, data_dst, data_len) == 0);
+  barray_store_state_data_get(bs, state_b, data_dst);
+  CHECK(memcmp(data_b, data_dst, data_len) == 0);
+  CHECK(barray_store_state_size_get(bs, state_empty) == 0);
+
+  barray_store_state_remove(bs, state_a);
+  barray_store_state_remove(bs, state_empty);
+  CHECK(barray_store_is_valid(bs));
+  CHECK(barray_store_calc_size_compacted_get(bs) == data_len);
+  barray_store_state_data_get(bs, state_b, data_dst);
+  CHECK(memcmp(data_b, data_dst, data_len) == 0);
+
+  barray_store_clear(bs);
+  CHECK(barray_store_calc_size_expanded_get(bs) == 0);
+  barray_store_free(bs);
+
+  free(data_a);
+  free(data_b);
+  free(data_dst);
+
+  printf("C API: OK\n");
+  return 0;
+}
@@ -0,0 +1,336 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+//! Typed index arena, elements are referenced by `ArenaId` instead of pointers.
+//!
+//! Freed slots are reused, each slot stores a generation
+//! so ids of freed elements are detected instead of accessing an unrelated element.
+//! Slots are retired (never reused) once their generation runs out.
+
+// allow some unused utility functions
+#![allow(dead_code)]
+
+use core::marker::PhantomData;
+use core::ops::{
+    Index,
+    IndexMut,
+};
+
+#[cfg(not(any(feature = "std", test)))]
+use alloc::vec::Vec;
+
+/// Generation of slots which are never reused,
+/// so ids of freed elements can't become valid again by the generation wrapping around.
+pub const GENERATION_RETIRED: u32 = u32::MAX;
+
+/// Reference to an element in an `Arena<T>`.
+pub struct ArenaId<T> {
+    index: u32,
+    generation: u32,
+    phantom: PhantomData<fn() -> T>,
+}
+
+// Implement manually, since deriving would require these traits for `T`.
+impl<T> Clone for ArenaId<T> {
+    #[inline] fn clone(&self) -> ArenaId<T> { *self }
+}
+impl<T> Copy for ArenaId<T> {}
+impl<T> PartialEq for ArenaId<T> {
+    #[inline] fn eq(&self, other: &ArenaId<T>) -> bool {
+        (self.index == other.index) && (self.generation == other.generation)
+    }
+}
+impl<T> Eq for ArenaId<T> {}
+impl<T> PartialOrd for ArenaId<T> {
+    #[inline] fn partial_cmp(&self, other: &ArenaId<T>) -> Option<::core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T> Ord for ArenaId<T> {
+    #[inline] fn cmp(&self, other: &ArenaId<T>) -> ::core::cmp::Ordering {
+        (self.index, self.generation).cmp(&(other.index, other.generation))
+    }
+}
+impl<T> ::core::hash::Hash for ArenaId<T> {
+    fn hash<H: ::core::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+impl<T> ::core::fmt::Debug for ArenaId<T> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+impl<T> ArenaId<T> {
+    /// The slot index, unique among all elements currently in the arena.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    #[inline]
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Create an id from values returned by `ArenaId.index` & `ArenaId.generation`.
+    #[inline]
+    pub fn from_parts(index: u32, generation: u32) -> ArenaId<T> {
+        ArenaId { index, generation, phantom: PhantomData }
+    }
+}
+
+#[derive(Clone)]
+enum ArenaSlot<T> {
+    Used {
+        generation: u32,
+        value: T,
+    },
+    Free {
+        // the generation of the next element in this slot,
+        // `GENERATION_RETIRED` for slots which aren't reused (and aren't in the free list).
+        generation: u32,
+        // next free slot
+        next: Option<u32>,
+    },
+}
+
+#[derive(Clone)]
+pub struct Arena<T> {
+    slots: Vec<ArenaSlot<T>>,
+    // first free slot
+    free: Option<u32>,
+    // only for book-keeping, not essential
+    len: usize,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Arena<T> {
+        Arena::new()
+    }
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Arena<T> {
+        Arena {
+            slots: Vec::new(),
+            free: None,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn alloc(&mut self, value: T) -> ArenaId<T> {
+        self.len += 1;
+        if let Some(index) = self.free {
+            let slot = &mut self.slots[index as usize];
+            let (generation, next) = match *slot {
+                ArenaSlot::Free { generation, next } => (generation, next),
+                ArenaSlot::Used { .. } => unreachable!(),
+            };
+            self.free = next;
+            *slot = ArenaSlot::Used { generation, value };
+            ArenaId { index, generation, phantom: PhantomData }
+        } else {
+            let index = self.slots.len();
+            assert!(index < u32::MAX as usize);
+            self.slots.push(ArenaSlot::Used { generation: 0, value });
+            ArenaId { index: index as u32, generation: 0, phantom: PhantomData }
+        }
+    }
+
+    /// Remove the element, returning it.
+    ///
+    /// Panics when `id` has already been freed.
+    pub fn free(&mut self, id: ArenaId<T>) -> T {
+        assert!(self.contains(id), "Arena: freeing an unknown id {:?}", id);
+        // elements never use `GENERATION_RETIRED`, so this can't overflow.
+        let generation = id.generation + 1;
+        let slot = ::core::mem::replace(
+            &mut self.slots[id.index as usize],
+            ArenaSlot::Free {
+                generation,
+                next: if generation != GENERATION_RETIRED { self.free } else { None },
+            },
+        );
+        if generation != GENERATION_RETIRED {
+            self.free = Some(id.index);
+        }
+        self.len -= 1;
+        match slot {
+            ArenaSlot::Used { value, .. } => value,
+            ArenaSlot::Free { .. } => unreachable!(),
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, id: ArenaId<T>) -> bool {
+        self.get(id).is_some()
+    }
+
+    #[inline]
+    pub fn get(&self, id: ArenaId<T>) -> Option<&T> {
+        match self.slots.get(id.index as usize) {
+            Some(&ArenaSlot::Used { generation, ref value }) if generation == id.generation => {
+                Some(value)
+            },
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, id: ArenaId<T>) -> Option<&mut T> {
+        match self.slots.get_mut(id.index as usize) {
+            Some(&mut ArenaSlot::Used { generation, ref mut value }) if generation == id.generation => {
+                Some(value)
+            },
+            _ => None,
+        }
+    }
+
+    /// Free all elements, ids from before clearing are never reused.
+    /// Return the number of bytes allocated for slots.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity() * ::core::mem::size_of::<ArenaSlot<T>>()
+    }
+
+    pub fn clear(&mut self) {
+        self.free = None;
+        for index in (0..self.slots.len()).rev() {
+            let generation = match self.slots[index] {
+                ArenaSlot::Used { generation, .. } => generation + 1,
+                ArenaSlot::Free { generation, .. } => generation,
+            };
+            if generation != GENERATION_RETIRED {
+                self.slots[index] = ArenaSlot::Free { generation, next: self.free };
+                self.free = Some(index as u32);
+            } else {
+                self.slots[index] = ArenaSlot::Free { generation, next: None };
+            }
+        }
+        self.len = 0;
+    }
+
+    /// Iterate over all elements (in order of their slots, not the order they were added).
+    pub fn iter<'a>(&'a self) -> ArenaIter<'a, T> {
+        ArenaIter {
+            iter: self.slots.iter().enumerate(),
+        }
+    }
+
+    /// Iterate over all slots as `(generation, value)`, `None` for free slots.
+    ///
+    /// Use with `Arena::from_slots` to create an arena with the same ids.
+    pub fn slots<'a>(&'a self) -> impl Iterator<Item = (u32, Option<&'a T>)> + 'a {
+        self.slots.iter().map(|slot| {
+            match *slot {
+                ArenaSlot::Used { generation, ref value } => (generation, Some(value)),
+                ArenaSlot::Free { generation, .. } => (generation, None),
+            }
+        })
+    }
+
+    /// Create an arena from `(generation, value)` slots, see `Arena.slots`.
+    ///
+    /// Panics when a value uses `GENERATION_RETIRED`.
+    pub fn from_slots<I: IntoIterator<Item = (u32, Option<T>)>>(slots: I) -> Arena<T> {
+        let mut arena = Arena::new();
+        for (generation, value) in slots {
+            if let Some(value) = value {
+                assert!(generation != GENERATION_RETIRED, "Arena: value in a retired slot");
+                arena.slots.push(ArenaSlot::Used { generation, value });
+                arena.len += 1;
+            } else {
+                arena.slots.push(ArenaSlot::Free { generation, next: None });
+            }
+        }
+        assert!(arena.slots.len() <= u32::MAX as usize);
+        // free slots are reused lowest first.
+        for index in (0..arena.slots.len()).rev() {
+            if let ArenaSlot::Free { generation, ref mut next } = arena.slots[index] {
+                if generation != GENERATION_RETIRED {
+                    *next = arena.free;
+                    arena.free = Some(index as u32);
+                }
+            }
+        }
+        arena
+    }
+
+    /// Iterate over all element ids, see `Arena.iter`.
+    pub fn ids<'a>(&'a self) -> ArenaIds<'a, T> {
+        ArenaIds {
+            iter: self.iter(),
+        }
+    }
+}
+
+impl<T> Index<ArenaId<T>> for Arena<T> {
+    type Output = T;
+    #[inline]
+    fn index(&self, id: ArenaId<T>) -> &T {
+        match self.get(id) {
+            Some(value) => value,
+            None => panic!("Arena: unknown id {:?}", id),
+        }
+    }
+}
+
+impl<T> IndexMut<ArenaId<T>> for Arena<T> {
+    #[inline]
+    fn index_mut(&mut self, id: ArenaId<T>) -> &mut T {
+        match self.get_mut(id) {
+            Some(value) => value,
+            None => panic!("Arena: unknown id {:?}", id),
+        }
+    }
+}
+
+pub struct ArenaIter<'a, T: 'a> {
+    iter: ::core::iter::Enumerate<::core::slice::Iter<'a, ArenaSlot<T>>>,
+}
+
+impl<'a, T> Iterator for ArenaIter<'a, T> {
+    type Item = (ArenaId<T>, &'a T);
+
+    fn next(&mut self) -> Option<(ArenaId<T>, &'a T)> {
+        for (index, slot) in &mut self.iter {
+            if let ArenaSlot::Used { generation, ref value } = *slot {
+                return Some((
+                    ArenaId { index: index as u32, generation, phantom: PhantomData },
+                    value,
+                ));
+            }
+        }
+        None
+    }
+}
+
+pub struct ArenaIds<'a, T: 'a> {
+    iter: ArenaIter<'a, T>,
+}
+
+impl<'a, T> Iterator for ArenaIds<'a, T> {
+    type Item = ArenaId<T>;
+
+    #[inline]
+    fn next(&mut self) -> Option<ArenaId<T>> {
+        self.iter.next().map(|(id, _)| id)
+    }
+}
+
+
+#[cfg(test)]
+#[path="tests_arena.rs"]
+mod test;
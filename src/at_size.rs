@@ -0,0 +1,107 @@
+// Apache License, Version 2.0
+// (c) Blender Foundation, 2016
+//     Campbell Barton, 2017
+
+//! Manage many array stores, one for each stride & chunk count.
+//!
+//! Useful when storing many arrays of different types together (in an undo step for example),
+//! since each type needs its own `BArrayStore`.
+
+use BArrayStore;
+use map::{
+    self,
+    Map,
+};
+
+///
+/// Collection of array stores, created on demand.
+///
+pub struct BArrayStoreAtSize {
+    // (stride, chunk_count) -> store
+    stores: Map<(usize, usize), BArrayStore>,
+}
+
+impl Default for BArrayStoreAtSize {
+    fn default() -> BArrayStoreAtSize {
+        BArrayStoreAtSize::new()
+    }
+}
+
+impl BArrayStoreAtSize {
+    pub fn new() -> BArrayStoreAtSize {
+        BArrayStoreAtSize {
+            stores: Map::new(),
+        }
+    }
+
+    /// Return the store for `stride` & `chunk_count`, creating it if needed.
+    ///
+    /// See `BArrayStore::new` for a description of the arguments.
+    pub fn ensure(
+        &mut self,
+        stride: usize,
+        chunk_count: usize,
+    ) -> &mut BArrayStore {
+        self.stores.entry((stride, chunk_count)).or_insert_with(|| {
+            BArrayStore::new(stride, chunk_count)
+        })
+    }
+
+    /// Return the store for `stride` & `chunk_count` if it has been created.
+    pub fn get(
+        &self,
+        stride: usize,
+        chunk_count: usize,
+    ) -> Option<&BArrayStore> {
+        self.stores.get(&(stride, chunk_count))
+    }
+
+    pub fn get_mut(
+        &mut self,
+        stride: usize,
+        chunk_count: usize,
+    ) -> Option<&mut BArrayStore> {
+        self.stores.get_mut(&(stride, chunk_count))
+    }
+
+    /// Return the number of stores created.
+    pub fn len(
+        &self,
+    ) -> usize {
+        self.stores.len()
+    }
+
+    pub fn is_empty(
+        &self,
+    ) -> bool {
+        self.stores.is_empty()
+    }
+
+    /// Iterate over all stores as: `((stride, chunk_count), store)` (in no particular order).
+    pub fn iter<'a>(
+        &'a self,
+    ) -> map::Iter<'a, (usize, usize), BArrayStore> {
+        self.stores.iter()
+    }
+
+    /// Free all stores (along with their states).
+    pub fn clear(
+        &mut self,
+    ) {
+        self.stores.clear();
+    }
+
+    /// Return the sum of `BArrayStore::calc_size_expanded_get` for all stores.
+    pub fn calc_size_expanded_get(
+        &self,
+    ) -> usize {
+        self.stores.values().fold(0, |size_accum, bs| size_accum + bs.calc_size_expanded_get())
+    }
+
+    /// Return the sum of `BArrayStore::calc_size_compacted_get` for all stores.
+    pub fn calc_size_compacted_get(
+        &self,
+    ) -> usize {
+        self.stores.values().fold(0, |size_accum, bs| size_accum + bs.calc_size_compacted_get())
+    }
+}
@@ -0,0 +1,387 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+//! `bacow`, de-duplicate a sequence of files (requires the `cli` feature).
+//!
+//! Each file is added to a `BArrayStore` using the previous file as a reference,
+//! printing the expanded & compacted sizes, useful to check how well data de-duplicates.
+//!
+//! Optionally write an archive containing all files,
+//! which can be inspected using the `list`, `extract` & `verify` sub-commands.
+
+extern crate bincode;
+extern crate block_array_cow;
+#[macro_use]
+extern crate serde;
+
+use std::fs;
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+use std::process;
+
+use block_array_cow::{
+    BArrayStore,
+    StateId,
+};
+
+const USAGE: &'static str = "\
+Usage: bacow [OPTIONS] FILES...
+       bacow list ARCHIVE
+       bacow extract ARCHIVE --state N [-o PATH]
+       bacow verify ARCHIVE
+
+De-duplicate a sequence of files, each file is added using the previous as a reference.
+
+Commands:
+  list               List the states in an archive, with their size and unique bytes
+                     (the size of data no other state uses).
+  extract            Write the contents of a state (to the standard output when -o isn't given).
+  verify             Check the archive is valid & the contents of all states match their checksums
+                     (a checksum is stored for each file & each of its chunks, mismatches are reported per chunk).
+
+Options:
+  --stride N         Size of each element in bytes, file sizes must be a multiple of this (default 1).
+  --chunk-count N    Number of elements in each chunk (default 1024).
+  --state N          The state to extract (as shown by list).
+  -o, --output PATH  Write an archive containing all files (the extracted file for extract).
+  -h, --help         Show this help.
+
+Use a path such as ./list for files named after commands.
+";
+
+/// Identifies archive files.
+const ARCHIVE_MAGIC: &'static [u8] = b"BACOW";
+/// Increment when the archive format changes.
+const ARCHIVE_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct ArchiveFile {
+    name: String,
+    state: StateId,
+    // see `checksum`.
+    checksum: u64,
+    // checksum of each chunk the state uses (in order), to report which chunk doesn't match.
+    chunk_checksums: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Archive {
+    // in the order they were added.
+    files: Vec<ArchiveFile>,
+    store: BArrayStore,
+}
+
+/// FNV-1a hash of the contents of each file & each chunk,
+/// since all chunks are used by at least one state (checked on reading),
+/// checking each state's chunks checks all chunks.
+fn checksum(data: &[u8]) -> u64 {
+    checksum_accum(CHECKSUM_INIT, data)
+}
+
+const CHECKSUM_INIT: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Continue a checksum, so a file's checksum can be calculated from its chunks.
+fn checksum_accum(mut hash: u64, data: &[u8]) -> u64 {
+    for &b in data {
+        hash = (hash ^ (b as u64)).wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    return hash;
+}
+
+/// Matches `bincode::serialize`, limiting the size read to `limit` bytes
+/// (so corrupt lengths fail instead of allocating).
+fn archive_options(
+    limit: u64,
+) -> impl bincode::Options {
+    use bincode::Options;
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+}
+
+fn archive_read(
+    path: &str,
+) -> Result<Archive, String> {
+    use bincode::Options;
+    let file = fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    // nothing in the archive can be larger than the file.
+    let file_len = file.metadata().map_err(|e| format!("{}: {}", path, e))?.len();
+    let mut r = io::BufReader::new(file);
+    let mut magic = [0u8; 5];
+    if r.read_exact(&mut magic).is_err() || &magic[..] != ARCHIVE_MAGIC {
+        return Err(format!("{}: not an archive", path));
+    }
+    let version: u32 = archive_options(file_len).deserialize_from(&mut r).map_err(|e| format!("{}: {}", path, e))?;
+    if version != ARCHIVE_VERSION {
+        return Err(format!("{}: unsupported archive version {} (expected {})", path, version, ARCHIVE_VERSION));
+    }
+    let archive: Archive = archive_options(file_len).deserialize_from(&mut r).map_err(|e| format!("{}: {}", path, e))?;
+    // the store is checked when reading, the states files refer to aren't.
+    for (i, file) in archive.files.iter().enumerate() {
+        if !archive.store.contains(file.state) {
+            return Err(format!("{}: state {} not found in the store ({})", path, i, file.name));
+        }
+    }
+    return Ok(archive);
+}
+
+fn archive_write(
+    archive: &Archive,
+    path: &str,
+) -> Result<(), String> {
+    let file = fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut w = io::BufWriter::new(file);
+    w.write_all(ARCHIVE_MAGIC).map_err(|e| format!("{}: {}", path, e))?;
+    bincode::serialize_into(&mut w, &(ARCHIVE_VERSION, archive)).map_err(|e| format!("{}: {}", path, e))?;
+    w.flush().map_err(|e| format!("{}: {}", path, e))?;
+    return Ok(());
+}
+
+#[derive(PartialEq)]
+enum Command {
+    Add,
+    List,
+    Extract,
+    Verify,
+}
+
+struct Args {
+    command: Command,
+    stride: usize,
+    chunk_count: usize,
+    state: Option<usize>,
+    output: Option<String>,
+    // the archive for all commands besides `Command::Add`.
+    files: Vec<String>,
+}
+
+fn args_parse_number(
+    arg: &str,
+    value: Option<String>,
+    min: usize,
+) -> Result<usize, String> {
+    let value = value.ok_or_else(|| format!("{} expects a value", arg))?;
+    match value.parse::<usize>() {
+        Ok(number) if number >= min => Ok(number),
+        _ => Err(format!("{} expects a number (at least {}), not {:?}", arg, min, value)),
+    }
+}
+
+/// Return the arguments, None when showing the help.
+fn args_parse<I: Iterator<Item = String>>(
+    args_iter: I,
+) -> Result<Option<Args>, String> {
+    let mut args_iter = args_iter.peekable();
+    let command = match args_iter.peek().map(|arg| arg.as_str()) {
+        Some("list") => Command::List,
+        Some("extract") => Command::Extract,
+        Some("verify") => Command::Verify,
+        _ => Command::Add,
+    };
+    if command != Command::Add {
+        args_iter.next();
+    }
+
+    let mut args = Args {
+        command: command,
+        stride: 1,
+        chunk_count: 1024,
+        state: None,
+        output: None,
+        files: Vec::new(),
+    };
+    let mut options_done = false;
+    while let Some(arg) = args_iter.next() {
+        if options_done || !arg.starts_with('-') {
+            args.files.push(arg);
+            continue;
+        }
+        // support `--arg=value` as well as `--arg value`.
+        let (arg, mut value) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (arg[..index].to_string(), Some(arg[(index + 1)..].to_string())),
+            _ => (arg, None),
+        };
+        let value_take = &mut || value.take().or_else(|| args_iter.next());
+        let is_add = args.command == Command::Add;
+        let is_extract = args.command == Command::Extract;
+        match arg.as_str() {
+            "-h" | "--help" => { return Ok(None); },
+            "--stride" if is_add => { args.stride = args_parse_number(&arg, value_take(), 1)?; },
+            "--chunk-count" if is_add => { args.chunk_count = args_parse_number(&arg, value_take(), 1)?; },
+            "--state" if is_extract => { args.state = Some(args_parse_number(&arg, value_take(), 0)?); },
+            "-o" | "--output" if is_add || is_extract => {
+                args.output = Some(value_take().ok_or_else(|| format!("{} expects a path", arg))?);
+            },
+            "--" => { options_done = true; },
+            _ => { return Err(format!("unknown argument {:?}", arg)); },
+        }
+    }
+    if args.command == Command::Add {
+        if args.files.is_empty() {
+            return Err("no files given".to_string());
+        }
+    } else if args.files.len() != 1 {
+        return Err("expected a single archive".to_string());
+    }
+    if args.command == Command::Extract && args.state.is_none() {
+        return Err("extract expects --state".to_string());
+    }
+    return Ok(Some(args));
+}
+
+fn percent(size: usize, size_total: usize) -> f64 {
+    if size_total != 0 {
+        return (size as f64 / size_total as f64) * 100.0;
+    } else {
+        return 0.0;
+    }
+}
+
+fn run_add(
+    args: Args,
+) -> Result<(), String> {
+    let mut bs = BArrayStore::new(args.stride, args.chunk_count);
+    let mut files: Vec<ArchiveFile> = Vec::with_capacity(args.files.len());
+
+    println!("{:>14} {:>14}  {}", "expanded", "compacted", "file");
+    let mut state_prev: Option<StateId> = None;
+    for name in args.files {
+        let data = fs::read(&name).map_err(|e| format!("{}: {}", name, e))?;
+        if data.len() % args.stride != 0 {
+            return Err(format!(
+                "{}: size {} isn't a multiple of the stride {}", name, data.len(), args.stride));
+        }
+        let size_compacted_prev = bs.calc_size_compacted_get();
+        let state = bs.state_add(&data[..], state_prev);
+        // the size of new chunks.
+        let size_compacted_added = bs.calc_size_compacted_get() - size_compacted_prev;
+        println!("{:>14} {:>14}  {}", data.len(), size_compacted_added, name);
+        let chunk_checksums: Vec<u64> = bs.state_ref(state).chunks().map(|chunk| checksum(&chunk[..])).collect();
+        files.push(ArchiveFile {
+            name: name,
+            state: state,
+            checksum: checksum(&data[..]),
+            chunk_checksums: chunk_checksums,
+        });
+        state_prev = Some(state);
+    }
+
+    let size_expanded = bs.calc_size_expanded_get();
+    let size_compacted = bs.calc_size_compacted_get();
+    println!("{:>14} {:>14}  total ({} files, compacted to {:.2}%)",
+             size_expanded, size_compacted, files.len(), percent(size_compacted, size_expanded));
+
+    if let Some(output) = args.output {
+        archive_write(&Archive { files: files, store: bs }, &output)?;
+    }
+    return Ok(());
+}
+
+fn run_list(
+    args: Args,
+) -> Result<(), String> {
+    let archive = archive_read(&args.files[0])?;
+    let bs = &archive.store;
+
+    println!("{:>6} {:>14} {:>14}  {}", "state", "size", "unique", "name");
+    for (i, file) in archive.files.iter().enumerate() {
+        println!("{:>6} {:>14} {:>14}  {}",
+                 i, bs.state_size_get(file.state), bs.calc_size_unique_get(file.state), file.name);
+    }
+
+    let size_expanded = bs.calc_size_expanded_get();
+    let size_compacted = bs.calc_size_compacted_get();
+    println!("{:>6} {:>14} {:>14}  total ({} states, compacted to {:.2}%)",
+             "", size_expanded, size_compacted, archive.files.len(), percent(size_compacted, size_expanded));
+    return Ok(());
+}
+
+fn run_extract(
+    args: Args,
+) -> Result<(), String> {
+    let archive = archive_read(&args.files[0])?;
+    let index = args.state.unwrap();
+    let file = archive.files.get(index).ok_or_else(|| {
+        format!("state {} not found (the archive has {} states)", index, archive.files.len())
+    })?;
+    let data = archive.store.state_data_get_alloc(file.state);
+    if let Some(output) = args.output {
+        fs::write(&output, &data[..]).map_err(|e| format!("{}: {}", output, e))?;
+    } else {
+        let stdout = io::stdout();
+        let mut w = stdout.lock();
+        w.write_all(&data[..]).and_then(|_| w.flush()).map_err(|e| format!("<stdout>: {}", e))?;
+    }
+    return Ok(());
+}
+
+fn run_verify(
+    args: Args,
+) -> Result<(), String> {
+    // reading checks references between states, chunk lists & chunks are valid.
+    let archive = archive_read(&args.files[0])?;
+    let bs = &archive.store;
+
+    let mut errors_len: usize = 0;
+    if let Err(errors) = bs.validate() {
+        for err in &errors {
+            println!("invalid store: {}", err);
+        }
+        errors_len += errors.len();
+    }
+    for (i, file) in archive.files.iter().enumerate() {
+        let mut file_checksum = CHECKSUM_INIT;
+        let mut chunks_len: usize = 0;
+        let mut offset: usize = 0;
+        for chunk in bs.state_ref(file.state).chunks() {
+            if file.chunk_checksums.get(chunks_len) != Some(&checksum(&chunk[..])) {
+                println!("state {}: chunk {} checksum mismatch at offset {} ({})", i, chunks_len, offset, file.name);
+                errors_len += 1;
+            }
+            file_checksum = checksum_accum(file_checksum, &chunk[..]);
+            chunks_len += 1;
+            offset += chunk.len();
+        }
+        if chunks_len != file.chunk_checksums.len() {
+            println!("state {}: has {} chunks, expected {} ({})", i, chunks_len, file.chunk_checksums.len(), file.name);
+            errors_len += 1;
+        }
+        if file_checksum != file.checksum {
+            println!("state {}: checksum mismatch ({})", i, file.name);
+            errors_len += 1;
+        }
+    }
+
+    if errors_len != 0 {
+        return Err(format!("{}: {} error(s) found", args.files[0], errors_len));
+    }
+    println!("{}: {} states OK", args.files[0], archive.files.len());
+    return Ok(());
+}
+
+fn run() -> Result<(), String> {
+    let args = match args_parse(::std::env::args().skip(1)).map_err(|e| format!("{} (see --help)", e))? {
+        Some(args) => args,
+        None => {
+            print!("{}", USAGE);
+            return Ok(());
+        },
+    };
+    match args.command {
+        Command::Add => run_add(args),
+        Command::List => run_list(args),
+        Command::Extract => run_extract(args),
+        Command::Verify => run_verify(args),
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        let _ = writeln!(io::stderr(), "bacow: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,178 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+//! C API (requires the `capi` feature), see `capi/block_array_cow.h`.
+//!
+//! Stores are passed as opaque pointers, states as `BArrayStateId` integers,
+//! zero is used for no state (so a `StateId` is stored with its index offset by one).
+//!
+//! Build the shared & static libraries using:
+//! `cargo build --release -p block-array-cow-capi` (see `capi/Cargo.toml`).
+//!
+//! Pointers must be valid (as documented in the header),
+//! invalid states & other errors abort (panics can't unwind out of `extern "C"` functions,
+//! which aborts since Rust 1.81, see `rust-version`).
+
+// Dereferencing pointers passed in from C can't be done without `unsafe`,
+// functions are only `unsafe` to convert their arguments.
+#![allow(unsafe_code)]
+// Documented once in the header.
+#![allow(clippy::missing_safety_doc)]
+
+use core::ffi::c_void;
+use core::ptr;
+use core::slice;
+
+use arena::ArenaId;
+use {
+    BArrayStore,
+    StateId,
+};
+
+/// `BArrayStateId` in the C API.
+type BArrayStateIdC = u64;
+
+const STATE_ID_NONE: BArrayStateIdC = 0;
+
+fn state_id_to_c(state: StateId) -> BArrayStateIdC {
+    ((state.0.generation() as u64) << 32) | ((state.0.index() as u64) + 1)
+}
+
+fn state_id_from_c(state: BArrayStateIdC) -> Option<StateId> {
+    if state == STATE_ID_NONE {
+        return None;
+    }
+    let index = (state & 0xffff_ffff) as u32;
+    debug_assert!(index != 0);
+    Some(StateId(ArenaId::from_parts(index - 1, (state >> 32) as u32)))
+}
+
+unsafe fn store_from_c<'a>(bs: *const BArrayStore) -> &'a BArrayStore {
+    assert!(!bs.is_null());
+    &*bs
+}
+
+unsafe fn store_from_c_mut<'a>(bs: *mut BArrayStore) -> &'a mut BArrayStore {
+    assert!(!bs.is_null());
+    &mut *bs
+}
+
+// # Store
+// []( { )
+
+#[no_mangle]
+pub extern "C" fn barray_store_new(
+    stride: usize,
+    chunk_count: usize,
+) -> *mut BArrayStore {
+    if stride == 0 || chunk_count == 0 {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(BArrayStore::new(stride, chunk_count)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn barray_store_free(
+    bs: *mut BArrayStore,
+) {
+    if !bs.is_null() {
+        drop(Box::from_raw(bs));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn barray_store_clear(
+    bs: *mut BArrayStore,
+) {
+    store_from_c_mut(bs).clear();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn barray_store_calc_size_expanded_get(
+    bs: *const BArrayStore,
+) -> usize {
+    store_from_c(bs).calc_size_expanded_get()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn barray_store_calc_size_compacted_get(
+    bs: *const BArrayStore,
+) -> usize {
+    store_from_c(bs).calc_size_compacted_get()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn barray_store_is_valid(
+    bs: *const BArrayStore,
+) -> bool {
+    store_from_c(bs).is_valid()
+}
+
+// []( } )
+
+
+// # State
+// []( { )
+
+#[no_mangle]
+pub unsafe extern "C" fn barray_store_state_add(
+    bs: *mut BArrayStore,
+    data: *const c_void,
+    data_len: usize,
+    state_reference: BArrayStateIdC,
+) -> BArrayStateIdC {
+    let data: &[u8] = if data_len == 0 {
+        &[]
+    } else {
+        assert!(!data.is_null());
+        slice::from_raw_parts(data as *const u8, data_len)
+    };
+    let bs = store_from_c_mut(bs);
+    // only checked in debug builds by `BArrayStore.state_add`.
+    assert!(data_len.is_multiple_of(bs.info.chunk_stride), "data_len must be a multiple of the stride");
+    let state = bs.state_add(data, state_id_from_c(state_reference));
+    state_id_to_c(state)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn barray_store_state_remove(
+    bs: *mut BArrayStore,
+    state: BArrayStateIdC,
+) {
+    let state = state_id_from_c(state).expect("state must be set");
+    store_from_c_mut(bs).state_remove(state);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn barray_store_state_size_get(
+    bs: *const BArrayStore,
+    state: BArrayStateIdC,
+) -> usize {
+    let state = state_id_from_c(state).expect("state must be set");
+    store_from_c(bs).state_size_get(state)
+}
+
+/// Fill in `data`, which must be `barray_store_state_size_get` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn barray_store_state_data_get(
+    bs: *const BArrayStore,
+    state: BArrayStateIdC,
+    data: *mut c_void,
+) {
+    let state = state_id_from_c(state).expect("state must be set");
+    let bs = store_from_c(bs);
+    let data_len = bs.state_size_get(state);
+    let data: &mut [u8] = if data_len == 0 {
+        &mut []
+    } else {
+        assert!(!data.is_null());
+        slice::from_raw_parts_mut(data as *mut u8, data_len)
+    };
+    bs.state_data_get(state, data);
+}
+
+// []( } )
+
+#[cfg(test)]
+#[path="tests_capi.rs"]
+mod test;
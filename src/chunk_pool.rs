@@ -0,0 +1,229 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+//! Share chunk data between stores, see `BArrayChunkPool` (requires the `std` feature).
+//!
+//! Each store keeps its own chunks (so de-duplication & memory statistics work per store),
+//! the pool only ensures chunks with identical contents use the same memory.
+
+use std::sync::{
+    Arc,
+    Mutex,
+    Weak,
+};
+
+use arena::Arena;
+use map::Map;
+use {
+    bchunk_data_table_key,
+    BArrayStore,
+    BChunk,
+    BChunkData,
+    BChunkId,
+    BChunkListId,
+};
+
+///
+/// Chunk data shared by any number of stores with the same stride & chunk count,
+/// see `BArrayStore.chunk_pool_set`.
+///
+/// Cloning returns another handle to the same pool.
+/// Data is freed once no store uses it (the pool doesn't keep data alive).
+///
+#[derive(Clone)]
+pub struct BArrayChunkPool {
+    stride: usize,
+    chunk_count: usize,
+    // (size, hash) -> data, see `bchunk_data_table_key`.
+    table: Arc<Mutex<BArrayChunkPoolTable>>,
+}
+
+struct BArrayChunkPoolTable {
+    data: Map<(usize, u32), Vec<Weak<Vec<u8>>>>,
+    // number of items in `data` (including data no longer in use).
+    data_len: usize,
+    // remove unused data when `data_len` exceeds this.
+    data_len_prune: usize,
+}
+
+/// Minimum value for `BArrayChunkPoolTable.data_len_prune`.
+const POOL_PRUNE_LEN_MIN: usize = 1024;
+
+impl BArrayChunkPoolTable {
+    /// Return the data to use for `data`, adding it when it isn't found.
+    fn ensure(
+        &mut self,
+        data: &Arc<Vec<u8>>,
+    ) -> Option<Arc<Vec<u8>>> {
+        let data_array = self.data.entry(bchunk_data_table_key(&data[..])).or_default();
+        let data_array_len = data_array.len();
+        data_array.retain(|data_other| data_other.strong_count() != 0);
+        self.data_len -= data_array_len - data_array.len();
+
+        for data_other in data_array.iter() {
+            if let Some(data_other) = data_other.upgrade() {
+                if Arc::ptr_eq(&data_other, data) {
+                    return None;
+                }
+                if data_other[..] == data[..] {
+                    return Some(data_other);
+                }
+            }
+        }
+        data_array.push(Arc::downgrade(data));
+        self.data_len += 1;
+        None
+    }
+
+    fn prune(
+        &mut self,
+    ) {
+        self.data.retain(|_, data_array| {
+            data_array.retain(|data_other| data_other.strong_count() != 0);
+            !data_array.is_empty()
+        });
+        self.data_len = self.data.values().map(|data_array| data_array.len()).sum();
+        self.data_len_prune = ::core::cmp::max(POOL_PRUNE_LEN_MIN, self.data_len * 2);
+    }
+}
+
+impl BArrayChunkPool {
+    /// Create a pool for stores created with the same arguments, see `BArrayStore::new`.
+    pub fn new(
+        stride: usize,
+        chunk_count: usize,
+    ) -> BArrayChunkPool {
+        BArrayChunkPool {
+            stride,
+            chunk_count,
+            table: Arc::new(Mutex::new(BArrayChunkPoolTable {
+                data: Map::new(),
+                data_len: 0,
+                data_len_prune: POOL_PRUNE_LEN_MIN,
+            })),
+        }
+    }
+
+    /// Return the number of chunks in use by any store
+    /// (chunks with identical contents are only counted once).
+    pub fn len(
+        &self,
+    ) -> usize {
+        let mut table = self.table.lock().unwrap();
+        table.prune();
+        table.data_len
+    }
+
+    pub fn is_empty(
+        &self,
+    ) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the amount of memory used by the data of all chunks in the pool,
+    /// the memory shared stores use (less than the sum of `BArrayStore.calc_size_compacted_get`
+    /// when chunks are shared).
+    ///
+    /// Note that delta chunks aren't pooled,
+    /// the memory used to store their changes isn't included.
+    pub fn calc_size_get(
+        &self,
+    ) -> usize {
+        let mut table = self.table.lock().unwrap();
+        table.prune();
+        let mut size_total: usize = 0;
+        for data_array in table.data.values() {
+            for data in data_array {
+                if let Some(data) = data.upgrade() {
+                    size_total += data.len();
+                }
+            }
+        }
+        size_total
+    }
+
+    /// Share the data of `chunk_ids` with other stores using this pool.
+    ///
+    /// * `force` Check chunks already checked by a pool
+    ///   (needed when a store changes pool).
+    fn chunks_ensure<I>(
+        &self,
+        chunks: &mut Arena<BChunk>,
+        chunk_ids: I,
+        force: bool,
+    )
+        where
+        I: Iterator<Item = BChunkId>,
+    {
+        let mut table = self.table.lock().unwrap();
+        for chunk_id in chunk_ids {
+            let chunk = &mut chunks[chunk_id];
+            if chunk.pooled && !force {
+                continue;
+            }
+            // delta chunks aren't pooled.
+            if let BChunkData::Array(ref mut chunk_data) = chunk.data {
+                if !chunk_data.is_empty() {
+                    if let Some(data) = table.ensure(chunk_data) {
+                        *chunk_data = data;
+                    }
+                }
+            }
+            chunk.pooled = true;
+        }
+        if table.data_len > table.data_len_prune {
+            table.prune();
+        }
+    }
+}
+
+impl BArrayStore {
+    /// Share chunk data with all other stores using `chunk_pool`,
+    /// so identical chunks in different stores are only stored once.
+    ///
+    /// The pool must be created with the same stride & chunk count as this store.
+    /// Existing chunks are added to the pool, None stops adding new chunks
+    /// (data already shared stays shared).
+    ///
+    /// Each store's statistics, clearing & removing states are unaffected by sharing,
+    /// use `BArrayChunkPool.calc_size_get` for the memory used by all stores together.
+    ///
+    /// The pool isn't serialized, it must be set again after loading a store.
+    pub fn chunk_pool_set(
+        &mut self,
+        chunk_pool: Option<BArrayChunkPool>,
+    ) {
+        if let Some(ref chunk_pool) = chunk_pool {
+            assert_eq!(self.info.chunk_stride, chunk_pool.stride);
+            assert_eq!(self.info.chunk_byte_size, chunk_pool.chunk_count * chunk_pool.stride);
+            let chunk_ids: Vec<BChunkId> = self.memory.chunk.ids().collect();
+            chunk_pool.chunks_ensure(&mut self.memory.chunk, chunk_ids.into_iter(), true);
+        }
+        self.info.chunk_pool = chunk_pool;
+    }
+
+    /// Return the pool set by `BArrayStore.chunk_pool_set`.
+    pub fn chunk_pool_get(
+        &self,
+    ) -> Option<&BArrayChunkPool> {
+        self.info.chunk_pool.as_ref()
+    }
+
+    /// Add new chunks to the pool (when set),
+    /// `chunk_list` limits the chunks checked to a single list, otherwise all chunks are checked.
+    pub(crate) fn chunk_pool_ensure(
+        &mut self,
+        chunk_list: Option<BChunkListId>,
+    ) {
+        if let Some(ref chunk_pool) = self.info.chunk_pool {
+            let memory = &mut self.memory;
+            if let Some(chunk_list) = chunk_list {
+                let chunk_refs = &memory.chunk_list[chunk_list].chunk_refs;
+                chunk_pool.chunks_ensure(&mut memory.chunk, chunk_refs.iter().cloned(), false);
+            } else {
+                let chunk_ids: Vec<BChunkId> = memory.chunk.ids().collect();
+                chunk_pool.chunks_ensure(&mut memory.chunk, chunk_ids.into_iter(), false);
+            }
+        }
+    }
+}
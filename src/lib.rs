@@ -17,7 +17,8 @@
 //! note: The only 2 structures here which are referenced externally are the.
 //!
 //! * `BArrayStore`: The whole array store.
-//! * `BArrayState`: Represents a single state (array) of data.
+//! * `BArrayState`: Represents a single state (array) of data,
+//!   referenced by a `StateId` handle.
 //!   These can be add using a reference state,
 //!   while this could be considered the previous or parent state.
 //!   no relationship is kept,
@@ -28,36 +29,40 @@
 //!  |  can store many 'states', which share memory.
 //!  |
 //!  |  This can store many arrays, however they must share the same 'stride'.
-//!  |  Arrays of different types will need to use a new BArrayStore.
+//!  |  Arrays of different types will need to use a new BArrayStore
+//!  |  (BArrayStoreAtSize can be used to manage these,
+//!  |  BArraySnapshot can group states from these stores into a single step).
 //!  |
 //!  +- <+> states (Collection of BArrayState's):
 //!  |   |  Each represents an array added by the user of this API.
 //!  |   |  and references a chunk_list (each state is a chunk_list user).
-//!  |   |  Note that the list order has no significance.
+//!  |   |  States are linked in the order they were added (see `BArrayStore::states`),
+//!  |   |  this order has no significance for de-duplication.
 //!  |   |
 //!  |   +- <+> chunk_list (BChunkList):
 //!  |       |  The chunks that make up this state.
 //!  |       |  Each state is a chunk_list user,
 //!  |       |  avoids duplicating lists when there is no change between states.
 //!  |       |
-//!  |       +- chunk_refs (List of BChunkRef): Each chunk_ref links to a a BChunk.
-//!  |          Each reference is a chunk user,
-//!  |          avoids duplicating smaller chunks of memory found in multiple states.
+//!  |       +- chunk_refs (Array of BChunk ids): Each links to a a BChunk.
+//!  |       |  Each reference is a chunk user,
+//!  |       |  avoids duplicating smaller chunks of memory found in multiple states.
+//!  |       |
+//!  |       +- chunk_offsets (Array of offsets): The offset of each chunk in the array,
+//!  |          used to find the chunk containing an offset (a binary search).
 //!  |
 //!  +- info (BArrayInfo):
 //!  |  Sizes and offsets for this array-store.
 //!  |  Also caches some variables for reuse.
 //!  |
 //!  +- <+> memory (BArrayMemory):
-//!      |  Memory pools for storing BArrayStore data.
+//!      |  Arenas for storing BArrayStore data,
+//!      |  elements are referenced by index (see `arena::ArenaId`).
 //!      |
-//!      +- chunk_list (Pool of BChunkList):
+//!      +- chunk_list (Arena of BChunkList):
 //!      |  All chunk_lists, (reference counted, used by BArrayState).
 //!      |
-//!      +- chunk_ref (Pool of BChunkRef):
-//!      |  All chunk_refs (link between BChunkList & BChunk).
-//!      |
-//!      +- chunks (Pool of BChunk):
+//!      +- chunks (Arena of BChunk):
 //!         All chunks, (reference counted, used by BChunkList).
 //!         These have their headers hashed for reuse so we can quickly check for duplicates.
 //! ```
@@ -84,6 +89,11 @@
 //! so this is checked to avoid performing so many hash-lookups.
 //! Otherwise new chunks are created.
 //!
+//! Optionally, new chunks which only differ from the reference chunk at the same position
+//! by a few strides can be stored as a delta (see: `BArrayStore::delta_strides_max_set`),
+//! as can chunks with numeric differences that compress well
+//! (see: `BArrayStore::delta_numeric_set`).
+//!
 //! # Example
 //!
 //! ```
@@ -97,16 +107,30 @@
 //! let state_c = bs.state_add(data_src_c, Some(state_b));
 //!
 //! // Check the data is stored correctly
-//! let data_dst = block_array_cow::BArrayStore::state_data_get_alloc(state_a);
+//! let data_dst = bs.state_data_get_alloc(state_a);
 //! assert_eq!(&data_src_a[..], &data_dst[..]);
 //!
-//! let data_dst = block_array_cow::BArrayStore::state_data_get_alloc(state_b);
+//! let data_dst = bs.state_data_get_alloc(state_b);
 //! assert_eq!(&data_src_b[..], &data_dst[..]);
 //!
-//! let data_dst = block_array_cow::BArrayStore::state_data_get_alloc(state_c);
+//! let data_dst = bs.state_data_get_alloc(state_c);
 //! assert_eq!(&data_src_c[..], &data_dst[..]);
 //! ```
 
+// Elements are referenced by index, see `arena`.
+// Any exceptions must be isolated & explain why they're needed.
+#![deny(unsafe_code)]
+
+// Only `alloc` is needed, the `std` feature adds threading & `BArrayStore.debug_dump`.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg_attr(not(any(feature = "std", test)), macro_use)]
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate core;
+#[cfg(feature = "serde")]
+extern crate serde;
+
 
 // -----------------------------------------------------------------------------
 // Constants
@@ -143,8 +167,8 @@ const USE_ALIGN_CHUNKS_TEST: bool = true;
 const BCHUNK_HASH_TABLE_ACCUMULATE_STEPS: usize = 4;
 
 /// Calculate the key once and reuse it
-const HASH_TABLE_KEY_UNSET: u64 = ::std::u64::MAX;
-const HASH_TABLE_KEY_FALLBACK: u64 = ::std::u64::MAX - 1;
+const HASH_TABLE_KEY_UNSET: u64 = u64::MAX;
+const HASH_TABLE_KEY_FALLBACK: u64 = u64::MAX - 1;
 
 /// How much larger the table is then the total number of chunks.
 const BCHUNK_HASH_TABLE_MUL: usize = 3;
@@ -174,6 +198,21 @@ const BCHUNK_SIZE_MIN_DIV: usize = 8;
 const BCHUNK_SIZE_MAX_MUL: usize = 2;
 /// USE_MERGE_CHUNKS
 
+/// Numeric deltas are only stored when the number of runs of repeating differences
+/// is no more than the number of strides in the chunk divided by this value.
+const BCHUNK_DELTA_NUMERIC_RUNS_DIV: usize = 8;
+
+/// When threads are enabled, the minimum number of hashes each thread calculates,
+/// smaller arrays are hashed using fewer threads (since threading has some overhead).
+const BCHUNK_THREAD_HASH_LEN_MIN: usize = 1 << 16;
+
+/// When threads are enabled, the range of table look-ups each thread runs ahead of the merge,
+/// starting with the minimum (after a chunk is found) and doubling while look-ups fail.
+#[cfg(feature = "std")]
+const BCHUNK_THREAD_LOOKUP_LEN_MIN: usize = 1 << 6;
+#[cfg(feature = "std")]
+const BCHUNK_THREAD_LOOKUP_LEN_MAX: usize = 1 << 12;
+
 /// slow (keep disabled), but handy for debugging
 const USE_VALIDATE_LIST_SIZE: bool = false;
 
@@ -181,37 +220,85 @@ const USE_VALIDATE_LIST_DATA_PARTIAL: bool = false;
 
 const USE_PARANOID_CHECKS: bool = false;
 
-const MEMPOOL_CHUNK_SIZE: usize = 512;
-
 // -----------------------------------------------------------------------------
 // Modules
 
-mod plain_ptr;
-use plain_ptr::{
-    PtrMut,
-    PtrConst,
+mod arena;
+use arena::{
+    Arena,
+    ArenaId,
+};
 
-    null_mut,
-    null_const,
+// Maps, hashed when `std` is available, otherwise ordered.
+#[cfg(feature = "std")]
+mod map {
+    pub use std::collections::{
+        HashMap as Map,
+        HashSet as Set,
+    };
+    pub use std::collections::hash_map::Iter;
+}
+#[cfg(not(feature = "std"))]
+mod map {
+    pub use alloc::collections::{
+        BTreeMap as Map,
+        BTreeSet as Set,
+    };
+    pub use alloc::collections::btree_map::Iter;
+}
+use map::{
+    Map,
+    Set,
 };
 
-mod mempool_elem;
-use mempool_elem::{
-    MemPool,
-    MemPoolElemUtils,
+mod at_size;
+pub use at_size::BArrayStoreAtSize;
+
+mod snapshot;
+pub use snapshot::{
+    BArraySnapshot,
+    BArraySnapshotArray,
 };
 
-mod list_base;
-use list_base::{
-    ListBase,
-    ListBaseElemUtils,
+mod transaction;
+pub use transaction::BArrayTransaction;
+
+mod state_ref;
+pub use state_ref::StateRef;
+
+#[cfg(feature = "std")]
+mod chunk_pool;
+#[cfg(feature = "std")]
+pub use chunk_pool::BArrayChunkPool;
+
+// C API, see `capi`.
+#[cfg(feature = "capi")]
+pub mod capi;
+
+// Serialization, see `serialize`.
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "serde")]
+use serde::{
+    Deserialize,
+    Serialize,
 };
 
-use ::std::cmp::{
+use ::core::cmp::{
     min,
     max,
 };
 
+use ::alloc::collections::VecDeque;
+
+use ::alloc::borrow::Cow;
+
+// requires `target_has_atomic = "ptr"` (documented in the readme).
+use ::alloc::sync::Arc;
+
+#[cfg(not(any(feature = "std", test)))]
+use ::alloc::vec::Vec;
+
 /// NOP for now, keep since this may be supported later.
 macro_rules! unlikely {
     ($body:expr) => {
@@ -224,6 +311,7 @@ macro_rules! unlikely {
 
 type HashKey = u64;
 
+#[derive(Clone)]
 struct BArrayInfo {
     chunk_stride: usize,
     // chunk_count: usize, // UNUSED
@@ -237,14 +325,87 @@ struct BArrayInfo {
     accum_read_ahead_bytes: usize,
     accum_steps: usize,
     accum_read_ahead_len: usize,
+
+    // Store chunks which differ from the reference chunk by this many strides (or less)
+    // as a delta, zero to disable.
+    delta_strides_max: usize,
+    // Store chunks as numeric differences from the reference chunk
+    // when they compress well, None to disable.
+    delta_numeric: Option<NumericType>,
+
+    // Number of threads to use for hashing & table look-ups, 1 for single threaded.
+    threads: usize,
+
+    // Record the reference of each state added as its parent.
+    parent_tracking: bool,
+
+    // Share chunk data with other stores, see `BArrayStore.chunk_pool_set`.
+    #[cfg(feature = "std")]
+    chunk_pool: Option<BArrayChunkPool>,
+}
+
+impl BArrayInfo {
+    #[inline]
+    fn use_delta(&self) -> bool {
+        (self.delta_strides_max != 0) || self.delta_numeric.is_some()
+    }
 }
 
+type BChunkId = ArenaId<BChunk>;
+type BChunkListId = ArenaId<BChunkList>;
+
+#[derive(Clone)]
 struct BArrayMemory {
-    state: MemPool<BArrayState>,
-    chunk_list: MemPool<BChunkList>,
-    chunk_ref: MemPool<BChunkRef>,
-    // this needs explicit drop on it's 'data'
-    chunk: MemPool<BChunk>,
+    chunk_list: Arena<BChunkList>,
+    chunk: Arena<BChunk>,
+}
+
+// Temporary memory used while adding states,
+// kept between calls to avoid reallocating (see `BArrayStore.shrink_scratch`).
+struct BArrayScratch {
+    table_hash_array: Vec<HashKey>,
+    table: Vec<Option<usize>>,
+    table_ref_stack: Vec<BTableRef>,
+    hash_store: Vec<HashKey>,
+    // contents of the state being de-duplicated by `BArrayStore.dedup_pending`.
+    pending_data: Vec<u8>,
+    // per thread, see `hash_array_from_data_accum_threaded`.
+    thread_hash_arrays: Vec<Vec<HashKey>>,
+    // per thread & the reference chunks they use, see `table_lookup_threaded`.
+    thread_lookup_arrays: Vec<Vec<Option<usize>>>,
+    table_lookup_chunks: Arena<BChunk>,
+    table_lookup_chunk_refs: Vec<BChunkId>,
+}
+
+impl BArrayScratch {
+    fn new() -> BArrayScratch {
+        BArrayScratch {
+            table_hash_array: Vec::new(),
+            table: Vec::new(),
+            table_ref_stack: Vec::new(),
+            hash_store: Vec::new(),
+            pending_data: Vec::new(),
+            thread_hash_arrays: Vec::new(),
+            thread_lookup_arrays: Vec::new(),
+            table_lookup_chunks: Arena::new(),
+            table_lookup_chunk_refs: Vec::new(),
+        }
+    }
+
+    /// Return the number of bytes allocated for reuse.
+    #[cfg(test)]
+    fn capacity(&self) -> usize {
+        use ::core::mem::size_of;
+        (self.table_hash_array.capacity() * size_of::<HashKey>()) +
+        (self.table.capacity() * size_of::<Option<usize>>()) +
+        (self.table_ref_stack.capacity() * size_of::<BTableRef>()) +
+        (self.hash_store.capacity() * size_of::<HashKey>()) +
+        self.pending_data.capacity() +
+        self.thread_hash_arrays.iter().map(|a| a.capacity() * size_of::<HashKey>()).sum::<usize>() +
+        self.thread_lookup_arrays.iter().map(|a| a.capacity() * size_of::<Option<usize>>()).sum::<usize>() +
+        self.table_lookup_chunks.capacity() +
+        (self.table_lookup_chunk_refs.capacity() * size_of::<BChunkId>())
+    }
 }
 
 ///
@@ -257,32 +418,96 @@ pub struct BArrayStore {
     // memory storage
     memory: BArrayMemory,
 
+    scratch: BArrayScratch,
+
     // `BArrayState` may be in any order
     // (logic should never depend on state order).
-    states: ListBase<BArrayState>,
+    states: Arena<BArrayState>,
+    // first & last states in the order they were added,
+    // see `BArrayState.state_prev` & `BArrayState.state_next`.
+    state_first: Option<StateId>,
+    state_last: Option<StateId>,
+
+    // States added with `state_add_deferred`, de-duplicated by `dedup_pending`
+    // (oldest first).
+    states_pending: VecDeque<BArrayStatePending>,
+}
+
+#[derive(Clone)]
+struct BArrayStatePending {
+    state: StateId,
+    // The reference state's chunk list (we're a user of this list),
+    // so the reference state may be removed before de-duplicating.
+    chunk_list_reference: BChunkListId,
 }
 
 
 ///
 /// A single instance of an array.
 ///
-/// This is how external API's hold a reference to an in-memory state,
-/// although the struct is private.
+/// External API's reference this using a `StateId`.
 ///
-pub struct BArrayState {
-    // linked list in `BArrayStore.states`
-    next: PtrMut<BArrayState>,
-    prev: PtrMut<BArrayState>,
-
+#[derive(Clone)]
+struct BArrayState {
     // BChunkList's
-    chunk_list: PtrMut<BChunkList>,
+    chunk_list: BChunkListId,
+    // caller defined data, see `BArrayStore.state_metadata_set`.
+    metadata: Vec<u8>,
+    // neighbors in the order states were added, see `BArrayStore.states`.
+    state_prev: Option<StateId>,
+    state_next: Option<StateId>,
+
+    // see `BArrayStore.parent_tracking_set`.
+    state_parent: Option<StateId>,
+    state_children: Vec<StateId>,
+
+    // in `BArrayStore.states_pending`, avoids searching it for states which aren't.
+    pending: bool,
+}
+
+///
+/// Handle for a state in a `BArrayStore`,
+/// returned by `BArrayStore.state_add`.
+///
+/// Only valid for the store that created it, until the state is removed.
+/// Using a removed state's handle panics (handles of removed states aren't reused).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StateId(ArenaId<BArrayState>);
+
+///
+/// The type of each element in an array, used to detect numeric changes
+/// (see `BArrayStore.delta_numeric_set`).
+///
+/// Integers may be signed or unsigned, values are in native byte order.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NumericType {
+    Int32,
+    Int64,
+    Float32,
+    Float64,
+}
+
+impl NumericType {
+    /// Size of each element in bytes.
+    pub fn size(&self) -> usize {
+        match *self {
+            NumericType::Int32 | NumericType::Float32 => 4,
+            NumericType::Int64 | NumericType::Float64 => 8,
+        }
+    }
 }
 
+#[derive(Clone)]
 struct BChunkList {
-    // BChunkRef's
-    chunk_refs: ListBase<BChunkRef>,
-    // ListBase.count(chunks), store for reuse.
-    chunk_refs_len: usize,
+    // BChunk's
+    chunk_refs: Vec<BChunkId>,
+    // offset of each chunk in `chunk_refs` (in bytes),
+    // so the chunk containing an offset can be found with a binary search.
+    chunk_offsets: Vec<usize>,
     // size of all chunks
     total_size: usize,
 
@@ -291,163 +516,418 @@ struct BChunkList {
 }
 
 /// A chunk of an array.
+#[derive(Clone)]
 struct BChunk {
-    data: Vec<u8>,
+    // use `bchunk_data` to access the contents (delta chunks need to be expanded).
+    data: BChunkData,
 
-    // number of `BChunkList` using this.
+    // number of `BChunkList` (and `BChunkDelta`) using this.
     users: isize,
 
     key: HashKey,
+
+    // checked by `BArrayInfo.chunk_pool` (its data is in the pool when it can be pooled).
+    #[cfg(feature = "std")]
+    pooled: bool,
 }
 
-/// Links to store `BChunk` data in `BChunkList.chunks`.
-struct BChunkRef {
-    next: PtrMut<BChunkRef>,
-    prev: PtrMut<BChunkRef>,
-    link: PtrMut<BChunk>,
+/// Contents of a `BChunk`.
+#[derive(Clone)]
+enum BChunkData {
+    // Shared with forked stores (see `BArrayStore.fork`).
+    Array(Arc<Vec<u8>>),
+    // store the data as changes to another chunk
+    // (see `BArrayInfo.delta_strides_max`).
+    Delta(Arc<BChunkDelta>),
+}
+
+/// Stores the contents of a chunk as differences to a similar chunk.
+struct BChunkDelta {
+    // `BChunk` this is a delta of, this holds a user and is never a delta itself.
+    base: BChunkId,
+    // size of `base` (and the chunk storing this delta),
+    // store to avoid looking up the base.
+    data_len: usize,
+    kind: BChunkDeltaKind,
+}
+
+enum BChunkDeltaKind {
+    /// Replace ranges of bytes in the base,
+    /// see `BArrayInfo.delta_strides_max`.
+    Patch {
+        // byte (offset, length) ranges which differ from `base`, always stride aligned.
+        runs: Vec<(usize, usize)>,
+        // data for all `runs` (packed together).
+        data: Vec<u8>,
+    },
+    /// Add element-wise differences to the base,
+    /// see `BArrayInfo.delta_numeric`.
+    Numeric {
+        numeric_type: NumericType,
+        // number of strides each difference is used for.
+        runs: Vec<usize>,
+        // a stride of differences for each of `runs` (packed together).
+        data: Vec<u8>,
+    },
+}
+
+impl BChunk {
+    #[inline]
+    fn data_len(&self) -> usize {
+        match self.data {
+            BChunkData::Array(ref data) => data.len(),
+            BChunkData::Delta(ref delta) => delta.data_len,
+        }
+    }
+
+    #[inline]
+    fn delta(&self) -> Option<&BChunkDelta> {
+        match self.data {
+            BChunkData::Array(_) => None,
+            BChunkData::Delta(ref delta) => Some(delta),
+        }
+    }
+
+    /// Data of a chunk which isn't stored as a delta.
+    #[inline]
+    fn array(&self) -> &Arc<Vec<u8>> {
+        match self.data {
+            BChunkData::Array(ref data) => data,
+            BChunkData::Delta(_) => unreachable!("delta chunk has no array"),
+        }
+    }
 }
 
 ///
 /// Single linked list used when putting chunks into a temporary table,
 /// used for lookups.
 ///
-/// Point to the `BChunkRef`, not the `BChunk`,
+/// Point to the index in `BChunkList.chunk_refs`, not the `BChunk`,
 /// to allow talking down the chunks in-order until a mis-match is found,
 /// this avoids having to do so many table lookups.
 ///
 struct BTableRef {
-    next: PtrMut<BTableRef>,
-    cref: PtrMut<BChunkRef>,
+    // index in `BArrayScratch.table_ref_stack`
+    next: Option<usize>,
+    // index in the reference `BChunkList.chunk_refs`
+    cref: usize,
 }
 
 /// internal structs
 
 
 // -----------------------------------------------------------------------------
-// MemPoolElemUtils impl
-
-macro_rules! mempool_list_elem_impl {
-    ($t:ty) => {
-        impl MemPoolElemUtils for $t {
-            #[inline] fn default_chunk_size() -> usize {
-                MEMPOOL_CHUNK_SIZE
-            }
-            #[inline] fn free_ptr_get(&self) -> *mut Self {
-                return self.next.as_ptr() as usize as *mut Self;
-            }
-            #[inline] fn free_ptr_set(&mut self, ptr: *mut Self) {
-                self.next = ::plain_ptr::PtrMut(ptr as usize as *mut _);
-                self.prev = PtrMut(self);
-            }
-            #[inline] fn free_ptr_test(&self) -> bool {
-                self.prev == PtrConst(self)
-            }
-        }
-    }
-}
+// Internal API
 
-mempool_list_elem_impl!(BArrayState);
-mempool_list_elem_impl!(BChunkRef);
+// put internal API in its own module
 
-impl MemPoolElemUtils for BChunkList {
-    #[inline] fn default_chunk_size() -> usize {
-        MEMPOOL_CHUNK_SIZE
-    }
-    #[inline] fn free_ptr_get(&self) -> *mut Self {
-        return self.chunk_refs.head.as_ptr() as usize as *mut Self;
-    }
-    #[inline] fn free_ptr_set(&mut self, ptr: *mut Self) {
-        self.chunk_refs.head = PtrMut(ptr as usize as *mut _);
-        self.chunk_refs.tail = PtrMut((self as *const _) as usize as *mut _);
-    }
-    #[inline] fn free_ptr_test(&self) -> bool {
-        self.chunk_refs.tail.as_ptr() as usize == (self as *const _ as usize)
-    }
-}
+/// # Internal BChunk API
+/// []( { )
 
-impl MemPoolElemUtils for BChunk {
-    #[inline] fn default_chunk_size() -> usize {
-        MEMPOOL_CHUNK_SIZE
-    }
-    #[inline] fn free_ptr_get(&self) -> *mut Self {
-        return self.users as *mut Self;
-    }
-    #[inline] fn free_ptr_set(&mut self, ptr: *mut Self) {
-        self.users = ptr as isize;
-        self.key = self as *const _ as HashKey;
-    }
-    #[inline] fn free_ptr_test(&self) -> bool {
-        self.key == self as *const _ as HashKey
-    }
+fn bchunk_new(
+    chunks: &mut Arena<BChunk>, data: Vec<u8>,
+) -> BChunkId {
+    bchunk_new_shared(chunks, Arc::new(data))
 }
 
-
-// -----------------------------------------------------------------------------
-// ListBaseElemUtils impl
-
-macro_rules! list_base_elem_impl {
-    ($t:ty) => {
-        impl ListBaseElemUtils for $t {
-            #[inline] fn next_get(&self) -> PtrMut<Self> { self.next }
-            #[inline] fn prev_get(&self) -> PtrMut<Self> { self.prev }
-            #[inline] fn next_set(&mut self, ptr: PtrMut<Self>) { self.next = ptr; }
-            #[inline] fn prev_set(&mut self, ptr: PtrMut<Self>) { self.prev = ptr; }
+/// Create a chunk using data which may be used by another store.
+fn bchunk_new_shared(
+    chunks: &mut Arena<BChunk>, data: Arc<Vec<u8>>,
+) -> BChunkId {
+    chunks.alloc(
+        BChunk {
+            data: BChunkData::Array(data),
+            users: 0,
+            key: HASH_TABLE_KEY_UNSET,
+            #[cfg(feature = "std")]
+            pooled: false,
         }
-    }
+    )
 }
 
-list_base_elem_impl!(BArrayState);
-list_base_elem_impl!(BChunkRef);
+fn bchunk_new_copydata(
+    chunks: &mut Arena<BChunk>, data: &[u8],
+) -> BChunkId {
+    let mut data_copy = Vec::with_capacity(data.len());
+    data_copy.extend_from_slice(data);
+    bchunk_new(chunks, data_copy)
+}
 
+/// Create a chunk which stores `data` as changes to `base`.
+///
+/// Returns None when `data` differs from `base` by more than
+/// `BArrayInfo.delta_strides_max` strides (or not at all),
+/// and the numeric differences (when `BArrayInfo.delta_numeric` is set) don't compress well.
+fn bchunk_new_delta(
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>,
+    mut base: BChunkId, data: &[u8],
+) -> Option<BChunkId> {
+    // delta chunks are always relative to regular chunks.
+    if let Some(delta) = chunks[base].delta() {
+        base = delta.base;
+    }
 
-// -----------------------------------------------------------------------------
-// Internal API
+    let kind: BChunkDeltaKind = {
+        let data_base = &chunks[base].array()[..];
+        if data_base.len() != data.len() {
+            return None;
+        }
 
-// put internal API in its own module
+        let mut kind: Option<BChunkDeltaKind> = None;
+        if info.delta_strides_max != 0 {
+            if let Some(patch_runs) = bchunk_delta_runs_calc(info, data_base, data) {
+                let mut patch_data: Vec<u8> = Vec::with_capacity(
+                    patch_runs.iter().fold(0, |len, &(_, run_len)| len + run_len));
+                for &(offset, run_len) in &patch_runs {
+                    patch_data.extend_from_slice(&data[offset..(offset + run_len)]);
+                }
+                kind = Some(BChunkDeltaKind::Patch {
+                    runs: patch_runs,
+                    data: patch_data,
+                });
+            }
+        }
+        if kind.is_none() {
+            if let Some(numeric_type) = info.delta_numeric {
+                if let Some((numeric_runs, numeric_data)) = bchunk_delta_numeric_calc(
+                    info, numeric_type, data_base, data)
+                {
+                    kind = Some(BChunkDeltaKind::Numeric {
+                        numeric_type,
+                        runs: numeric_runs,
+                        data: numeric_data,
+                    });
+                }
+            }
+        }
+        match kind {
+            Some(kind) => kind,
+            None => { return None; }
+        }
+    };
 
-/// # Internal BChunk API
-/// []( { )
+    chunks[base].users += 1;
 
-fn bchunk_new(
-    bs_mem: &mut BArrayMemory, data: Vec<u8>,
-) -> PtrMut<BChunk> {
-    PtrMut(bs_mem.chunk.alloc_elem_from(
+    Some(chunks.alloc(
         BChunk {
-            data: data,
+            data: BChunkData::Delta(Arc::new(BChunkDelta {
+                base,
+                data_len: data.len(),
+                kind,
+            })),
             users: 0,
             key: HASH_TABLE_KEY_UNSET,
+            #[cfg(feature = "std")]
+            pooled: false,
         }
     ))
 }
 
-fn bchunk_new_copydata(
-    bs_mem: &mut BArrayMemory, data: &[u8],
-) -> PtrMut<BChunk> {
-    let mut data_copy = Vec::with_capacity(data.len());
-    data_copy.extend_from_slice(data);
-    return bchunk_new(bs_mem, data_copy);
+/// Return (offset, length) byte ranges where `data` differs from `data_base`,
+/// None when there are too many differences to store a delta (or none at all).
+fn bchunk_delta_runs_calc(
+    info: &BArrayInfo, data_base: &[u8], data: &[u8],
+) -> Option<Vec<(usize, usize)>> {
+    debug_assert_eq!(data_base.len(), data.len());
+    let mut patch_runs: Vec<(usize, usize)> = Vec::new();
+    let mut strides_differ: usize = 0;
+    let mut i = 0;
+    while i != data.len() {
+        let i_next = i + info.chunk_stride;
+        if data_base[i..i_next] != data[i..i_next] {
+            strides_differ += 1;
+            if strides_differ > info.delta_strides_max {
+                return None;
+            }
+            let mut run_extend = false;
+            if let Some(run) = patch_runs.last_mut() {
+                if run.0 + run.1 == i {
+                    run.1 += info.chunk_stride;
+                    run_extend = true;
+                }
+            }
+            if !run_extend {
+                patch_runs.push((i, info.chunk_stride));
+            }
+        }
+        i = i_next;
+    }
+
+    if patch_runs.is_empty() {
+        return None;
+    }
+    Some(patch_runs)
+}
+
+/// Return run-length encoded numeric differences between `data_base` & `data`,
+/// as (runs, data) see: `BChunkDeltaKind::Numeric`.
+///
+/// None when the differences don't compress well,
+/// or can't be stored without loss of precision.
+fn bchunk_delta_numeric_calc(
+    info: &BArrayInfo, numeric_type: NumericType, data_base: &[u8], data: &[u8],
+) -> Option<(Vec<usize>, Vec<u8>)> {
+    debug_assert_eq!(data_base.len(), data.len());
+    let stride = info.chunk_stride;
+    let runs_max: usize = max(1, (data.len() / stride) / BCHUNK_DELTA_NUMERIC_RUNS_DIV);
+    let mut numeric_runs: Vec<usize> = Vec::new();
+    let mut numeric_data: Vec<u8> = Vec::new();
+    let mut diff: Vec<u8> = vec![0; stride];
+    let mut i = 0;
+    while i != data.len() {
+        let i_next = i + stride;
+        if !numeric_diff_calc(numeric_type, &data_base[i..i_next], &data[i..i_next], &mut diff[..]) {
+            return None;
+        }
+        if  (!numeric_runs.is_empty()) &&
+            (numeric_data[(numeric_data.len() - stride)..] == diff[..])
+        {
+            *numeric_runs.last_mut().unwrap() += 1;
+        } else {
+            if numeric_runs.len() == runs_max {
+                return None;
+            }
+            numeric_runs.push(1);
+            numeric_data.extend_from_slice(&diff[..]);
+        }
+        i = i_next;
+    }
+
+    if numeric_runs.is_empty() {
+        return None;
+    }
+    Some((numeric_runs, numeric_data))
 }
 
 fn bchunk_decref(
-    bs_mem: &mut BArrayMemory, mut chunk: PtrMut<BChunk>,
+    chunks: &mut Arena<BChunk>, chunk: BChunkId,
+) {
+    debug_assert!(chunks[chunk].users > 0);
+    if chunks[chunk].users == 1 {
+        let chunk = chunks.free(chunk);
+        if let BChunkData::Delta(delta) = chunk.data {
+            bchunk_decref(chunks, delta.base);
+        }
+    } else {
+        chunks[chunk].users -= 1;
+    }
+}
+
+/// Return the contents of `chunk`,
+/// this only needs to allocate for delta chunks.
+fn bchunk_data<'a>(
+    chunks: &'a Arena<BChunk>, chunk: &'a BChunk,
+) -> Cow<'a, [u8]> {
+    if chunk.delta().is_none() {
+        Cow::Borrowed(&chunk.array()[..])
+    } else {
+        let mut data: Vec<u8> = vec![0; chunk.data_len()];
+        bchunk_data_copy_to(chunks, chunk, &mut data[..]);
+        Cow::Owned(data)
+    }
+}
+
+/// Write the contents of `chunk` into `data`.
+fn bchunk_data_copy_to(
+    chunks: &Arena<BChunk>, chunk: &BChunk,
+    data: &mut [u8],
 ) {
-    debug_assert!(chunk.users > 0);
-    if chunk.users == 1 {
-        unsafe { ::std::ptr::drop_in_place(&mut chunk.data) };
-        bs_mem.chunk.free_elem(chunk.as_ptr());
+    debug_assert_eq!(chunk.data_len(), data.len());
+    if let Some(delta) = chunk.delta() {
+        let data_base = &chunks[delta.base].array()[..];
+        match delta.kind {
+            BChunkDeltaKind::Patch { runs: ref patch_runs, data: ref patch_data } => {
+                data.clone_from_slice(data_base);
+                let mut patch_step = 0;
+                for &(offset, run_len) in patch_runs {
+                    let patch_step_next = patch_step + run_len;
+                    data[offset..(offset + run_len)].clone_from_slice(
+                        &patch_data[patch_step..patch_step_next]);
+                    patch_step = patch_step_next;
+                }
+            },
+            BChunkDeltaKind::Numeric {
+                numeric_type, runs: ref numeric_runs, data: ref numeric_data,
+            } => {
+                let stride = numeric_data.len() / numeric_runs.len();
+                let mut i = 0;
+                for (run_index, run_len) in numeric_runs.iter().enumerate() {
+                    let diff = &numeric_data[(run_index * stride)..((run_index + 1) * stride)];
+                    for _ in 0..*run_len {
+                        let i_next = i + stride;
+                        numeric_diff_apply(
+                            numeric_type, &data_base[i..i_next], diff, &mut data[i..i_next]);
+                        i = i_next;
+                    }
+                }
+                debug_assert_eq!(i, data.len());
+            },
+        }
+    } else {
+        data.clone_from_slice(&chunk.array()[..]);
+    }
+}
+
+/// Return the number of bytes used to store the contents of `chunk`.
+fn bchunk_data_size_stored(
+    chunk: &BChunk,
+) -> usize {
+    if let Some(delta) = chunk.delta() {
+        match delta.kind {
+            BChunkDeltaKind::Patch { runs: ref patch_runs, data: ref patch_data } => {
+                patch_data.len() +
+                (patch_runs.len() * ::core::mem::size_of::<(usize, usize)>())
+            },
+            BChunkDeltaKind::Numeric { runs: ref numeric_runs, data: ref numeric_data, .. } => {
+                numeric_data.len() +
+                (numeric_runs.len() * ::core::mem::size_of::<usize>())
+            },
+        }
     } else {
-        chunk.users -= 1;
+        chunk.array().len()
     }
 }
 
 fn bchunk_data_compare(
-    chunk: PtrMut<BChunk>,
+    chunks: &Arena<BChunk>,
+    chunk: BChunkId,
     data_base: &[u8],
     data_base_len: usize,
     offset: usize,
 ) -> bool {
-    if offset + chunk.data.len() <= data_base_len {
-        return &data_base[offset..(offset + chunk.data.len())] == &chunk.data[..];
+    let chunk: &BChunk = &chunks[chunk];
+    if offset + chunk.data_len() <= data_base_len {
+        let data_base = &data_base[offset..(offset + chunk.data_len())];
+        if let Some(delta) = chunk.delta() {
+            let (patch_runs, patch_data) = {
+                match delta.kind {
+                    BChunkDeltaKind::Patch { runs: ref patch_runs, data: ref patch_data } => {
+                        (patch_runs, patch_data)
+                    },
+                    BChunkDeltaKind::Numeric { .. } => {
+                        return data_base == &bchunk_data(chunks, chunk)[..];
+                    },
+                }
+            };
+            // compare un-patched ranges with the base, then the patches.
+            let data_delta_base = &chunks[delta.base].array()[..];
+            let mut i_prev = 0;
+            let mut patch_step = 0;
+            for &(offset, run_len) in patch_runs {
+                let patch_step_next = patch_step + run_len;
+                if  (data_base[i_prev..offset] != data_delta_base[i_prev..offset]) ||
+                    (data_base[offset..(offset + run_len)] !=
+                     patch_data[patch_step..patch_step_next])
+                {
+                    return false;
+                }
+                i_prev = offset + run_len;
+                patch_step = patch_step_next;
+            }
+            data_base[i_prev..] == data_delta_base[i_prev..]
+        } else {
+            data_base == &chunk.array()[..]
+        }
     } else {
         return false;
     }
@@ -455,47 +935,157 @@ fn bchunk_data_compare(
 
 /// []( } )
 
+// # Internal Numeric Delta API
+//
+// Element-wise differences, used by `BChunkDeltaKind::Numeric`.
+// []( { )
+
+trait NumericElem: Copy {
+    fn read(data: &[u8]) -> Self;
+    fn write(self, data: &mut [u8]);
+    /// Return `self - base`.
+    fn diff(self, base: Self) -> Self;
+    /// Return `self + diff`.
+    fn apply(self, diff: Self) -> Self;
+    /// Exact comparison (no tolerance for floating point values).
+    fn is_identical(self, other: Self) -> bool;
+}
+
+macro_rules! numeric_elem_int_impl {
+    ($t:ty, $size:expr) => {
+        impl NumericElem for $t {
+            #[inline] fn read(data: &[u8]) -> Self {
+                let mut bytes = [0_u8; $size];
+                bytes.copy_from_slice(data);
+                <$t>::from_ne_bytes(bytes)
+            }
+            #[inline] fn write(self, data: &mut [u8]) {
+                data.copy_from_slice(&self.to_ne_bytes());
+            }
+            #[inline] fn diff(self, base: Self) -> Self { self.wrapping_sub(base) }
+            #[inline] fn apply(self, diff: Self) -> Self { self.wrapping_add(diff) }
+            #[inline] fn is_identical(self, other: Self) -> bool { self == other }
+        }
+    }
+}
+
+macro_rules! numeric_elem_float_impl {
+    ($t:ty, $t_bits:ty) => {
+        impl NumericElem for $t {
+            #[inline] fn read(data: &[u8]) -> Self {
+                <$t>::from_bits(<$t_bits>::read(data))
+            }
+            #[inline] fn write(self, data: &mut [u8]) {
+                self.to_bits().write(data);
+            }
+            #[inline] fn diff(self, base: Self) -> Self { self - base }
+            #[inline] fn apply(self, diff: Self) -> Self { self + diff }
+            #[inline] fn is_identical(self, other: Self) -> bool {
+                self.to_bits() == other.to_bits()
+            }
+        }
+    }
+}
+
+numeric_elem_int_impl!(u32, 4);
+numeric_elem_int_impl!(u64, 8);
+numeric_elem_float_impl!(f32, u32);
+numeric_elem_float_impl!(f64, u64);
+
+fn numeric_diff_calc_typed<T: NumericElem>(
+    data_base: &[u8], data: &[u8], diff: &mut [u8],
+) -> bool {
+    let size = ::core::mem::size_of::<T>();
+    let mut i = 0;
+    while i != data.len() {
+        let i_next = i + size;
+        let value_base = T::read(&data_base[i..i_next]);
+        let value = T::read(&data[i..i_next]);
+        let value_diff = value.diff(value_base);
+        // ensure we can get back the exact value.
+        if !value_base.apply(value_diff).is_identical(value) {
+            return false;
+        }
+        value_diff.write(&mut diff[i..i_next]);
+        i = i_next;
+    }
+    true
+}
+
+fn numeric_diff_apply_typed<T: NumericElem>(
+    data_base: &[u8], diff: &[u8], data: &mut [u8],
+) {
+    let size = ::core::mem::size_of::<T>();
+    let mut i = 0;
+    while i != data.len() {
+        let i_next = i + size;
+        T::read(&data_base[i..i_next]).apply(T::read(&diff[i..i_next])).write(&mut data[i..i_next]);
+        i = i_next;
+    }
+}
+
+/// Calculate the difference of each element between `data` & `data_base` into `diff`.
+///
+/// Returns false when the difference can't be applied to `data_base`
+/// to get back the exact values in `data` (floating point precision loss).
+fn numeric_diff_calc(
+    numeric_type: NumericType, data_base: &[u8], data: &[u8], diff: &mut [u8],
+) -> bool {
+    match numeric_type {
+        NumericType::Int32 => numeric_diff_calc_typed::<u32>(data_base, data, diff),
+        NumericType::Int64 => numeric_diff_calc_typed::<u64>(data_base, data, diff),
+        NumericType::Float32 => numeric_diff_calc_typed::<f32>(data_base, data, diff),
+        NumericType::Float64 => numeric_diff_calc_typed::<f64>(data_base, data, diff),
+    }
+}
+
+/// Add the difference of each element in `diff` to `data_base`, writing the result into `data`.
+fn numeric_diff_apply(
+    numeric_type: NumericType, data_base: &[u8], diff: &[u8], data: &mut [u8],
+) {
+    match numeric_type {
+        NumericType::Int32 => numeric_diff_apply_typed::<u32>(data_base, diff, data),
+        NumericType::Int64 => numeric_diff_apply_typed::<u64>(data_base, diff, data),
+        NumericType::Float32 => numeric_diff_apply_typed::<f32>(data_base, diff, data),
+        NumericType::Float64 => numeric_diff_apply_typed::<f64>(data_base, diff, data),
+    }
+}
+
+// []( } )
+
 /// # Internal BChunkList API
 /// []( { )
 
 fn bchunk_list_new(
-    bs_mem: &mut BArrayMemory,
     total_size: usize,
-) -> PtrMut<BChunkList> {
-    PtrMut(bs_mem.chunk_list.alloc_elem_from(
-        BChunkList {
-            chunk_refs: ListBase::new(),
-            chunk_refs_len: 0,
-            total_size: total_size,
-            users: 0,
-        }
-    ))
+) -> BChunkList {
+    BChunkList {
+        chunk_refs: Vec::new(),
+        chunk_offsets: Vec::new(),
+        total_size,
+        users: 0,
+    }
 }
 
 fn bchunk_list_decref(
-    bs_mem: &mut BArrayMemory, mut chunk_list: PtrMut<BChunkList>,
+    bs_mem: &mut BArrayMemory, chunk_list: BChunkListId,
 ) {
-    debug_assert!(chunk_list.users > 0);
-    if chunk_list.users == 1 {
-        let mut cref = chunk_list.chunk_refs.head;
-        while cref != null_mut() {
-            let cref_next = cref.next;
-            bchunk_decref(bs_mem, cref.link);
-            bs_mem.chunk_ref.free_elem(cref.as_ptr());
-            cref = cref_next;
+    debug_assert!(bs_mem.chunk_list[chunk_list].users > 0);
+    if bs_mem.chunk_list[chunk_list].users == 1 {
+        let chunk_list = bs_mem.chunk_list.free(chunk_list);
+        for chunk in chunk_list.chunk_refs {
+            bchunk_decref(&mut bs_mem.chunk, chunk);
         }
-
-        bs_mem.chunk_list.free_elem(chunk_list.as_ptr());
     } else {
-        chunk_list.users -= 1;
+        bs_mem.chunk_list[chunk_list].users -= 1;
     }
 }
 
 macro_rules! debug_assert_chunklist_size {
-    ($chunk_list:expr, $n:expr) => {
+    ($chunks:expr, $chunk_list:expr, $n:expr) => {
         {
             if USE_VALIDATE_LIST_SIZE {
-                debug_assert_eq!(bchunk_list_size($chunk_list), $n)
+                debug_assert_eq!(bchunk_list_size($chunks, $chunk_list), $n)
             }
         }
     }
@@ -503,23 +1093,24 @@ macro_rules! debug_assert_chunklist_size {
 
 // USE_VALIDATE_LIST_DATA_PARTIAL
 fn bchunk_list_data_check(
-    chunk_list: PtrMut<BChunkList>, data: &[u8],
+    chunks: &Arena<BChunk>, chunk_list: &BChunkList, data: &[u8],
 ) -> bool {
     let mut offset = 0;
-    for cref in chunk_list.chunk_refs.iter() {
-        if &data[offset..(offset + cref.link.data.len())] != &cref.link.data[..] {
+    for &chunk in &chunk_list.chunk_refs {
+        let chunk: &BChunk = &chunks[chunk];
+        if data[offset..(offset + chunk.data_len())] != bchunk_data(chunks, chunk)[..] {
             return false;
         }
-        offset += cref.link.data.len();
+        offset += chunk.data_len();
     }
     return true;
 }
 
 macro_rules! debug_assert_chunklist_data {
-    ($chunk_list:expr, $data:expr) => {
+    ($chunks:expr, $chunk_list:expr, $data:expr) => {
         {
             if USE_VALIDATE_LIST_DATA_PARTIAL {
-                debug_assert!(bchunk_list_data_check($chunk_list, $data));
+                debug_assert!(bchunk_list_data_check($chunks, $chunk_list, $data));
             }
         }
     }
@@ -527,34 +1118,36 @@ macro_rules! debug_assert_chunklist_data {
 
 // USE_MERGE_CHUNKS
 fn bchunk_list_ensure_min_size_last(
-    info: &BArrayInfo, bs_mem: &mut BArrayMemory,
-    mut chunk_list: PtrMut<BChunkList>,
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>,
+    chunk_list: &mut BChunkList,
 ) {
-    let mut cref = chunk_list.chunk_refs.tail;
-    if cref != null_mut() && cref.prev != null_mut() {
+    let chunk_refs_len = chunk_list.chunk_refs.len();
+    if chunk_refs_len >= 2 {
         // both are decref'd after use (end of this block)
-        let chunk_curr: PtrMut<BChunk> = cref.link;
-        let chunk_prev: PtrMut<BChunk> = cref.prev.link;
+        let chunk_curr: BChunkId = chunk_list.chunk_refs[chunk_refs_len - 1];
+        let chunk_prev: BChunkId = chunk_list.chunk_refs[chunk_refs_len - 2];
+        let chunk_curr_len = chunks[chunk_curr].data_len();
+        let chunk_prev_len = chunks[chunk_prev].data_len();
 
-        if min(chunk_prev.data.len(), chunk_curr.data.len()) < info.chunk_byte_size_min {
-            let data_merge_len = chunk_prev.data.len() + chunk_curr.data.len();
+        if min(chunk_prev_len, chunk_curr_len) < info.chunk_byte_size_min {
+            let data_merge_len = chunk_prev_len + chunk_curr_len;
             // we could pass, but no need
             if data_merge_len <= info.chunk_byte_size_max {
                 // we have enough space to merge
-
-                // remove last from linklist
-                debug_assert!(chunk_list.chunk_refs.tail != chunk_list.chunk_refs.head);
-                cref.prev.next = null_mut();
-                chunk_list.chunk_refs.tail = cref.prev;
-                chunk_list.chunk_refs_len -= 1;
-
-                let mut data_merge: Vec<u8> = Vec::with_capacity(data_merge_len);
-                data_merge.extend_from_slice(&chunk_prev.data[..]);
-                data_merge.extend_from_slice(&chunk_curr.data[..]);
-
-                cref.prev.link = bchunk_new(bs_mem, data_merge);
-                cref.prev.link.users += 1;
-                bs_mem.chunk_ref.free_elem(cref.as_ptr());
+                let data_merge: Vec<u8> = {
+                    let mut data_merge: Vec<u8> = Vec::with_capacity(data_merge_len);
+                    data_merge.extend_from_slice(&bchunk_data(chunks, &chunks[chunk_prev])[..]);
+                    data_merge.extend_from_slice(&bchunk_data(chunks, &chunks[chunk_curr])[..]);
+                    data_merge
+                };
+
+                // remove last from the list
+                chunk_list.chunk_refs.pop();
+                chunk_list.chunk_offsets.pop();
+
+                let chunk_merge: BChunkId = bchunk_new(chunks, data_merge);
+                chunks[chunk_merge].users += 1;
+                chunk_list.chunk_refs[chunk_refs_len - 2] = chunk_merge;
             } else {
                 // If we always merge small slices,
                 // we should _almost_ never end up having very large chunks.
@@ -571,43 +1164,52 @@ fn bchunk_list_ensure_min_size_last(
                 let mut data_prev: Vec<u8> = Vec::with_capacity(data_prev_len);
                 let mut data_curr: Vec<u8> = Vec::with_capacity(data_curr_len);
 
-                if data_prev_len <= chunk_prev.data.len() {
-                    // setup 'data_prev'
-                    data_prev.extend_from_slice(&chunk_prev.data[..]);
-
-                    // setup 'data_curr'
-                    data_curr.extend_from_slice(
-                        &chunk_prev.data[data_prev_len..chunk_prev.data.len()]);
-                    data_curr.extend_from_slice(
-                        &chunk_curr.data[..]);
-                } else {
-                    debug_assert!(data_curr_len <= chunk_curr.data.len());
-                    debug_assert!(data_prev_len >= chunk_prev.data.len());
+                {
+                    let chunk_prev_data = bchunk_data(chunks, &chunks[chunk_prev]);
+                    let chunk_curr_data = bchunk_data(chunks, &chunks[chunk_curr]);
+
+                    if data_prev_len <= chunk_prev_len {
+                        // setup 'data_prev'
+                        data_prev.extend_from_slice(&chunk_prev_data[..]);
+
+                        // setup 'data_curr'
+                        data_curr.extend_from_slice(
+                            &chunk_prev_data[data_prev_len..chunk_prev_len]);
+                        data_curr.extend_from_slice(
+                            &chunk_curr_data[..]);
+                    } else {
+                        debug_assert!(data_curr_len <= chunk_curr_len);
+                        debug_assert!(data_prev_len >= chunk_prev_len);
 
-                    let data_prev_grow_len = data_prev_len - chunk_prev.data.len();
+                        let data_prev_grow_len = data_prev_len - chunk_prev_len;
 
-                    // setup 'data_prev'
-                    data_prev.extend_from_slice(&chunk_prev.data[..]);
-                    data_prev.extend_from_slice(&chunk_curr.data[0..data_prev_grow_len]);
+                        // setup 'data_prev'
+                        data_prev.extend_from_slice(&chunk_prev_data[..]);
+                        data_prev.extend_from_slice(&chunk_curr_data[0..data_prev_grow_len]);
 
-                    // setup 'data_curr'
-                    data_curr.extend_from_slice(
-                        &chunk_curr.data[data_prev_grow_len..(data_prev_grow_len + data_curr_len)]);
+                        // setup 'data_curr'
+                        data_curr.extend_from_slice(
+                            &chunk_curr_data[data_prev_grow_len..(data_prev_grow_len + data_curr_len)]);
+                    }
                 }
 
                 debug_assert_eq!(data_prev_len, data_prev.len());
                 debug_assert_eq!(data_curr_len, data_curr.len());
 
-                cref.prev.link = bchunk_new(bs_mem, data_prev);
-                cref.prev.link.users += 1;
+                let chunk_prev_new: BChunkId = bchunk_new(chunks, data_prev);
+                chunks[chunk_prev_new].users += 1;
+                chunk_list.chunk_refs[chunk_refs_len - 2] = chunk_prev_new;
 
-                cref.link = bchunk_new(bs_mem, data_curr);
-                cref.link.users += 1;
+                let chunk_curr_new: BChunkId = bchunk_new(chunks, data_curr);
+                chunks[chunk_curr_new].users += 1;
+                chunk_list.chunk_refs[chunk_refs_len - 1] = chunk_curr_new;
+                chunk_list.chunk_offsets[chunk_refs_len - 1] =
+                    chunk_list.chunk_offsets[chunk_refs_len - 2] + data_prev_len;
             }
 
             // free zero users
-            bchunk_decref(bs_mem, chunk_curr);
-            bchunk_decref(bs_mem, chunk_prev);
+            bchunk_decref(chunks, chunk_curr);
+            bchunk_decref(chunks, chunk_prev);
         }
     }
 }
@@ -654,28 +1256,69 @@ fn bchunk_list_calc_trim_len(
     (data_trim_len, data_last_chunk_len)
 }
 
+/// Return the size of all chunks in `chunk_list`,
+/// unlike `BChunkList.total_size` this is valid while the list is being filled.
+#[inline]
+fn bchunk_list_offset_end(
+    chunks: &Arena<BChunk>, chunk_list: &BChunkList,
+) -> usize {
+    if let Some(&chunk) = chunk_list.chunk_refs.last() {
+        *chunk_list.chunk_offsets.last().unwrap() + chunks[chunk].data_len()
+    } else {
+        0
+    }
+}
+
+/// Return the index of the chunk in `chunk_list` containing `offset`.
+#[inline]
+fn bchunk_list_find_offset(
+    chunk_list: &BChunkList, offset: usize,
+) -> usize {
+    debug_assert!(offset < chunk_list.total_size);
+    match chunk_list.chunk_offsets.binary_search(&offset) {
+        Ok(index) => index,
+        Err(index) => index - 1,
+    }
+}
+
 /// Append and don't manage merging small chunks.
 fn bchunk_list_append_only(
-    bs_mem: &mut BArrayMemory,
-    mut chunk_list: PtrMut<BChunkList>, mut chunk: PtrMut<BChunk>,
+    chunks: &mut Arena<BChunk>,
+    chunk_list: &mut BChunkList, chunk: BChunkId,
 ) {
-    let cref = PtrMut(bs_mem.chunk_ref.alloc_elem_from(
-        BChunkRef {
-            next: null_mut(),
-            prev: null_mut(),
-            link: chunk,
-        })
-    );
-    chunk_list.chunk_refs.push_back(cref);
-    chunk_list.chunk_refs_len += 1;
-    chunk.users += 1
+    let offset = bchunk_list_offset_end(chunks, chunk_list);
+    chunk_list.chunk_refs.push(chunk);
+    chunk_list.chunk_offsets.push(offset);
+    chunks[chunk].users += 1;
+}
+
+/// Append a range of chunks from `chunk_list_src`,
+/// (similar to `bchunk_list_append_only` for each chunk),
+/// offsets are copied from the source instead of being calculated.
+fn bchunk_list_append_only_range(
+    chunks: &mut Arena<BChunk>,
+    chunk_list: &mut BChunkList,
+    chunk_list_src: &BChunkList, cref_range: ::core::ops::Range<usize>,
+) {
+    if cref_range.start == cref_range.end {
+        return;
+    }
+    let offset = bchunk_list_offset_end(chunks, chunk_list);
+    let offset_src = chunk_list_src.chunk_offsets[cref_range.start];
+    chunk_list.chunk_offsets.extend(
+        chunk_list_src.chunk_offsets[cref_range.clone()].iter().map(|o| (o - offset_src) + offset));
+    let chunk_refs_src = &chunk_list_src.chunk_refs[cref_range];
+    chunk_list.chunk_refs.extend_from_slice(chunk_refs_src);
+    for &chunk in chunk_refs_src {
+        chunks[chunk].users += 1;
+    }
 }
 
 /// note: This is for writing single chunks,
 /// use `bchunk_list_append_data_n` when writing large blocks of memory into many chunks.
 fn bchunk_list_append_data(
-    info: &BArrayInfo, bs_mem: &mut BArrayMemory,
-    chunk_list: PtrMut<BChunkList>,
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>,
+    chunk_list: &mut BChunkList,
     data: &[u8],
 ) {
     debug_assert!(data.len() != 0);
@@ -683,34 +1326,44 @@ fn bchunk_list_append_data(
     if USE_MERGE_CHUNKS {
         debug_assert!(data.len() <= info.chunk_byte_size_max);
 
-        if !chunk_list.chunk_refs.is_empty() {
-            let mut cref: PtrMut<BChunkRef> = chunk_list.chunk_refs.tail;
-            let chunk_prev: PtrMut<BChunk> = cref.link;
-            if min(chunk_prev.data.len(), data.len()) < info.chunk_byte_size_min {
-                let data_merge_len = chunk_prev.data.len() + data.len();
-                // realloc for single user
-                if cref.link.users == 1 {
-                    cref.link.data.extend_from_slice(data);
+        if let Some(&chunk_prev) = chunk_list.chunk_refs.last() {
+            let chunk_prev_len = chunks[chunk_prev].data_len();
+            if min(chunk_prev_len, data.len()) < info.chunk_byte_size_min {
+                let data_merge_len = chunk_prev_len + data.len();
+                // realloc for single user (when the data isn't shared with a forked store).
+                let data_prev: Option<&mut Vec<u8>> = {
+                    let chunk_prev = &mut chunks[chunk_prev];
+                    match chunk_prev.data {
+                        BChunkData::Array(ref mut data) if chunk_prev.users == 1 => Arc::get_mut(data),
+                        _ => None,
+                    }
+                };
+                if let Some(data_prev) = data_prev {
+                    data_prev.extend_from_slice(data);
                 } else {
-                    let mut data_merge: Vec<u8> = Vec::with_capacity(data_merge_len);
-                    data_merge.extend_from_slice(&chunk_prev.data[..]);
-                    data_merge.extend_from_slice(data);
-                    cref.link = bchunk_new(bs_mem, data_merge);
-                    cref.link.users += 1;
-                    bchunk_decref(bs_mem, chunk_prev);
+                    let data_merge: Vec<u8> = {
+                        let mut data_merge: Vec<u8> = Vec::with_capacity(data_merge_len);
+                        data_merge.extend_from_slice(&bchunk_data(chunks, &chunks[chunk_prev])[..]);
+                        data_merge.extend_from_slice(data);
+                        data_merge
+                    };
+                    let chunk_merge: BChunkId = bchunk_new(chunks, data_merge);
+                    chunks[chunk_merge].users += 1;
+                    *chunk_list.chunk_refs.last_mut().unwrap() = chunk_merge;
+                    bchunk_decref(chunks, chunk_prev);
                 }
-                debug_assert_eq!(data_merge_len, cref.link.data.len());
+                debug_assert_eq!(data_merge_len, chunks[*chunk_list.chunk_refs.last().unwrap()].data_len());
                 return;
             }
         }
     }
 
-    let chunk: PtrMut<BChunk> = bchunk_new_copydata(bs_mem, data);
-    bchunk_list_append_only(bs_mem, chunk_list, chunk);
+    let chunk: BChunkId = bchunk_new_copydata(chunks, data);
+    bchunk_list_append_only(chunks, chunk_list, chunk);
 
     // don't run this, instead preemptively avoid creating a chunk only to merge it (above).
     if false && USE_MERGE_CHUNKS {
-        bchunk_list_ensure_min_size_last(info, bs_mem, chunk_list);
+        bchunk_list_ensure_min_size_last(info, chunks, chunk_list);
     }
 }
 
@@ -720,8 +1373,8 @@ fn bchunk_list_append_data(
 /// Note: this function takes care not to perform redundant chunk-merging checks,
 /// so we can write successive fixed size chunks quickly.
 fn bchunk_list_append_data_n(
-    info: &BArrayInfo, bs_mem: &mut BArrayMemory,
-    chunk_list: PtrMut<BChunkList>,
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>,
+    chunk_list: &mut BChunkList,
     data: &[u8],
 ) {
     let (data_trim_len, data_last_chunk_len) = bchunk_list_calc_trim_len(info, data.len());
@@ -731,21 +1384,21 @@ fn bchunk_list_append_data_n(
 
         {
             let i = info.chunk_byte_size;
-            bchunk_list_append_data(info, bs_mem, chunk_list, &data[0..i]);
+            bchunk_list_append_data(info, chunks, chunk_list, &data[0..i]);
             i_prev = i;
         }
 
         while i_prev != data_trim_len {
             let i = i_prev + info.chunk_byte_size;
-            let chunk = bchunk_new_copydata(bs_mem, &data[i_prev..i]);
-            bchunk_list_append_only(bs_mem, chunk_list, chunk);
+            let chunk = bchunk_new_copydata(chunks, &data[i_prev..i]);
+            bchunk_list_append_only(chunks, chunk_list, chunk);
             i_prev = i;
         }
 
         if data_last_chunk_len != 0 {
             let chunk = bchunk_new_copydata(
-                bs_mem, &data[i_prev..(i_prev + data_last_chunk_len)]);
-            bchunk_list_append_only(bs_mem, chunk_list, chunk);
+                chunks, &data[i_prev..(i_prev + data_last_chunk_len)]);
+            bchunk_list_append_only(chunks, chunk_list, chunk);
             // i_prev = data.len();  // UNUSED
         }
     } else {
@@ -753,65 +1406,71 @@ fn bchunk_list_append_data_n(
         // we may need to merge with the last.
         if data_last_chunk_len != 0 {
             debug_assert_eq!(data.len(), data_last_chunk_len);
-            bchunk_list_append_data(info, bs_mem, chunk_list, data);
+            bchunk_list_append_data(info, chunks, chunk_list, data);
             // i_prev = data.len();  // UNUSED
         }
     }
 
     if USE_MERGE_CHUNKS {
         if data.len() > info.chunk_byte_size {
-            debug_assert!(chunk_list.chunk_refs.tail.link.data.len() >= info.chunk_byte_size_min);
+            debug_assert!(
+                chunks[*chunk_list.chunk_refs.last().unwrap()].data_len() >= info.chunk_byte_size_min);
         }
     }
 }
 
 fn bchunk_list_append(
-    info: &BArrayInfo, bs_mem: &mut BArrayMemory,
-    chunk_list: PtrMut<BChunkList>,
-    chunk: PtrMut<BChunk>,
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>,
+    chunk_list: &mut BChunkList,
+    chunk: BChunkId,
 ) {
-    bchunk_list_append_only(bs_mem, chunk_list, chunk);
+    bchunk_list_append_only(chunks, chunk_list, chunk);
 
     if USE_MERGE_CHUNKS {
-        bchunk_list_ensure_min_size_last(info, bs_mem, chunk_list);
+        bchunk_list_ensure_min_size_last(info, chunks, chunk_list);
     }
 }
 
 fn bchunk_list_fill_from_array(
-    info: &BArrayInfo, bs_mem: &mut BArrayMemory,
-    chunk_list: PtrMut<BChunkList>,
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>,
+    chunk_list: &mut BChunkList,
     data: &[u8],
 ) {
     debug_assert!(chunk_list.chunk_refs.is_empty());
     let (data_trim_len, data_last_chunk_len) = bchunk_list_calc_trim_len(info, data.len());
 
+    let chunk_refs_len = (data_trim_len / info.chunk_byte_size) + if data_last_chunk_len != 0 { 1 } else { 0 };
+    chunk_list.chunk_refs.reserve(chunk_refs_len);
+    chunk_list.chunk_offsets.reserve(chunk_refs_len);
+
     let mut i_prev = 0;
     while i_prev != data_trim_len {
         let i = i_prev + info.chunk_byte_size;
-        let chunk = bchunk_new_copydata(bs_mem, &data[i_prev..i]);
-        bchunk_list_append_only(bs_mem, chunk_list, chunk);
+        let chunk = bchunk_new_copydata(chunks, &data[i_prev..i]);
+        bchunk_list_append_only(chunks, chunk_list, chunk);
         i_prev = i;
     }
 
     if data_last_chunk_len != 0 {
-        let chunk = bchunk_new_copydata(bs_mem, &data[i_prev..(i_prev + data_last_chunk_len)]);
-        bchunk_list_append_only(bs_mem, chunk_list, chunk);
+        let chunk = bchunk_new_copydata(chunks, &data[i_prev..(i_prev + data_last_chunk_len)]);
+        bchunk_list_append_only(chunks, chunk_list, chunk);
         // i_prev = data.len();
     }
 
     if USE_MERGE_CHUNKS {
         if data.len() > info.chunk_byte_size {
-            debug_assert!(chunk_list.chunk_refs.tail.link.data.len() >= info.chunk_byte_size_min);
+            debug_assert!(
+                chunks[*chunk_list.chunk_refs.last().unwrap()].data_len() >= info.chunk_byte_size_min);
         }
     }
 
     // works but better avoid redundant re-alloc
     if false && USE_MERGE_CHUNKS {
-        bchunk_list_ensure_min_size_last(info, bs_mem, chunk_list);
+        bchunk_list_ensure_min_size_last(info, chunks, chunk_list);
     }
 
-    debug_assert_chunklist_size!(chunk_list, data.len());
-    debug_assert_chunklist_data!(chunk_list, data);
+    debug_assert_chunklist_size!(chunks, chunk_list, data.len());
+    debug_assert_chunklist_data!(chunks, chunk_list, data);
 }
 
 
@@ -864,26 +1523,30 @@ fn hash_array_from_data(
 
 /// Similar to `hash_array_from_data`,
 /// but able to step into the next chunk if we run-out of data.
+///
+/// * `cref` Index of the first chunk in `chunk_refs`.
 fn hash_array_from_cref(
-    info: &BArrayInfo, mut cref: PtrMut<BChunkRef>, data_len: usize,
+    info: &BArrayInfo, chunks: &Arena<BChunk>,
+    chunk_refs: &[BChunkId], mut cref: usize, data_len: usize,
     hash_array: &mut [HashKey],
 ) {
     let hash_array_len = data_len / info.chunk_stride;
     let mut i: usize = 0;
     loop {
+        let chunk: &BChunk = &chunks[chunk_refs[cref]];
         let mut i_next: usize = hash_array_len - i;
         let mut data_trim_len = i_next * info.chunk_stride;
-        if data_trim_len > cref.link.data.len() {
-            data_trim_len = cref.link.data.len();
+        if data_trim_len > chunk.data_len() {
+            data_trim_len = chunk.data_len();
             i_next = data_trim_len / info.chunk_stride;
         }
-        debug_assert!(data_trim_len <= cref.link.data.len());
+        debug_assert!(data_trim_len <= chunk.data_len());
         hash_array_from_data(
-            info, &cref.link.data[0..data_trim_len], &mut hash_array[i..(i + i_next)]);
+            info, &bchunk_data(chunks, chunk)[0..data_trim_len], &mut hash_array[i..(i + i_next)]);
         i += i_next;
-        cref = cref.next;
+        cref += 1;
 
-        if !((i < hash_array_len) && (cref != null_const())) {
+        if !((i < hash_array_len) && (cref != chunk_refs.len())) {
             break;
         }
     }
@@ -932,23 +1595,37 @@ fn hash_accum_single(hash_array: &mut [HashKey], mut iter_steps: usize) {
     }
 }
 
-fn key_from_chunk_ref(
-    info: &BArrayInfo, cref: PtrMut<BChunkRef>,
-    // avoid reallocating each time
-    hash_store: &mut [HashKey],
-) -> HashKey {
-    // fill in a reusable array
-    let mut chunk: PtrMut<BChunk> = cref.link;
-    debug_assert_ne!(0, (info.accum_read_ahead_bytes * info.chunk_stride));
-
-    if info.accum_read_ahead_bytes <= chunk.data.len() {
-        let mut key: HashKey = chunk.key;
-
+/// Similar to `hash_accum`, only accumulating the first `hash_array_search_len` values,
+/// used to accumulate a part of a larger hash array.
+fn hash_accum_range(hash_array: &mut [HashKey], hash_array_search_len: usize, mut iter_steps: usize) {
+    while iter_steps != 0 {
+        let hash_offset: usize = iter_steps;
+        let hash_array_search_len = min(hash_array_search_len, hash_array.len() - hash_offset);
+        for i in 0..hash_array_search_len {
+            hash_array[i] += (hash_array[i + hash_offset]) * ((hash_array[i] & 0xff) + 1);
+        }
+        iter_steps -= 1;
+    }
+}
+
+fn key_from_chunk_ref(
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>,
+    chunk_refs: &[BChunkId], cref: usize,
+    // avoid reallocating each time
+    hash_store: &mut [HashKey],
+) -> HashKey {
+    // fill in a reusable array
+    let chunk: BChunkId = chunk_refs[cref];
+    debug_assert_ne!(0, (info.accum_read_ahead_bytes * info.chunk_stride));
+
+    if info.accum_read_ahead_bytes <= chunks[chunk].data_len() {
+        let mut key: HashKey = chunks[chunk].key;
+
         if key != HASH_TABLE_KEY_UNSET {
             // Using key cache!
             // avoids calculating every time
         } else {
-            hash_array_from_cref(info, cref, info.accum_read_ahead_bytes, hash_store);
+            hash_array_from_cref(info, chunks, chunk_refs, cref, info.accum_read_ahead_bytes, hash_store);
             hash_accum_single(hash_store, info.accum_steps);
             key = hash_store[0];
 
@@ -956,12 +1633,12 @@ fn key_from_chunk_ref(
             if key == HASH_TABLE_KEY_UNSET {
                 key = HASH_TABLE_KEY_FALLBACK;
             }
-            chunk.key = key;
+            chunks[chunk].key = key;
         }
         return key;
     } else {
         // corner case - we're too small, calculate the key each time.
-        hash_array_from_cref(info, cref, info.accum_read_ahead_bytes, hash_store);
+        hash_array_from_cref(info, chunks, chunk_refs, cref, info.accum_read_ahead_bytes, hash_store);
         hash_accum_single(hash_store, info.accum_steps);
         let mut key: HashKey = hash_store[0];
 
@@ -972,28 +1649,42 @@ fn key_from_chunk_ref(
     }
 }
 
+/// The table & array data used by `table_lookup`,
+/// these don't change while looking up chunks for an array.
+struct BTableLookup<'a> {
+    info: &'a BArrayInfo,
+    // the reference `BChunkList.chunk_refs`.
+    chunk_refs: &'a [BChunkId],
+    table: &'a [Option<usize>],
+    table_ref_stack: &'a [BTableRef],
+    // hash of each stride of `data` from `i_table_start`.
+    table_hash_array: &'a [HashKey],
+    i_table_start: usize,
+    data: &'a [u8],
+    data_len: usize,
+}
+
+/// Return the index in `chunk_refs` of a chunk matching `data` at `offset`.
 fn table_lookup(
-    info: &BArrayInfo, table: &Vec<PtrMut<BTableRef>>, table_len: usize, i_table_start: usize,
-    data: &[u8], data_len: usize, offset: usize, table_hash_array: &Vec<HashKey>,
-) -> PtrMut<BChunkRef> {
-    let size_left: usize = data_len - offset;
-    let key: HashKey = table_hash_array[((offset - i_table_start) / info.chunk_stride)];
-    let key_index = (key % (table_len as HashKey)) as usize;
-    let mut tref: PtrMut<BTableRef> = table[key_index];
-    while tref != null_const() {
-        let cref: PtrMut<BChunkRef> = tref.cref;
-        if cref.link.key == key {
-            let chunk_test: PtrMut<BChunk> = cref.link;
-            if chunk_test.data.len() <= size_left {
-                if bchunk_data_compare(chunk_test, data, data_len, offset) {
-                    // we could remove the chunk from the table, to avoid multiple hits
-                    return cref;
-                }
-            }
+    lookup: &BTableLookup, chunks: &Arena<BChunk>, offset: usize,
+) -> Option<usize> {
+    let size_left: usize = lookup.data_len - offset;
+    let key: HashKey = lookup.table_hash_array[(offset - lookup.i_table_start) / lookup.info.chunk_stride];
+    let key_index = (key % (lookup.table.len() as HashKey)) as usize;
+    let mut tref: Option<usize> = lookup.table[key_index];
+    while let Some(tref_index) = tref {
+        let cref: usize = lookup.table_ref_stack[tref_index].cref;
+        let chunk_test: &BChunk = &chunks[lookup.chunk_refs[cref]];
+        if  (chunk_test.key == key) &&
+            (chunk_test.data_len() <= size_left) &&
+            bchunk_data_compare(chunks, lookup.chunk_refs[cref], lookup.data, lookup.data_len, offset)
+        {
+            // we could remove the chunk from the table, to avoid multiple hits
+            return Some(cref);
         }
-        tref = tref.next;
+        tref = lookup.table_ref_stack[tref_index].next;
     }
-    null_mut()
+    None
 }
 
 // End Table Lookup
@@ -1001,23 +1692,276 @@ fn table_lookup(
 
 /// []( } )
 
+// # Internal Threading API
+//
+// Threaded hashing & table look-ups,
+// these must give identical results to the single threaded functions.
+// []( { )
+
+/// Return the number of threads to use for `hash_array_len` hashes.
+fn threads_calc(
+    info: &BArrayInfo, hash_array_len: usize,
+) -> usize {
+    max(1, min(info.threads, hash_array_len / BCHUNK_THREAD_HASH_LEN_MIN))
+}
+
+/// Call `f` for each span of `array` (`span_len` elements each) with the index of the span
+/// and its own item of `span_scratch` (which must have an item for each span),
+/// each on its own thread (spans are handled in order when `std` isn't available).
+fn spans_for_each_threaded<T, S, F>(
+    array: &mut [T], span_len: usize,
+    span_scratch: &mut [S],
+    f: F,
+)
+    where
+    T: Send,
+    S: Send,
+    F: Fn(usize, &mut [T], &mut S) + Sync,
+{
+    debug_assert!(array.len().div_ceil(span_len) <= span_scratch.len());
+    #[cfg(feature = "std")]
+    {
+        let f = &f;
+        ::std::thread::scope(|scope| {
+            for ((span_index, span), scratch) in array.chunks_mut(span_len).enumerate().zip(span_scratch) {
+                scope.spawn(move || f(span_index, span, scratch));
+            }
+        });
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        for ((span_index, span), scratch) in array.chunks_mut(span_len).enumerate().zip(span_scratch) {
+            f(span_index, span, scratch);
+        }
+    }
+}
+
+/// Threaded `hash_array_from_data` followed by `hash_accum`.
+///
+/// Each thread hashes its own range of `hash_array`,
+/// including the values ahead of this range which are accumulated into it.
+///
+/// * `thread_hash_arrays` Reused between calls, one array for each thread.
+fn hash_array_from_data_accum_threaded(
+    info: &BArrayInfo, data_slice: &[u8],
+    hash_array: &mut [HashKey],
+    threads: usize,
+    thread_hash_arrays: &mut Vec<Vec<HashKey>>,
+) {
+    let hash_array_len = hash_array.len();
+    let iter_steps = min(info.accum_steps, hash_array_len);
+    let hash_array_search_len = hash_array_len - iter_steps;
+    // triangle number, the furthest value accumulated into any other.
+    let read_ahead_len = (iter_steps * (iter_steps + 1)) / 2;
+    let span_len = hash_array_len.div_ceil(threads);
+
+    if thread_hash_arrays.len() < threads {
+        thread_hash_arrays.resize_with(threads, Vec::new);
+    }
+    spans_for_each_threaded(hash_array, span_len, &mut thread_hash_arrays[..], |span_index, hash_array_span, hash_array_local| {
+        let span_start = span_index * span_len;
+        let span_end = min(span_start + hash_array_span.len() + read_ahead_len, hash_array_len);
+        hash_array_local.clear();
+        hash_array_local.resize(span_end - span_start, 0);
+        hash_array_from_data(
+            info,
+            &data_slice[(span_start * info.chunk_stride)..(span_end * info.chunk_stride)],
+            &mut hash_array_local[..]);
+        hash_accum_range(
+            &mut hash_array_local[..],
+            hash_array_search_len.saturating_sub(span_start),
+            iter_steps);
+        hash_array_span.copy_from_slice(&hash_array_local[..hash_array_span.len()]);
+    });
+}
+
+/// Copy the reference chunks used for look-ups (delta chunks are expanded),
+/// so look-ups can run on other threads while chunks are added to the store.
+///
+/// The data of other chunks is shared, so only references are copied.
+fn table_lookup_chunks_copy(
+    chunks: &Arena<BChunk>, chunk_refs: &[BChunkId],
+    chunks_lookup: &mut Arena<BChunk>, chunk_refs_lookup: &mut Vec<BChunkId>,
+) {
+    chunks_lookup.clear();
+    chunk_refs_lookup.clear();
+    for &chunk in chunk_refs {
+        let chunk: &BChunk = &chunks[chunk];
+        let data: Arc<Vec<u8>> = match chunk.data {
+            BChunkData::Array(ref data) => data.clone(),
+            BChunkData::Delta(_) => Arc::new(bchunk_data(chunks, chunk).into_owned()),
+        };
+        chunk_refs_lookup.push(chunks_lookup.alloc(BChunk {
+            data: BChunkData::Array(data),
+            users: 1,
+            key: chunk.key,
+            #[cfg(feature = "std")]
+            pooled: false,
+        }));
+    }
+}
+
+/// Fill `lookup_array` with the result of `table_lookup` for each stride from `hash_index`.
+#[cfg(feature = "std")]
+fn table_lookup_span(
+    lookup: &BTableLookup, chunks: &Arena<BChunk>,
+    hash_index: usize, lookup_array: &mut [Option<usize>],
+) {
+    let mut offset = lookup.i_table_start + (hash_index * lookup.info.chunk_stride);
+    for cref_found in lookup_array.iter_mut() {
+        *cref_found = table_lookup(lookup, chunks, offset);
+        offset += lookup.info.chunk_stride;
+    }
+}
+
+/// Call `f` with a function returning the result of `table_lookup` for an offset,
+/// each thread looks up its own range of offsets ahead of the offset requested.
+///
+/// Look-ups only run further ahead while they fail (when new data is being merged),
+/// so little work is discarded when a chunk is found (skipping the offsets it contains).
+/// Threads are kept until `f` returns.
+///
+/// * `chunks_lookup` The reference chunks (see `table_lookup_chunks_copy`),
+///   since the store's chunks are modified by `f`.
+/// * `thread_lookup_arrays` Reused between calls, one array for each thread.
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+fn table_lookup_threaded<F>(
+    lookup: &BTableLookup, chunks_lookup: &Arena<BChunk>,
+    threads: usize,
+    thread_lookup_arrays: &mut Vec<Vec<Option<usize>>>,
+    f: F,
+)
+    where
+    F: FnOnce(&mut dyn FnMut(&Arena<BChunk>, usize) -> Option<usize>),
+{
+    #[cfg(feature = "std")]
+    {
+        use std::sync::mpsc;
+
+        if thread_lookup_arrays.len() < threads {
+            thread_lookup_arrays.resize_with(threads, Vec::new);
+        }
+        let hash_array_len = lookup.table_hash_array.len();
+        // the range of look-ups in `thread_lookup_arrays`.
+        let mut lookup_start: usize = 0;
+        let mut lookup_end: usize = 0;
+        let mut lookup_len: usize = BCHUNK_THREAD_LOOKUP_LEN_MIN;
+
+        ::std::thread::scope(|scope| {
+            // each thread (besides this one) has its own channels,
+            // so a thread panicking can't leave this thread waiting for its result.
+            let mut thread_channels = Vec::with_capacity(threads - 1);
+            for _ in 1..threads {
+                let (job_send, job_recv) = mpsc::channel::<(usize, Vec<Option<usize>>)>();
+                let (result_send, result_recv) = mpsc::channel::<Vec<Option<usize>>>();
+                scope.spawn(move || {
+                    for (hash_index, mut lookup_array) in job_recv {
+                        table_lookup_span(lookup, chunks_lookup, hash_index, &mut lookup_array[..]);
+                        if result_send.send(lookup_array).is_err() {
+                            break;
+                        }
+                    }
+                });
+                thread_channels.push((job_send, result_recv));
+            }
+
+            let mut lookup_fn = |_: &Arena<BChunk>, offset: usize| -> Option<usize> {
+                let hash_index = (offset - lookup.i_table_start) / lookup.info.chunk_stride;
+                if !((hash_index >= lookup_start) && (hash_index < lookup_end)) {
+                    lookup_len = {
+                        if hash_index == lookup_end {
+                            min(lookup_len * 2, BCHUNK_THREAD_LOOKUP_LEN_MAX)
+                        } else {
+                            BCHUNK_THREAD_LOOKUP_LEN_MIN
+                        }
+                    };
+                    lookup_start = hash_index;
+                    lookup_end = min(hash_index + (threads * lookup_len), hash_array_len);
+                    let mut thread_pending: usize = 0;
+                    for (thread_index, (job_send, _)) in thread_channels.iter().enumerate() {
+                        let span_start = lookup_start + ((thread_index + 1) * lookup_len);
+                        if span_start >= lookup_end {
+                            break;
+                        }
+                        let mut lookup_array = ::core::mem::take(&mut thread_lookup_arrays[thread_index + 1]);
+                        lookup_array.clear();
+                        lookup_array.resize(min(lookup_len, lookup_end - span_start), None);
+                        job_send.send((span_start, lookup_array)).unwrap();
+                        thread_pending += 1;
+                    }
+                    {
+                        let lookup_array = &mut thread_lookup_arrays[0];
+                        lookup_array.clear();
+                        lookup_array.resize(min(lookup_len, lookup_end - lookup_start), None);
+                        table_lookup_span(lookup, chunks_lookup, lookup_start, &mut lookup_array[..]);
+                    }
+                    for (thread_index, (_, result_recv)) in thread_channels.iter().enumerate().take(thread_pending) {
+                        thread_lookup_arrays[thread_index + 1] = result_recv.recv().expect("look-up thread failed");
+                    }
+                }
+                let lookup_index = hash_index - lookup_start;
+                thread_lookup_arrays[lookup_index / lookup_len][lookup_index % lookup_len]
+            };
+            f(&mut lookup_fn);
+        });
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        f(&mut |_, offset| table_lookup(lookup, chunks_lookup, offset));
+    }
+}
+
+// []( } )
+
+/// Return the reference chunk at `offset` to use for `data` when the table lookup fails,
+/// either because it matches exactly or because `data` can be stored as a delta of it
+/// (in which case a new delta chunk is returned).
+///
+/// Only used when storing deltas, for arrays which are the same size as the reference.
+fn bchunk_list_aligned_find(
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>,
+    chunk_list_reference: &BChunkList, cref_last: usize,
+    data: &[u8], data_len: usize, offset: usize,
+) -> Option<BChunkId> {
+    let cref: usize = bchunk_list_find_offset(chunk_list_reference, offset);
+    if (cref >= cref_last) || (chunk_list_reference.chunk_offsets[cref] != offset) {
+        return None;
+    }
+    let chunk_reference: BChunkId = chunk_list_reference.chunk_refs[cref];
+    let i: usize = offset + chunks[chunk_reference].data_len();
+    if i > data_len {
+        None
+    } else if bchunk_data_compare(chunks, chunk_reference, data, data_len, offset) {
+        Some(chunk_reference)
+    } else {
+        bchunk_new_delta(info, chunks, chunk_reference, &data[offset..i])
+    }
+}
+
 /// * `data` Data to store in the returned value.
 /// * `data_len_original` Length of data in bytes.
 /// * `chunk_list_reference` Reuse this list or chunks within it, don't modify its content.
 ///
+/// * `scratch` Reused between calls to avoid reallocating.
+///
+/// Returns None when `data` matches `chunk_list_reference`, which should be used instead.
+///
 /// Note: The caller is responsible for adding the user.
 fn bchunk_list_from_data_merge(
-    info: &BArrayInfo, bs_mem: &mut BArrayMemory,
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>, scratch: &mut BArrayScratch,
     data: &[u8], data_len_original: usize,
-    chunk_list_reference: PtrMut<BChunkList>,
-) -> PtrMut<BChunkList> {
-    debug_assert_chunklist_size!(chunk_list_reference, chunk_list_reference.total_size);
+    chunk_list_reference: &BChunkList,
+) -> Option<BChunkList> {
+    debug_assert_chunklist_size!(chunks, chunk_list_reference, chunk_list_reference.total_size);
+
+    let chunk_refs_reference: &[BChunkId] = &chunk_list_reference.chunk_refs[..];
 
     // -----------------------------------------------------------------------
     // Fast-Path for exact match
     // Check for exact match, if so, return the current list.
 
-    let mut cref_match_first: PtrMut<BChunkRef> = null_mut();
+    // Index of the last matching chunk in `chunk_refs_reference`.
+    let mut cref_match_first: Option<usize> = None;
 
     let mut chunk_list_reference_skip_len: usize = 0;
     let mut chunk_list_reference_skip_bytes: usize = 0;
@@ -1026,16 +1970,17 @@ fn bchunk_list_from_data_merge(
     if USE_FASTPATH_CHUNKS_FIRST {
         let mut full_match: bool = true;
 
-        let mut cref: PtrMut<BChunkRef> = chunk_list_reference.chunk_refs.head;
+        let mut cref: usize = 0;
         while i_prev < data_len_original {
-            if  cref != null_mut() &&
-                bchunk_data_compare(cref.link, data, data_len_original, i_prev)
+            if  cref != chunk_refs_reference.len() &&
+                bchunk_data_compare(chunks, chunk_refs_reference[cref], data, data_len_original, i_prev)
             {
-                cref_match_first = cref;
+                let chunk_len = chunks[chunk_refs_reference[cref]].data_len();
+                cref_match_first = Some(cref);
                 chunk_list_reference_skip_len += 1;
-                chunk_list_reference_skip_bytes += cref.link.data.len();
-                i_prev += cref.link.data.len();
-                cref = cref.next;
+                chunk_list_reference_skip_bytes += chunk_len;
+                i_prev += chunk_len;
+                cref += 1;
             } else {
                 full_match = false;
                 break;
@@ -1044,7 +1989,7 @@ fn bchunk_list_from_data_merge(
 
         if full_match {
             if chunk_list_reference.total_size == data_len_original {
-                return chunk_list_reference;
+                return None;
             }
         }
     }
@@ -1052,25 +1997,16 @@ fn bchunk_list_from_data_merge(
     // ---------------------
 
     // Copy until we have a mismatch
-    let chunk_list: PtrMut<BChunkList> = bchunk_list_new(bs_mem, data_len_original);
-    if cref_match_first != null_const() {
-        let mut chunk_size_step: usize = 0;
-        let mut cref: PtrMut<BChunkRef> = chunk_list_reference.chunk_refs.head;
-        loop {
-            let chunk: PtrMut<BChunk> = cref.link;
-            chunk_size_step += chunk.data.len();
-            bchunk_list_append_only(bs_mem, chunk_list, chunk);
-            debug_assert_chunklist_size!(chunk_list, chunk_size_step);
-            debug_assert_chunklist_data!(chunk_list, data);
-            if cref == cref_match_first {
-                break;
-            } else {
-                cref = cref.next;
-            }
-        }
+    let mut chunk_list: BChunkList = bchunk_list_new(data_len_original);
+    if let Some(cref_match_first) = cref_match_first {
+        bchunk_list_append_only_range(
+            chunks, &mut chunk_list, chunk_list_reference, 0..(cref_match_first + 1));
+        let chunk_size_step: usize = bchunk_list_offset_end(chunks, &chunk_list);
+        debug_assert_chunklist_size!(chunks, &chunk_list, chunk_size_step);
+        debug_assert_chunklist_data!(chunks, &chunk_list, data);
         // happens when bytes are removed from the end of the array
         if chunk_size_step == data_len_original {
-            return chunk_list;
+            return Some(chunk_list);
         }
 
         i_prev = chunk_size_step;
@@ -1090,24 +2026,26 @@ fn bchunk_list_from_data_merge(
     // since we want to ignore chunks already matched
     let mut data_len: usize = data_len_original;
 
-    let mut chunk_list_reference_last: PtrMut<BChunkRef> = null_mut();
+    // Index of the first trailing chunk which matches,
+    // the length of `chunk_refs_reference` when there are none.
+    let mut chunk_list_reference_last: usize = chunk_refs_reference.len();
 
     if USE_FASTPATH_CHUNKS_LAST {
-        if !chunk_list_reference.chunk_refs.is_empty() {
-            let mut cref: PtrMut<BChunkRef> = chunk_list_reference.chunk_refs.tail;
+        if !chunk_refs_reference.is_empty() {
+            let mut cref: usize = chunk_refs_reference.len() - 1;
             while
-                (cref.prev != null_mut()) &&
-                (cref != cref_match_first) &&
-                (cref.link.data.len() <= data_len - i_prev)
+                (cref != 0) &&
+                (Some(cref) != cref_match_first) &&
+                (chunks[chunk_refs_reference[cref]].data_len() <= data_len - i_prev)
             {
-                let chunk_test: PtrMut<BChunk> = cref.link;
-                let offset: usize = data_len - chunk_test.data.len();
-                if bchunk_data_compare(chunk_test, data, data_len, offset) {
+                let chunk_test: BChunkId = chunk_refs_reference[cref];
+                let offset: usize = data_len - chunks[chunk_test].data_len();
+                if bchunk_data_compare(chunks, chunk_test, data, data_len, offset) {
                     data_len = offset;
                     chunk_list_reference_last = cref;
                     chunk_list_reference_skip_len += 1;
-                    chunk_list_reference_skip_bytes += cref.link.data.len();
-                    cref = cref.prev;
+                    chunk_list_reference_skip_bytes += chunks[chunk_test].data_len();
+                    cref -= 1;
                 } else {
                     break;
                 }
@@ -1129,7 +2067,9 @@ fn bchunk_list_from_data_merge(
     if USE_ALIGN_CHUNKS_TEST {
         if chunk_list.total_size == chunk_list_reference.total_size {
             // if we're already a quarter aligned
-            if data_len - i_prev <= chunk_list.total_size / 4 {
+            // when storing deltas, chunks which don't match are checked by the table lookup
+            // before creating a delta (see `bchunk_list_aligned_find`).
+            if (data_len - i_prev <= chunk_list.total_size / 4) && !info.use_delta() {
                 use_aligned = true;
             } else {
                 // TODO, walk over chunks and check if some arbitrary amount align
@@ -1142,37 +2082,32 @@ fn bchunk_list_from_data_merge(
 
     if use_aligned {
         // Copy matching chunks, creates using the same 'layout' as the reference
-        let mut cref: PtrMut<BChunkRef> = {
-            if cref_match_first != null_mut() {
-                cref_match_first.next
-            } else {
-                chunk_list_reference.chunk_refs.head
-            }
-        };
+        let mut cref: usize = cref_match_first.map_or(0, |cref| cref + 1);
         while i_prev != data_len {
-            let i: usize = i_prev + cref.link.data.len();
+            let chunk_reference: BChunkId = chunk_refs_reference[cref];
+            let i: usize = i_prev + chunks[chunk_reference].data_len();
             debug_assert!(i != i_prev);
 
             if (cref != chunk_list_reference_last) &&
-                bchunk_data_compare(cref.link, data, data_len, i_prev)
+                bchunk_data_compare(chunks, chunk_reference, data, data_len, i_prev)
             {
-                bchunk_list_append(info, bs_mem, chunk_list, cref.link);
-                debug_assert_chunklist_size!(chunk_list, i);
-                debug_assert_chunklist_data!(chunk_list, data);
+                bchunk_list_append(info, chunks, &mut chunk_list, chunk_reference);
+                debug_assert_chunklist_size!(chunks, &chunk_list, i);
+                debug_assert_chunklist_data!(chunks, &chunk_list, data);
             } else {
-                bchunk_list_append_data(info, bs_mem, chunk_list, &data[i_prev..i]);
-                debug_assert_chunklist_size!(chunk_list, i);
-                debug_assert_chunklist_data!(chunk_list, data);
+                bchunk_list_append_data(info, chunks, &mut chunk_list, &data[i_prev..i]);
+                debug_assert_chunklist_size!(chunks, &chunk_list, i);
+                debug_assert_chunklist_data!(chunks, &chunk_list, data);
             }
 
-            cref = cref.next;
+            cref += 1;
 
             i_prev = i;
         }
     } else if
         (data_len - i_prev >= info.chunk_byte_size) &&
-        (chunk_list_reference.chunk_refs_len >= chunk_list_reference_skip_len) &&
-        (chunk_list_reference.chunk_refs.head != null_mut())
+        (chunk_refs_reference.len() >= chunk_list_reference_skip_len) &&
+        (!chunk_refs_reference.is_empty())
     {
 
         // --------------------------------------------------------------------
@@ -1185,46 +2120,57 @@ fn bchunk_list_from_data_merge(
 
         let i_table_start = i_prev;
         let table_hash_array_len: usize = (data_len - i_prev) / info.chunk_stride;
-        let mut table_hash_array: Vec<HashKey> = Vec::with_capacity(table_hash_array_len);
-        unsafe { table_hash_array.set_len(table_hash_array_len) };
-
-        hash_array_from_data(info, &data[i_prev..data_len], &mut table_hash_array[..]);
+        let table_hash_array: &mut Vec<HashKey> = &mut scratch.table_hash_array;
+        table_hash_array.clear();
+        table_hash_array.resize(table_hash_array_len, 0);
+
+        let threads: usize = threads_calc(info, table_hash_array_len);
+        if threads > 1 {
+            hash_array_from_data_accum_threaded(
+                info, &data[i_prev..data_len], &mut table_hash_array[..], threads,
+                &mut scratch.thread_hash_arrays);
+        } else {
+            hash_array_from_data(info, &data[i_prev..data_len], &mut table_hash_array[..]);
 
-        hash_accum(&mut table_hash_array[..], table_hash_array_len, info.accum_steps);
+            hash_accum(&mut table_hash_array[..], table_hash_array_len, info.accum_steps);
+        }
 
         let chunk_list_reference_remaining_len: usize =
-            (chunk_list_reference.chunk_refs_len - chunk_list_reference_skip_len) + 1;
-        let mut table_ref_stack: Vec<BTableRef> =
-            Vec::with_capacity(chunk_list_reference_remaining_len);
+            (chunk_refs_reference.len() - chunk_list_reference_skip_len) + 1;
+        let table_ref_stack: &mut Vec<BTableRef> = &mut scratch.table_ref_stack;
+        table_ref_stack.clear();
+        table_ref_stack.reserve(chunk_list_reference_remaining_len);
 
         let table_len = chunk_list_reference_remaining_len * BCHUNK_HASH_TABLE_MUL;
-        let mut table: Vec<PtrMut<BTableRef>> = vec![null_mut(); table_len];
+        let table: &mut Vec<Option<usize>> = &mut scratch.table;
+        table.clear();
+        table.resize(table_len, None);
 
         // table_make - inline
         // include one matching chunk, to allow for repeating values
         {
             // all values are filled
-            let mut hash_store: Vec<HashKey> = Vec::with_capacity(info.accum_read_ahead_len);
-            unsafe { hash_store.set_len(info.accum_read_ahead_len) };
+            let hash_store: &mut Vec<HashKey> = &mut scratch.hash_store;
+            hash_store.clear();
+            hash_store.resize(info.accum_read_ahead_len, 0);
 
             let mut chunk_list_reference_bytes_remaining: usize =
                 chunk_list_reference.total_size - chunk_list_reference_skip_bytes;
 
-            let mut cref: PtrMut<BChunkRef> = {
-                if cref_match_first != null_mut() {
-                    chunk_list_reference_bytes_remaining += cref_match_first.link.data.len();
+            let mut cref: usize = {
+                if let Some(cref_match_first) = cref_match_first {
+                    chunk_list_reference_bytes_remaining +=
+                        chunks[chunk_refs_reference[cref_match_first]].data_len();
                     cref_match_first
                 } else {
-                    chunk_list_reference.chunk_refs.head
+                    0
                 }
             };
 
             if USE_PARANOID_CHECKS {
                 let mut test_bytes_len: usize = 0;
-                let mut cr: PtrMut<BChunkRef> = cref;
-                while cr != chunk_list_reference_last {
-                    test_bytes_len += cr.link.data.len();
-                    cr = cr.next;
+                for &chunk in &chunk_refs_reference[cref..chunk_list_reference_last] {
+                    test_bytes_len += chunks[chunk].data_len();
                 }
                 debug_assert!(test_bytes_len == chunk_list_reference_bytes_remaining);
             }
@@ -1233,89 +2179,144 @@ fn bchunk_list_from_data_merge(
                 (cref != chunk_list_reference_last) &&
                 (chunk_list_reference_bytes_remaining >= info.accum_read_ahead_bytes)
             {
-                let key: HashKey = key_from_chunk_ref(info, cref, &mut hash_store[..]);
+                let key: HashKey = key_from_chunk_ref(
+                    info, chunks, chunk_refs_reference, cref, &mut hash_store[..]);
                 let key_index: usize = (key % table_len as HashKey) as usize;
-                let tref_prev: PtrMut<BTableRef> = table[key_index];
                 debug_assert!(table_ref_stack.len() < chunk_list_reference_remaining_len);
-                table_ref_stack.push(BTableRef { cref: cref, next: tref_prev });
-                table[key_index] = PtrMut(table_ref_stack.last_mut().unwrap());
+                table_ref_stack.push(BTableRef { cref, next: table[key_index] });
+                table[key_index] = Some(table_ref_stack.len() - 1);
 
-                chunk_list_reference_bytes_remaining -= cref.link.data.len();
-                cref = cref.next;
+                chunk_list_reference_bytes_remaining -= chunks[chunk_refs_reference[cref]].data_len();
+                cref += 1;
             }
 
             debug_assert!(table_ref_stack.len() <= chunk_list_reference_remaining_len);
-
-            drop(hash_store);
         }
         // done making the table
 
-        debug_assert!(i_prev <= data_len);
-        let mut i = i_prev;
-        while i < data_len {
-            // Assumes exiting chunk isnt a match!
-            let mut cref_found: PtrMut<BChunkRef> = table_lookup(
-                info,
-                &table, table_len, i_table_start,
-                data, data_len, i, &table_hash_array);
-
-            if cref_found != null_const() {
-                debug_assert!(i < data_len);
-                if i != i_prev {
-                    bchunk_list_append_data_n(info, bs_mem, chunk_list, &data[i_prev..i]);
-                    i_prev = i;
-                    if false && i_prev != 0 { } // quiet warning!
-                }
+        // when storing deltas, the reference chunk at the same offset is checked
+        // for offsets the table lookup doesn't find.
+        let use_aligned_delta: bool =
+            info.use_delta() && (chunk_list.total_size == chunk_list_reference.total_size);
+
+        let lookup = BTableLookup {
+            info,
+            chunk_refs: chunk_refs_reference,
+            table: &table[..],
+            table_ref_stack: &table_ref_stack[..],
+            table_hash_array: &table_hash_array[..],
+            i_table_start,
+            data,
+            data_len,
+        };
 
-                // now add the reference chunk
-                {
-                    let chunk_found: PtrMut<BChunk> = cref_found.link;
-                    i += chunk_found.data.len();
-                    bchunk_list_append(info, bs_mem, chunk_list, chunk_found);
-                }
-                i_prev = i;
-                debug_assert!(i_prev <= data_len);
-                debug_assert_chunklist_size!(chunk_list, i_prev);
-                debug_assert_chunklist_data!(chunk_list, data);
-
-                // its likely that the next chunk in the list will be a match, so check it!
-                while
-                    (cref_found.next != null_mut()) &&
-                    (cref_found.next != chunk_list_reference_last)
-                {
-                    cref_found = cref_found.next;
-                    let chunk_found: PtrMut<BChunk> = cref_found.link;
-
-                    if bchunk_data_compare(chunk_found, data, data_len, i_prev) {
-                        // may be useful to remove table data,
-                        // assuming we dont have repeating memory
-                        // where it would be useful to re-use chunks.
-                        i += chunk_found.data.len();
-                        bchunk_list_append(info, bs_mem, chunk_list, chunk_found);
-                        // chunk_found may be freed!
+        if threads > 1 {
+            table_lookup_chunks_copy(
+                chunks, chunk_refs_reference,
+                &mut scratch.table_lookup_chunks, &mut scratch.table_lookup_chunk_refs);
+        }
+
+        // merge using `table_lookup_fn` to find chunks,
+        // so look-ups can run on other threads (see `table_lookup_threaded`).
+        let mut table_merge = |table_lookup_fn: &mut dyn FnMut(&Arena<BChunk>, usize) -> Option<usize>| {
+            debug_assert!(i_prev <= data_len);
+            let mut i = i_prev;
+            while i < data_len {
+                // Assumes exiting chunk isnt a match!
+                let cref_found: Option<usize> = table_lookup_fn(chunks, i);
+
+                if let Some(mut cref_found) = cref_found {
+                    debug_assert!(i < data_len);
+                    if i != i_prev {
+                        bchunk_list_append_data_n(info, chunks, &mut chunk_list, &data[i_prev..i]);
+                    }
+
+                    // now add the reference chunk
+                    {
+                        let chunk_found: BChunkId = chunk_refs_reference[cref_found];
+                        i += chunks[chunk_found].data_len();
+                        bchunk_list_append(info, chunks, &mut chunk_list, chunk_found);
+                    }
+                    i_prev = i;
+                    debug_assert!(i_prev <= data_len);
+                    debug_assert_chunklist_size!(chunks, &chunk_list, i_prev);
+                    debug_assert_chunklist_data!(chunks, &chunk_list, data);
+
+                    // its likely that the next chunk in the list will be a match, so check it!
+                    while
+                        (cref_found + 1 != chunk_refs_reference.len()) &&
+                        (cref_found + 1 != chunk_list_reference_last)
+                    {
+                        cref_found += 1;
+                        let chunk_found: BChunkId = chunk_refs_reference[cref_found];
+
+                        if bchunk_data_compare(chunks, chunk_found, data, data_len, i_prev) {
+                            // may be useful to remove table data,
+                            // assuming we dont have repeating memory
+                            // where it would be useful to re-use chunks.
+                            i += chunks[chunk_found].data_len();
+                            bchunk_list_append(info, chunks, &mut chunk_list, chunk_found);
+                            // chunk_found may be freed!
+                            i_prev = i;
+                            debug_assert!(i_prev <= data_len);
+                            debug_assert_chunklist_size!(chunks, &chunk_list, i_prev);
+                            debug_assert_chunklist_data!(chunks, &chunk_list, data);
+                        } else {
+                            break;
+                        }
+                    }
+                } else {
+                    let chunk_aligned: Option<BChunkId> = {
+                        if use_aligned_delta {
+                            bchunk_list_aligned_find(
+                                info, chunks, chunk_list_reference, chunk_list_reference_last,
+                                data, data_len, i)
+                        } else {
+                            None
+                        }
+                    };
+                    if let Some(chunk_aligned) = chunk_aligned {
+                        if i != i_prev {
+                            bchunk_list_append_data_n(info, chunks, &mut chunk_list, &data[i_prev..i]);
+                        }
+                        i += chunks[chunk_aligned].data_len();
+                        bchunk_list_append(info, chunks, &mut chunk_list, chunk_aligned);
                         i_prev = i;
                         debug_assert!(i_prev <= data_len);
-                        debug_assert_chunklist_size!(chunk_list, i_prev);
-                        debug_assert_chunklist_data!(chunk_list, data);
+                        debug_assert_chunklist_size!(chunks, &chunk_list, i_prev);
+                        debug_assert_chunklist_data!(chunks, &chunk_list, data);
                     } else {
-                        break;
+                        i += info.chunk_stride;
                     }
                 }
-            } else {
-                i = i + info.chunk_stride;
             }
+
+        };
+        if threads > 1 {
+            let lookup_threaded = BTableLookup {
+                chunk_refs: &scratch.table_lookup_chunk_refs[..],
+                ..lookup
+            };
+            table_lookup_threaded(
+                &lookup_threaded, &scratch.table_lookup_chunks, threads,
+                &mut scratch.thread_lookup_arrays, table_merge);
+            // don't keep chunk data from being freed.
+            scratch.table_lookup_chunks.clear();
+            scratch.table_lookup_chunk_refs.clear();
+        } else {
+            let mut table_lookup_fn = |chunks: &Arena<BChunk>, offset: usize| table_lookup(&lookup, chunks, offset);
+            table_merge(&mut table_lookup_fn);
         }
 
-        drop(table_hash_array);
-        drop(table);
-        drop(table_ref_stack);
+        // keep scratch memory for reuse.
+        table.clear();
 
         // End Table Lookup
         // ----------------
     }
 
-    debug_assert_chunklist_size!(chunk_list, i_prev);
-    debug_assert_chunklist_data!(chunk_list, data);
+    debug_assert_chunklist_size!(chunks, &chunk_list, i_prev);
+    debug_assert_chunklist_data!(chunks, &chunk_list, data);
 
     // -----------------------------------------------------------------------
     // No Duplicates to copy, write new chunks
@@ -1323,43 +2324,323 @@ fn bchunk_list_from_data_merge(
     // Trailing chunks, no matches found in table lookup above.
     // Write all new data. */
     if i_prev != data_len {
-        bchunk_list_append_data_n(info, bs_mem, chunk_list, &data[i_prev..data_len]);
+        bchunk_list_append_data_n(info, chunks, &mut chunk_list, &data[i_prev..data_len]);
         i_prev = data_len;
     }
 
     debug_assert!(i_prev == data_len);
 
     if USE_FASTPATH_CHUNKS_LAST {
-        if chunk_list_reference_last != null_mut() {
-            // write chunk_list_reference_last since it hasn't been written yet
-            let mut cref: PtrMut<BChunkRef> = chunk_list_reference_last;
-            while cref != null_mut() {
-                let chunk: PtrMut<BChunk> = cref.link;
-                // debug_assert!(bchunk_data_compare(chunk, data, data_len, i_prev));
-                i_prev += chunk.data.len();
-                // use simple since we assume the references
-                // chunks have already been sized correctly.
-                bchunk_list_append_only(bs_mem, chunk_list, chunk);
-                debug_assert_chunklist_data!(chunk_list, data);
-                cref = cref.next;
-            }
-        }
+        // write chunk_list_reference_last since it hasn't been written yet
+        // use simple since we assume the references
+        // chunks have already been sized correctly.
+        bchunk_list_append_only_range(
+            chunks, &mut chunk_list, chunk_list_reference,
+            chunk_list_reference_last..chunk_refs_reference.len());
+        i_prev = bchunk_list_offset_end(chunks, &chunk_list);
+        debug_assert_chunklist_data!(chunks, &chunk_list, data);
     }
 
     debug_assert!(i_prev == data_len_original);
 
     // check we're the correct size and that we didn't accidentally modify the reference
-    debug_assert_chunklist_size!(chunk_list, data_len_original);
-    debug_assert_chunklist_size!(chunk_list_reference, chunk_list_reference.total_size);
+    debug_assert_chunklist_size!(chunks, &chunk_list, data_len_original);
+    debug_assert_chunklist_size!(chunks, chunk_list_reference, chunk_list_reference.total_size);
 
-    debug_assert_chunklist_data!(chunk_list, data);
+    debug_assert_chunklist_data!(chunks, &chunk_list, data);
 
-    return chunk_list;
+    Some(chunk_list)
 }
 // end private API
 
 /// []( } )
 
+// # Internal Compaction API
+//
+// Only used by `BArrayStore.compact` & `BArrayStore.compact_state`.
+// []( { )
+
+/// Lookup chunks by their contents, so identical chunks can be shared.
+///
+/// Delta chunks are never added since they're never re-chunked.
+type BChunkDataTable = Map<(usize, u32), Vec<BChunkId>>;
+
+#[inline]
+fn bchunk_data_table_key(data: &[u8]) -> (usize, u32) {
+    (data.len(), hash_data(data))
+}
+
+fn bchunk_data_table_insert(
+    chunks: &Arena<BChunk>, table: &mut BChunkDataTable, chunk: BChunkId,
+) {
+    debug_assert!(chunks[chunk].delta().is_none());
+    table.entry(bchunk_data_table_key(&chunks[chunk].array()[..])).or_default().push(chunk);
+}
+
+fn bchunk_data_table_remove(
+    chunks: &Arena<BChunk>, table: &mut BChunkDataTable, chunk: BChunkId,
+) {
+    debug_assert!(chunks[chunk].delta().is_none());
+    let key = bchunk_data_table_key(&chunks[chunk].array()[..]);
+    let is_empty = {
+        if let Some(chunk_array) = table.get_mut(&key) {
+            chunk_array.retain(|c| *c != chunk);
+            chunk_array.is_empty()
+        } else {
+            false
+        }
+    };
+    if is_empty {
+        table.remove(&key);
+    }
+}
+
+fn bchunk_data_table_lookup(
+    chunks: &Arena<BChunk>, table: &BChunkDataTable, data: &[u8],
+) -> Option<BChunkId> {
+    if let Some(chunk_array) = table.get(&bchunk_data_table_key(data)) {
+        for &chunk in chunk_array {
+            if &chunks[chunk].array()[..] == data {
+                return Some(chunk);
+            }
+        }
+    }
+    None
+}
+
+/// Similar to `bchunk_list_append_data_n`,
+/// except existing chunks with matching contents are re-used.
+///
+/// Note: `data` is expected to be made up of existing (already merged) chunks,
+/// so there is no need to merge with the previous chunk.
+fn bchunk_list_append_data_n_dedup(
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>,
+    chunk_list: &mut BChunkList,
+    data: &[u8],
+    table: &mut BChunkDataTable,
+) {
+    let (data_trim_len, data_last_chunk_len) = bchunk_list_calc_trim_len(info, data.len());
+
+    macro_rules! append_dedup {
+        ($data_slice:expr) => {
+            {
+                let data_slice: &[u8] = $data_slice;
+                let chunk: BChunkId = {
+                    if let Some(chunk) = bchunk_data_table_lookup(chunks, table, data_slice) {
+                        chunk
+                    } else {
+                        let chunk = bchunk_new_copydata(chunks, data_slice);
+                        bchunk_data_table_insert(chunks, table, chunk);
+                        chunk
+                    }
+                };
+                bchunk_list_append_only(chunks, chunk_list, chunk);
+            }
+        }
+    }
+
+    let mut i_prev = 0;
+    while i_prev != data_trim_len {
+        let i = i_prev + info.chunk_byte_size;
+        append_dedup!(&data[i_prev..i]);
+        i_prev = i;
+    }
+
+    if data_last_chunk_len != 0 {
+        append_dedup!(&data[i_prev..(i_prev + data_last_chunk_len)]);
+    }
+}
+
+/// Re-chunk runs of chunks only used by `chunk_list`,
+/// so their boundaries align with `BArrayInfo.chunk_byte_size`,
+/// sharing chunks from `table` where possible.
+///
+/// The contents of `chunk_list` are replaced in-place, so all its users see the change.
+fn bchunk_list_compact(
+    info: &BArrayInfo, bs_mem: &mut BArrayMemory,
+    chunk_list: BChunkListId,
+    table: &mut BChunkDataTable,
+) {
+    let chunks: &mut Arena<BChunk> = &mut bs_mem.chunk;
+    let chunk_list: &mut BChunkList = &mut bs_mem.chunk_list[chunk_list];
+
+    // Store before making any changes, since chunks from this list may be re-used.
+    // Delta chunks are kept as-is, since re-chunking would expand them.
+    let chunk_array: Vec<(BChunkId, bool)> = chunk_list.chunk_refs.iter().map(|&chunk| {
+        (chunk, chunks[chunk].users == 1 && chunks[chunk].delta().is_none())
+    }).collect();
+
+    if !chunk_array.iter().any(|&(_, is_single)| is_single) {
+        return;
+    }
+
+    let mut chunk_list_new: BChunkList = bchunk_list_new(chunk_list.total_size);
+    let mut data_run: Vec<u8> = Vec::new();
+    for &(chunk, is_single) in &chunk_array {
+        if is_single {
+            data_run.extend_from_slice(&chunks[chunk].array()[..]);
+        } else {
+            if !data_run.is_empty() {
+                bchunk_list_append_data_n_dedup(info, chunks, &mut chunk_list_new, &data_run[..], table);
+                data_run.clear();
+            }
+            bchunk_list_append_only(chunks, &mut chunk_list_new, chunk);
+        }
+    }
+    if !data_run.is_empty() {
+        bchunk_list_append_data_n_dedup(info, chunks, &mut chunk_list_new, &data_run[..], table);
+    }
+    drop(data_run);
+
+    debug_assert_chunklist_size!(chunks, &chunk_list_new, chunk_list.total_size);
+
+    // Swap the chunks into the original list, keeping it's users,
+    // then free the old chunks.
+    ::core::mem::swap(&mut chunk_list.chunk_refs, &mut chunk_list_new.chunk_refs);
+    ::core::mem::swap(&mut chunk_list.chunk_offsets, &mut chunk_list_new.chunk_offsets);
+
+    for chunk in chunk_list_new.chunk_refs {
+        // remove chunks which are about to be freed from the table.
+        if chunks[chunk].users == 1 {
+            if let Some(delta) = chunks[chunk].delta() {
+                if chunks[delta.base].users == 1 {
+                    bchunk_data_table_remove(chunks, table, delta.base);
+                }
+            } else {
+                bchunk_data_table_remove(chunks, table, chunk);
+            }
+        }
+        bchunk_decref(chunks, chunk);
+    }
+}
+
+// []( } )
+
+// # Internal Import API
+//
+// Only used by `BArrayStore.state_import`.
+// []( { )
+
+/// Return the key of a chunk from another store (which can't cache it),
+/// only for chunks large enough that the key doesn't depend on the chunks after it.
+fn key_from_chunk_ref_import(
+    info: &BArrayInfo, chunks_src: &Arena<BChunk>,
+    chunk_refs_src: &[BChunkId], cref: usize,
+    hash_store: &mut [HashKey],
+) -> HashKey {
+    let chunk: &BChunk = &chunks_src[chunk_refs_src[cref]];
+    debug_assert!(info.accum_read_ahead_bytes <= chunk.data_len());
+    if chunk.key != HASH_TABLE_KEY_UNSET {
+        return chunk.key;
+    }
+    hash_array_from_cref(info, chunks_src, chunk_refs_src, cref, info.accum_read_ahead_bytes, hash_store);
+    hash_accum_single(hash_store, info.accum_steps);
+    let key: HashKey = hash_store[0];
+    if unlikely!(key == HASH_TABLE_KEY_UNSET) {
+        return HASH_TABLE_KEY_FALLBACK;
+    }
+    key
+}
+
+/// Create a chunk list with the same chunks as `chunk_list_src` (from another store),
+/// re-using chunks from `chunk_list_reference` which have the same contents.
+///
+/// Returns None when the result would be identical to `chunk_list_reference`.
+///
+/// Note: the stride & chunk size of both stores must match,
+/// so the chunks of `chunk_list_src` don't need to be re-chunked.
+fn bchunk_list_from_chunk_list_import(
+    info: &BArrayInfo, chunks: &mut Arena<BChunk>, scratch: &mut BArrayScratch,
+    chunks_src: &Arena<BChunk>, chunk_list_src: &BChunkList,
+    chunk_list_reference: Option<&BChunkList>,
+) -> Option<BChunkList> {
+    let hash_store: &mut Vec<HashKey> = &mut scratch.hash_store;
+    hash_store.clear();
+    hash_store.resize(info.accum_read_ahead_len, 0);
+
+    // Lookup reference chunks by their (cached) keys,
+    // smaller chunks don't have a key that only depends on their own contents,
+    // use their data instead.
+    let mut table_key: Map<HashKey, Vec<BChunkId>> = Map::new();
+    let mut table_data: BChunkDataTable = Map::new();
+    if let Some(chunk_list_reference) = chunk_list_reference {
+        let chunk_refs_reference: &[BChunkId] = &chunk_list_reference.chunk_refs[..];
+        for (cref, &chunk) in chunk_refs_reference.iter().enumerate() {
+            if info.accum_read_ahead_bytes <= chunks[chunk].data_len() {
+                let key = key_from_chunk_ref(info, chunks, chunk_refs_reference, cref, &mut hash_store[..]);
+                table_key.entry(key).or_default().push(chunk);
+            } else if chunks[chunk].delta().is_none() {
+                bchunk_data_table_insert(chunks, &mut table_data, chunk);
+            }
+        }
+    }
+
+    // each source chunk is only looked up once.
+    let mut chunk_map: Map<BChunkId, BChunkId> = Map::new();
+
+    let mut chunk_list = bchunk_list_new(chunk_list_src.total_size);
+    let chunk_refs_src: &[BChunkId] = &chunk_list_src.chunk_refs[..];
+    for (cref, &chunk_src) in chunk_refs_src.iter().enumerate() {
+        if let Some(&chunk) = chunk_map.get(&chunk_src) {
+            bchunk_list_append_only(chunks, &mut chunk_list, chunk);
+            continue;
+        }
+
+        let data_src = bchunk_data(chunks_src, &chunks_src[chunk_src]);
+        let mut key: HashKey = HASH_TABLE_KEY_UNSET;
+        let mut chunk_found: Option<BChunkId> = None;
+        if info.accum_read_ahead_bytes <= data_src.len() {
+            key = key_from_chunk_ref_import(info, chunks_src, chunk_refs_src, cref, &mut hash_store[..]);
+            if let Some(chunk_array) = table_key.get(&key) {
+                for &chunk in chunk_array {
+                    if  chunks[chunk].data_len() == data_src.len() &&
+                        bchunk_data(chunks, &chunks[chunk])[..] == data_src[..]
+                    {
+                        chunk_found = Some(chunk);
+                        break;
+                    }
+                }
+            }
+        } else {
+            chunk_found = bchunk_data_table_lookup(chunks, &table_data, &data_src[..]);
+        }
+
+        let chunk = match chunk_found {
+            Some(chunk) => chunk,
+            None => {
+                // share the data with the source store, delta chunks are expanded
+                // since their base isn't in this store.
+                let chunk = {
+                    if chunks_src[chunk_src].delta().is_none() {
+                        bchunk_new_shared(chunks, chunks_src[chunk_src].array().clone())
+                    } else {
+                        bchunk_new(chunks, data_src.into_owned())
+                    }
+                };
+                chunks[chunk].key = key;
+                chunk
+            },
+        };
+        chunk_map.insert(chunk_src, chunk);
+        bchunk_list_append_only(chunks, &mut chunk_list, chunk);
+    }
+
+    if let Some(chunk_list_reference) = chunk_list_reference {
+        if chunk_list.chunk_refs == chunk_list_reference.chunk_refs {
+            // all chunks are from the reference, so there is nothing to free.
+            for &chunk in &chunk_list.chunk_refs {
+                chunks[chunk].users -= 1;
+            }
+            return None;
+        }
+    }
+
+    debug_assert_chunklist_size!(chunks, &chunk_list, chunk_list.total_size);
+    Some(chunk_list)
+}
+
+// []( } )
+
 /// # Main Array Storage API
 /// []( { )
 
@@ -1409,40 +2690,212 @@ impl BArrayStore {
                 // https://en.wikipedia.org/wiki/Triangular_number (+ 1)
                 accum_read_ahead_len: accum_read_ahead_len,
                 accum_read_ahead_bytes: accum_read_ahead_bytes,
+
+                delta_strides_max: 0,
+                delta_numeric: None,
+
+                threads: 1,
+
+                parent_tracking: false,
+
+                #[cfg(feature = "std")]
+                chunk_pool: None,
             },
             memory: BArrayMemory {
-                state: MemPool::new(),
-                chunk_list: MemPool::new(),
-                chunk_ref: MemPool::new(),
-                // allow iteration to simplify freeing, otherwise its not needed
-                // (we could loop over all states as an alternative).
-                chunk: MemPool::new(),
+                chunk_list: Arena::new(),
+                chunk: Arena::new(),
             },
-            states: ListBase::new(),
+            scratch: BArrayScratch::new(),
+            states: Arena::new(),
+            state_first: None,
+            state_last: None,
+            states_pending: VecDeque::new(),
         }
     }
 
-    fn free_data(&mut self) {
-        // free chunk data
-        for mut chunk in self.memory.chunk.iter_mut() {
-            unsafe { ::std::ptr::drop_in_place(&mut chunk.data); }
+    /// Store new chunks which differ from the chunk at the same position in the reference state
+    /// by `strides_max` strides (or less) as a delta, instead of storing a copy.
+    ///
+    /// This is useful when small changes are spread over many chunks,
+    /// at the cost of some overhead adding & accessing states.
+    ///
+    /// Only used when the reference state is the same size, zero disables (the default).
+    pub fn delta_strides_max_set(
+        &mut self,
+        strides_max: usize,
+    ) {
+        self.info.delta_strides_max = strides_max;
+    }
+
+    /// Store new chunks as the difference of each element from the chunk at the same position
+    /// in the reference state, when these differences compress well
+    /// (values all offset by the same amount for example).
+    ///
+    /// This is lossless, floating point differences are only used
+    /// when they give back the exact same values.
+    ///
+    /// * `numeric_type` The type of each element, the stride must be a multiple of its size.
+    ///   None disables (the default).
+    ///
+    /// Only used when the reference state is the same size,
+    /// and the chunk isn't stored as a delta of changed strides (see `delta_strides_max_set`).
+    pub fn delta_numeric_set(
+        &mut self,
+        numeric_type: Option<NumericType>,
+    ) {
+        if let Some(numeric_type) = numeric_type {
+            assert_eq!(0, self.info.chunk_stride % numeric_type.size());
         }
+        self.info.delta_numeric = numeric_type;
+    }
+
+    /// Use up to `threads` threads when hashing the data of states being added
+    /// and comparing it with the chunks of the reference state, useful for large arrays.
+    ///
+    /// The stored chunks are identical to the single threaded result.
+    ///
+    /// * `threads` The maximum number of threads,
+    ///   1 disables (the default), 0 uses the number of available cores.
+    ///
+    /// Only available with the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn threads_set(
+        &mut self,
+        threads: usize,
+    ) {
+        self.info.threads = {
+            if threads == 0 {
+                ::std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+            } else {
+                threads
+            }
+        };
+    }
+
+    /// Record the reference of each state added as its parent,
+    /// see `BArrayStore.state_parent` & `BArrayStore.state_children`.
+    ///
+    /// When a state with children is removed,
+    /// its children are re-parented to its own parent (when it has one).
+    ///
+    /// Only applies to states added after this is enabled, disabled by default.
+    pub fn parent_tracking_set(
+        &mut self,
+        parent_tracking: bool,
+    ) {
+        self.info.parent_tracking = parent_tracking;
     }
 
     /// Clear all contents, allowing reuse of `self`.
     pub fn clear(
         &mut self,
     ) {
-        self.free_data();
-
         self.states.clear();
+        self.state_first = None;
+        self.state_last = None;
+        self.states_pending.clear();
 
         self.memory.chunk_list.clear();
-        self.memory.chunk_ref.clear();
         self.memory.chunk.clear();
     }
 
-    /// # BArrayStore Statistics
+    /// Return a new store with the same states & settings,
+    /// sharing the data of all chunks with `self` (nothing is copied besides book-keeping).
+    ///
+    /// Both stores can be changed independently,
+    /// and all `StateId`'s from `self` are valid for the new store.
+    /// Shared data is reference counted atomically,
+    /// so the new store can be moved to another thread (to save history in the background for example).
+    ///
+    /// Note that `BArrayStore.calc_size_compacted_get` includes shared data in both stores.
+    pub fn fork(
+        &self,
+    ) -> BArrayStore {
+        BArrayStore {
+            info: self.info.clone(),
+            memory: self.memory.clone(),
+            scratch: BArrayScratch::new(),
+            states: self.states.clone(),
+            state_first: self.state_first,
+            state_last: self.state_last,
+            states_pending: self.states_pending.clone(),
+        }
+    }
+
+    /// Free temporary memory kept for reuse when adding states.
+    ///
+    /// Useful after adding large arrays, since the memory used depends on the array size.
+    pub fn shrink_scratch(
+        &mut self,
+    ) {
+        self.scratch = BArrayScratch::new();
+    }
+
+    // # BArrayStore Compaction
+    // []( { )
+
+    fn compact_table_create(
+        &mut self,
+    ) -> BChunkDataTable {
+        let mut table: BChunkDataTable = Map::new();
+        for (chunk_id, chunk) in self.memory.chunk.iter() {
+            if chunk.delta().is_none() {
+                bchunk_data_table_insert(&self.memory.chunk, &mut table, chunk_id);
+            }
+        }
+        table
+    }
+
+    /// Re-chunk data that is only used by a single state,
+    /// so its chunk boundaries align to the chunk size again.
+    /// Any re-chunked data matching existing chunks is de-duplicated.
+    ///
+    /// Adding and removing states causes chunks to be merged and split,
+    /// after many edits these may no longer align,
+    /// causing new states to de-duplicate less effectively.
+    ///
+    /// This is relatively expensive, intended to be run occasionally (when idle for example).
+    /// The contents of states is never changed.
+    pub fn compact(
+        &mut self,
+    ) {
+        let mut table = self.compact_table_create();
+
+        let mut chunk_list_array: Vec<BChunkListId> = Vec::new();
+        {
+            let mut chunk_list_set: Set<BChunkListId> = Set::new();
+            for (_, state) in self.states.iter() {
+                if chunk_list_set.insert(state.chunk_list) {
+                    chunk_list_array.push(state.chunk_list);
+                }
+            }
+        }
+
+        for chunk_list in chunk_list_array {
+            bchunk_list_compact(&self.info, &mut self.memory, chunk_list, &mut table);
+        }
+        #[cfg(feature = "std")]
+        self.chunk_pool_ensure(None);
+    }
+
+    /// Compact a single state, see `BArrayStore.compact`.
+    ///
+    /// Note that any other states sharing the same chunk list are compacted too.
+    pub fn compact_state(
+        &mut self,
+        state: StateId,
+    ) {
+        let chunk_list = self.states[state.0].chunk_list;
+
+        let mut table = self.compact_table_create();
+        bchunk_list_compact(&self.info, &mut self.memory, chunk_list, &mut table);
+        #[cfg(feature = "std")]
+        self.chunk_pool_ensure(Some(chunk_list));
+    }
+
+    // []( } )
+
+    /// # BArrayStore Statistics
     /// []( { )
 
     /// return the total amount of memory that would be used by getting the arrays for all states.
@@ -1450,21 +2903,62 @@ impl BArrayStore {
         &self,
     ) -> usize {
         let mut size_accum: usize = 0;
-        for state in self.states.iter() {
-            size_accum += state.chunk_list.total_size;
+        for (_, state) in self.states.iter() {
+            size_accum += self.memory.chunk_list[state.chunk_list].total_size;
         }
         size_accum
     }
 
     /// return the amount of memory used by all `BChunk.data`
-    /// (duplicate chunks are only counted once).
+    /// (duplicate chunks are only counted once,
+    /// delta chunks count the memory used to store their changes).
     pub fn calc_size_compacted_get(
         &self,
     ) -> usize {
         let mut size_total: usize = 0;
-        for chunk in self.memory.chunk.iter() {
+        for (_, chunk) in self.memory.chunk.iter() {
             debug_assert!(chunk.users > 0);
-            size_total += chunk.data.len();
+            size_total += bchunk_data_size_stored(chunk);
+        }
+        size_total
+    }
+
+    /// return the amount of memory only used by `state`
+    /// (the amount `BArrayStore.calc_size_compacted_get` is reduced by removing it).
+    pub fn calc_size_unique_get(
+        &self,
+        state: StateId,
+    ) -> usize {
+        let chunks = &self.memory.chunk;
+        let chunk_list = &self.memory.chunk_list[self.states[state.0].chunk_list];
+        // the list is shared (by another state, or as a reference for pending de-duplication).
+        if chunk_list.users > 1 {
+            return 0;
+        }
+
+        // users of each chunk which are removed along with the state.
+        let mut chunk_users: Map<BChunkId, isize> = Map::new();
+        for &chunk in &chunk_list.chunk_refs {
+            *chunk_users.entry(chunk).or_insert(0) += 1;
+        }
+        // removing a delta chunk removes a user from its base.
+        let mut base_users: Vec<BChunkId> = Vec::new();
+        for (&chunk, &users) in &chunk_users {
+            if chunks[chunk].users == users {
+                if let Some(delta) = chunks[chunk].delta() {
+                    base_users.push(delta.base);
+                }
+            }
+        }
+        for base in base_users {
+            *chunk_users.entry(base).or_insert(0) += 1;
+        }
+
+        let mut size_total: usize = 0;
+        for (&chunk, &users) in &chunk_users {
+            if chunks[chunk].users == users {
+                size_total += bchunk_data_size_stored(&chunks[chunk]);
+            }
         }
         size_total
     }
@@ -1488,57 +2982,273 @@ impl BArrayStore {
     pub fn state_add(
         &mut self,
         data: &[u8],
-        state_reference: Option<*const BArrayState>,
-    ) -> *mut BArrayState {
+        state_reference: Option<StateId>,
+    ) -> StateId {
         // ensure we're aligned to the stride
         debug_assert_eq!(0, data.len() % self.info.chunk_stride);
 
         if USE_PARANOID_CHECKS {
             if let Some(state_reference) = state_reference {
-                assert!(self.states.index_at(PtrConst(state_reference)).is_some());
+                assert!(self.contains(state_reference));
             }
         }
 
-        let mut chunk_list = {
+        let chunk_list: BChunkListId = {
             if let Some(state_reference) = state_reference {
-                bchunk_list_from_data_merge(
-                    &self.info, &mut self.memory,
-                    data, data.len(),
-                    // re-use reference chunks
-                    PtrConst(state_reference).chunk_list,
-                )
+                let chunk_list_reference = self.states[state_reference.0].chunk_list;
+                // re-use reference chunks
+                self.chunk_list_from_data_merge(data, chunk_list_reference)
             } else {
-                let chunk_list = bchunk_list_new(&mut self.memory, data.len());
+                let mut chunk_list = bchunk_list_new(data.len());
                 bchunk_list_fill_from_array(
-                    &self.info, &mut self.memory,
-                    chunk_list,
+                    &self.info, &mut self.memory.chunk,
+                    &mut chunk_list,
                     data,
                 );
-                chunk_list
+                self.memory.chunk_list.alloc(chunk_list)
             }
         };
 
-        chunk_list.users += 1;
-
-        let state = PtrMut(self.memory.state.alloc_elem_from(
-            BArrayState {
-                next: null_mut(),
-                prev: null_mut(),
-                chunk_list: chunk_list,
-            })
-        );
+        self.memory.chunk_list[chunk_list].users += 1;
+        #[cfg(feature = "std")]
+        self.chunk_pool_ensure(Some(chunk_list));
 
-        self.states.push_back(state);
+        let state = self.state_alloc(chunk_list);
+        if self.info.parent_tracking {
+            if let Some(state_reference) = state_reference {
+                self.state_parent_link(state, state_reference);
+            }
+        }
 
         if USE_PARANOID_CHECKS {
-            let data_test = BArrayStore::state_data_get_alloc(state.as_ptr());
+            let data_test = self.state_data_get_alloc(state);
             assert_eq!(data_test.len(), data.len());
             // we don't want to print the
             assert!(data_test == data);
             // data_test gets freed
         }
 
-        return state.as_ptr();
+        state
+    }
+
+    /// Add a state using `chunk_list` (which the caller has added a user to),
+    /// after all other states.
+    fn state_alloc(
+        &mut self,
+        chunk_list: BChunkListId,
+    ) -> StateId {
+        let state = StateId(self.states.alloc(BArrayState {
+            chunk_list,
+            metadata: Vec::new(),
+            state_prev: self.state_last,
+            state_next: None,
+            state_parent: None,
+            state_children: Vec::new(),
+            pending: false,
+        }));
+        if let Some(state_last) = self.state_last {
+            self.states[state_last.0].state_next = Some(state);
+        } else {
+            self.state_first = Some(state);
+        }
+        self.state_last = Some(state);
+        state
+    }
+
+    /// Set the parent of `state` (which must not have a parent).
+    fn state_parent_link(
+        &mut self,
+        state: StateId,
+        state_parent: StateId,
+    ) {
+        debug_assert!(self.states[state.0].state_parent.is_none());
+        self.states[state.0].state_parent = Some(state_parent);
+        self.states[state_parent.0].state_children.push(state);
+    }
+
+    /// Return a chunk list for `data` (without adding a user),
+    /// either a new list or `chunk_list_reference` when the contents match.
+    fn chunk_list_from_data_merge(
+        &mut self,
+        data: &[u8],
+        chunk_list_reference: BChunkListId,
+    ) -> BChunkListId {
+        let chunk_list = bchunk_list_from_data_merge(
+            &self.info, &mut self.memory.chunk, &mut self.scratch,
+            data, data.len(),
+            &self.memory.chunk_list[chunk_list_reference],
+        );
+        if let Some(chunk_list) = chunk_list {
+            self.memory.chunk_list.alloc(chunk_list)
+        } else {
+            chunk_list_reference
+        }
+    }
+
+    /// Add a copy of `state_src` from another store `src`,
+    /// without expanding the array when the stride & chunk count of both stores match.
+    ///
+    /// The chunks of `state_src` are used as-is,
+    /// chunks matching the contents of chunks in `state_reference` (from `self`) are re-used,
+    /// data of other chunks is shared with `src` (see `BArrayStore.fork`).
+    /// Otherwise the state is expanded and added using `BArrayStore.state_add`.
+    ///
+    /// The state's metadata is copied too,
+    /// to move the state, remove `state_src` from `src` afterwards.
+    ///
+    /// Returns the new state (only valid for `self`).
+    pub fn state_import(
+        &mut self,
+        src: &BArrayStore,
+        state_src: StateId,
+        state_reference: Option<StateId>,
+    ) -> StateId {
+        assert_eq!(self.info.chunk_stride, src.info.chunk_stride);
+
+        if USE_PARANOID_CHECKS {
+            assert!(src.contains(state_src));
+            if let Some(state_reference) = state_reference {
+                assert!(self.contains(state_reference));
+            }
+        }
+
+        if self.info.chunk_byte_size != src.info.chunk_byte_size {
+            let state = self.state_add(&src.state_data_get_alloc(state_src)[..], state_reference);
+            self.states[state.0].metadata = src.states[state_src.0].metadata.clone();
+            return state;
+        }
+
+        let chunk_list: BChunkListId = {
+            let chunk_list_src = &src.memory.chunk_list[src.states[state_src.0].chunk_list];
+            let chunk_list_reference: Option<BChunkListId> =
+                state_reference.map(|state_reference| self.states[state_reference.0].chunk_list);
+            let memory = &mut self.memory;
+            let chunk_list = bchunk_list_from_chunk_list_import(
+                &self.info, &mut memory.chunk, &mut self.scratch,
+                &src.memory.chunk, chunk_list_src,
+                match chunk_list_reference {
+                    Some(chunk_list_reference) => Some(&memory.chunk_list[chunk_list_reference]),
+                    None => None,
+                },
+            );
+            if let Some(chunk_list) = chunk_list {
+                memory.chunk_list.alloc(chunk_list)
+            } else {
+                chunk_list_reference.unwrap()
+            }
+        };
+
+        self.memory.chunk_list[chunk_list].users += 1;
+        #[cfg(feature = "std")]
+        self.chunk_pool_ensure(Some(chunk_list));
+
+        let state = self.state_alloc(chunk_list);
+        self.states[state.0].metadata = src.states[state_src.0].metadata.clone();
+        if self.info.parent_tracking {
+            if let Some(state_reference) = state_reference {
+                self.state_parent_link(state, state_reference);
+            }
+        }
+
+        if USE_PARANOID_CHECKS {
+            let data_test = self.state_data_get_alloc(state);
+            assert!(data_test == src.state_data_get_alloc(state_src));
+        }
+
+        state
+    }
+
+    /// Add a new state without de-duplicating against `state_reference`,
+    /// this is fast but stores a full copy of `data` until `dedup_pending` is called.
+    ///
+    /// Arguments and return value match `BArrayStore.state_add`,
+    /// the contents of the state are the same either way.
+    ///
+    /// The reference state may be removed before de-duplicating,
+    /// its data is kept until then.
+    pub fn state_add_deferred(
+        &mut self,
+        data: &[u8],
+        state_reference: Option<StateId>,
+    ) -> StateId {
+        let state = self.state_add(data, None);
+        if let Some(state_reference) = state_reference {
+            if self.info.parent_tracking {
+                self.state_parent_link(state, state_reference);
+            }
+            let chunk_list_reference = self.states[state_reference.0].chunk_list;
+            self.memory.chunk_list[chunk_list_reference].users += 1;
+            self.states_pending.push_back(BArrayStatePending {
+                state,
+                chunk_list_reference,
+            });
+            self.states[state.0].pending = true;
+        }
+        state
+    }
+
+    /// De-duplicate states added with `state_add_deferred` against their reference,
+    /// oldest first.
+    ///
+    /// * `budget` The number of bytes of state data to process,
+    ///   once exceeded the remaining states are left for the next call.
+    ///   At least one state is processed (when any are pending).
+    ///
+    /// Returns the number of states still pending.
+    pub fn dedup_pending(
+        &mut self,
+        budget: usize,
+    ) -> usize {
+        // reused between calls (taken since `chunk_list_from_data_merge` uses the scratch too).
+        let mut data: Vec<u8> = ::core::mem::take(&mut self.scratch.pending_data);
+        let mut data_len_done: usize = 0;
+        while let Some(pending) = self.states_pending.pop_front() {
+            let state = pending.state;
+            self.states[state.0].pending = false;
+            data.clear();
+            data.resize(self.state_size_get(state), 0);
+            self.state_data_get(state, &mut data[..]);
+
+            let chunk_list = self.chunk_list_from_data_merge(&data, pending.chunk_list_reference);
+            self.memory.chunk_list[chunk_list].users += 1;
+            #[cfg(feature = "std")]
+            self.chunk_pool_ensure(Some(chunk_list));
+
+            // states referencing this one use the de-duplicated chunk list too,
+            // otherwise they would de-duplicate against the chunks we've just replaced.
+            let chunk_list_prev = self.states[state.0].chunk_list;
+            for pending_other in self.states_pending.iter_mut() {
+                if pending_other.chunk_list_reference == chunk_list_prev {
+                    self.memory.chunk_list[chunk_list_prev].users -= 1;
+                    pending_other.chunk_list_reference = chunk_list;
+                    self.memory.chunk_list[chunk_list].users += 1;
+                }
+            }
+
+            bchunk_list_decref(&mut self.memory, chunk_list_prev);
+            bchunk_list_decref(&mut self.memory, pending.chunk_list_reference);
+            self.states[state.0].chunk_list = chunk_list;
+
+            if USE_PARANOID_CHECKS {
+                let data_test = self.state_data_get_alloc(state);
+                assert!(data_test == data);
+            }
+
+            data_len_done += data.len();
+            if data_len_done >= budget {
+                break;
+            }
+        }
+        data.clear();
+        self.scratch.pending_data = data;
+        self.states_pending.len()
+    }
+
+    /// Return the number of states waiting to be de-duplicated, see `BArrayStore.dedup_pending`.
+    pub fn dedup_pending_len(
+        &self,
+    ) -> usize {
+        self.states_pending.len()
     }
 
     /// Remove a state and free any unused `BChunk` data.
@@ -1546,176 +3256,742 @@ impl BArrayStore {
     /// The states can be freed in any order.
     pub fn state_remove(
         &mut self,
-        state: *mut BArrayState,
+        state: StateId,
     ) {
-        let state = PtrMut(state);
+        if self.states[state.0].pending {
+            let index = self.states_pending.iter().position(|p| p.state == state).unwrap();
+            let pending = self.states_pending.remove(index).unwrap();
+            bchunk_list_decref(&mut self.memory, pending.chunk_list_reference);
+        }
+
         if USE_PARANOID_CHECKS {
-            assert!(self.states.index_at(state).is_some());
+            assert!(self.contains(state));
+        }
+
+        let state_data = self.states.free(state.0);
+
+        // re-parent children.
+        if let Some(state_parent) = state_data.state_parent {
+            let state_parent_children = &mut self.states[state_parent.0].state_children;
+            let index = state_parent_children.iter().position(|&s| s == state).unwrap();
+            state_parent_children.remove(index);
+            state_parent_children.extend_from_slice(&state_data.state_children);
+        }
+        for &state_child in &state_data.state_children {
+            self.states[state_child.0].state_parent = state_data.state_parent;
+        }
+
+        match state_data.state_prev {
+            Some(state_prev) => { self.states[state_prev.0].state_next = state_data.state_next; },
+            None => { self.state_first = state_data.state_next; },
         }
+        match state_data.state_next {
+            Some(state_next) => { self.states[state_next.0].state_prev = state_data.state_prev; },
+            None => { self.state_last = state_data.state_prev; },
+        }
+        bchunk_list_decref(&mut self.memory, state_data.chunk_list);
+    }
+
+    /// Return true when `state` is in the store (it's been added and not removed).
+    pub fn contains(
+        &self,
+        state: StateId,
+    ) -> bool {
+        self.states.contains(state.0)
+    }
+
+    /// Return the number of states.
+    pub fn state_count(
+        &self,
+    ) -> usize {
+        self.states.len()
+    }
 
-        bchunk_list_decref(&mut self.memory, state.chunk_list);
-        self.states.remove(state);
+    /// Return the state `state` was added with as a reference
+    /// (or its closest ancestor when the reference has been removed),
+    /// only set when parent tracking is enabled, see `BArrayStore.parent_tracking_set`.
+    pub fn state_parent(
+        &self,
+        state: StateId,
+    ) -> Option<StateId> {
+        self.states[state.0].state_parent
+    }
 
-        self.memory.state.free_elem(state.as_ptr());
+    /// Return all states which have `state` as a parent, see `BArrayStore.state_parent`.
+    ///
+    /// In the order they were added,
+    /// children re-parented by removing a state are added after existing children.
+    pub fn state_children(
+        &self,
+        state: StateId,
+    ) -> &[StateId] {
+        &self.states[state.0].state_children[..]
+    }
+
+    /// Iterate over all states in the order they were added.
+    pub fn states<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = StateId> + 'a {
+        let mut state_iter = self.state_first;
+        ::core::iter::from_fn(move || {
+            let state = state_iter?;
+            state_iter = self.states[state.0].state_next;
+            Some(state)
+        })
     }
 
     /// return the expanded size of the array,
     /// use this to know how much memory to allocate `BArrayStore.state_data_get` 's argument.
     pub fn state_size_get(
-        state: *const BArrayState,
+        &self,
+        state: StateId,
     ) -> usize {
-        return unsafe { (*state).chunk_list.total_size };
+        self.memory.chunk_list[self.states[state.0].chunk_list].total_size
     }
 
     /// Fill in existing allocated memory with the contents of `state`.
     pub fn state_data_get(
-        state: *const BArrayState,
+        &self,
+        state: StateId,
         data: &mut [u8],
     ) {
-        let state = PtrConst(state);
+        let chunks = &self.memory.chunk;
+        let chunk_list = &self.memory.chunk_list[self.states[state.0].chunk_list];
         if USE_PARANOID_CHECKS {
             let mut data_test_len: usize = 0;
-            for cref in state.chunk_list.chunk_refs.iter() {
-                data_test_len += cref.link.data.len();
+            for &chunk in &chunk_list.chunk_refs {
+                data_test_len += chunks[chunk].data_len();
             }
-            assert_eq!(data_test_len, state.chunk_list.total_size);
+            assert_eq!(data_test_len, chunk_list.total_size);
             assert_eq!(data_test_len, data.len());
         }
 
-        debug_assert_eq!(state.chunk_list.total_size, data.len());
+        debug_assert_eq!(chunk_list.total_size, data.len());
         let mut data_step = 0;
-        for cref in state.chunk_list.chunk_refs.iter() {
-            let data_step_next = data_step + cref.link.data.len();
-            debug_assert!(cref.link.users > 0);
-            {
-                let aaa = &cref.link.data[..];
-                data[data_step..data_step_next].clone_from_slice(aaa);
+        for &chunk in &chunk_list.chunk_refs {
+            let chunk: &BChunk = &chunks[chunk];
+            let data_step_next = data_step + chunk.data_len();
+            debug_assert!(chunk.users > 0);
+            bchunk_data_copy_to(chunks, chunk, &mut data[data_step..data_step_next]);
+            data_step = data_step_next;
+        }
+    }
+
+    /// Fill in existing allocated memory with part of the contents of `state`,
+    /// starting at `offset` (in bytes), reading `data.len()` bytes.
+    ///
+    /// Only the chunks overlapping the range are accessed,
+    /// useful to access a small part of a large array.
+    pub fn state_data_get_range(
+        &self,
+        state: StateId,
+        offset: usize,
+        data: &mut [u8],
+    ) {
+        let chunks = &self.memory.chunk;
+        let chunk_list = &self.memory.chunk_list[self.states[state.0].chunk_list];
+        assert!(offset + data.len() <= chunk_list.total_size);
+        if data.is_empty() {
+            return;
+        }
+
+        let mut cref = bchunk_list_find_offset(chunk_list, offset);
+        let mut data_step = 0;
+        while data_step != data.len() {
+            let chunk: &BChunk = &chunks[chunk_list.chunk_refs[cref]];
+            let chunk_offset = chunk_list.chunk_offsets[cref];
+            // only the first chunk may start before `offset`.
+            let chunk_start = (offset + data_step) - chunk_offset;
+            let chunk_end = min(chunk.data_len(), (offset + data.len()) - chunk_offset);
+            let data_step_next = data_step + (chunk_end - chunk_start);
+            if chunk_start == 0 && chunk_end == chunk.data_len() {
+                bchunk_data_copy_to(chunks, chunk, &mut data[data_step..data_step_next]);
+            } else {
+                data[data_step..data_step_next].copy_from_slice(
+                    &bchunk_data(chunks, chunk)[chunk_start..chunk_end]);
             }
             data_step = data_step_next;
+            cref += 1;
         }
     }
 
     /// Allocate an array for `state` and return it.
     pub fn state_data_get_alloc(
-        state: *const BArrayState,
+        &self,
+        state: StateId,
     ) -> Vec<u8> {
-        let state = PtrConst(state);
-        let mut data: Vec<u8> = Vec::with_capacity(state.chunk_list.total_size);
-        unsafe { data.set_len(state.chunk_list.total_size) };
-        BArrayStore::state_data_get(state.as_ptr(), &mut data[..]);
+        let mut data: Vec<u8> = vec![0; self.state_size_get(state)];
+        self.state_data_get(state, &mut data[..]);
         return data;
     }
 
-    pub fn is_valid(
+    /// Attach caller defined data to `state` (a label or time-stamp for example),
+    /// replacing any existing data.
+    ///
+    /// Metadata is removed along with the state & included when serializing the store.
+    pub fn state_metadata_set(
+        &mut self,
+        state: StateId,
+        metadata: Vec<u8>,
+    ) {
+        self.states[state.0].metadata = metadata;
+    }
+
+    /// Return the data set by `BArrayStore.state_metadata_set` (empty when unset).
+    pub fn state_metadata_get(
         &self,
-    ) -> bool {
+        state: StateId,
+    ) -> &[u8] {
+        &self.states[state.0].metadata[..]
+    }
+
+    /// Iterate over all states and their metadata (in the order they were added).
+    pub fn state_metadata_iter<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = (StateId, &'a [u8])> + 'a {
+        self.states().map(move |state| (state, &self.states[state.0].metadata[..]))
+    }
+
+    /// Check the internal state of the store is consistent,
+    /// returning all problems found.
+    ///
+    /// This is slow, only intended for testing & debugging.
+    pub fn validate(
+        &self,
+    ) -> Result<(), Vec<ValidationError>> {
+        let mut errors: Vec<ValidationError> = Vec::new();
+        let chunks: &Arena<BChunk> = &self.memory.chunk;
 
         // Check Length
         // ------------
 
-        for state in self.states.iter() {
-            let chunk_list: PtrMut<BChunkList> = state.chunk_list;
-            if bchunk_list_size(chunk_list) != chunk_list.total_size {
-                return false;
+        for (state_id, state) in self.states.iter() {
+            let chunk_list: &BChunkList = &self.memory.chunk_list[state.chunk_list];
+            let chunks_size = bchunk_list_size(chunks, chunk_list);
+            if chunks_size != chunk_list.total_size {
+                errors.push(ValidationError::SizeMismatch {
+                    state: StateId(state_id),
+                    total_size: chunk_list.total_size,
+                    chunks_size,
+                });
             }
 
-            if chunk_list.chunk_refs.len_calc() != chunk_list.chunk_refs_len {
-                return false;
+            // only report the first offset that doesn't match, since the following offsets won't.
+            {
+                let mut offset_calc: usize = 0;
+                for (chunk_index, &chunk) in chunk_list.chunk_refs.iter().enumerate() {
+                    let offset = chunk_list.chunk_offsets.get(chunk_index).cloned();
+                    if offset != Some(offset_calc) {
+                        errors.push(ValidationError::ChunkOffsetMismatch {
+                            state: StateId(state_id),
+                            chunk_index,
+                            offset,
+                            offset_calc,
+                        });
+                        break;
+                    }
+                    offset_calc += chunks[chunk].data_len();
+                }
+                if chunk_list.chunk_offsets.len() > chunk_list.chunk_refs.len() {
+                    errors.push(ValidationError::ChunkOffsetMismatch {
+                        state: StateId(state_id),
+                        chunk_index: chunk_list.chunk_refs.len(),
+                        offset: Some(chunk_list.chunk_offsets[chunk_list.chunk_refs.len()]),
+                        offset_calc,
+                    });
+                }
             }
 
             if USE_MERGE_CHUNKS {
                 // ensure we merge all chunks that could be merged
                 if chunk_list.total_size > self.info.chunk_byte_size_min {
-                    for cref in chunk_list.chunk_refs.iter() {
-                        if cref.link.data.len() < self.info.chunk_byte_size_min {
-                            return false;
+                    for (chunk_index, &chunk) in chunk_list.chunk_refs.iter().enumerate() {
+                        if chunks[chunk].data_len() < self.info.chunk_byte_size_min {
+                            errors.push(ValidationError::ChunkUnmerged {
+                                state: StateId(state_id),
+                                chunk_index,
+                                size: chunks[chunk].data_len(),
+                            });
                         }
                     }
                 }
             }
         }
 
+        // Check State Order
+        // -----------------
+
+        {
+            // step over the states in the order they were added, checking links in both directions.
+            let mut states_ordered: usize = 0;
+            let mut state_prev: Option<StateId> = None;
+            let mut state_iter: Option<StateId> = self.state_first;
+            while let Some(state) = state_iter {
+                match self.states.get(state.0) {
+                    Some(state_data) if state_data.state_prev == state_prev &&
+                                        states_ordered < self.states.len() => {
+                        states_ordered += 1;
+                        state_prev = Some(state);
+                        state_iter = state_data.state_next;
+                    },
+                    _ => { break; },
+                }
+            }
+            if states_ordered != self.states.len() || state_iter.is_some() || state_prev != self.state_last {
+                errors.push(ValidationError::StateOrderMismatch {
+                    states: self.states.len(),
+                    states_ordered,
+                });
+            }
+        }
+
+        // Check Parents
+        // -------------
+
+        for (state_id, state) in self.states.iter() {
+            let state_id = StateId(state_id);
+            let parent_valid = match state.state_parent {
+                Some(state_parent) => {
+                    self.states.get(state_parent.0).is_some_and(|state_parent_data| {
+                        state_parent_data.state_children.iter().filter(|&&s| s == state_id).count() == 1
+                    })
+                },
+                None => true,
+            };
+            let children_valid = state.state_children.iter().all(|state_child| {
+                self.states.get(state_child.0).is_some_and(|state_child_data| {
+                    state_child_data.state_parent == Some(state_id)
+                })
+            });
+            if !(parent_valid && children_valid) {
+                errors.push(ValidationError::StateParentMismatch {
+                    state: state_id,
+                });
+            }
+        }
+
         // Check User Count & Lost References
         // ----------------------------------
 
         {
-            use std::collections::HashMap;
-            use std::collections::hash_map::Entry::{
-                Occupied,
-                Vacant,
-            };
-            macro_rules! GHASH_PTR_ADD_USER {
-                ($gh:expr, $pt:expr) => {
-                    match $gh.entry($pt.as_ptr()) {
-                        Occupied(mut val) => {
-                            *val.get_mut() += 1;
-                        },
-                        Vacant(entry) => {
-                            entry.insert(1);
-                        }
-                    }
+            // Count users, storing the first state & chunk index found for each,
+            // so errors can reference them.
+            macro_rules! GHASH_ID_ADD_USER {
+                ($gh:expr, $id:expr, $state:expr, $chunk_index:expr) => {
+                    $gh.entry($id).or_insert((0, $state, $chunk_index)).0 += 1;
                 }
             }
 
-
             // count chunk_list's
-            let mut chunk_list_map: HashMap<*const BChunkList, isize> = HashMap::new();
-            let mut chunk_map: HashMap<*const BChunk, isize> = HashMap::new();
+            let mut chunk_list_map: Map<
+                BChunkListId, (isize, StateId, usize)> = Map::new();
+            let mut chunk_map: Map<
+                BChunkId, (isize, StateId, usize)> = Map::new();
 
-            let mut totrefs: usize = 0;
-            for state in self.states.iter() {
-                GHASH_PTR_ADD_USER!(chunk_list_map, state.chunk_list);
+            for (state_id, state) in self.states.iter() {
+                GHASH_ID_ADD_USER!(chunk_list_map, state.chunk_list, StateId(state_id), 0);
             }
-            for (chunk_list, users) in chunk_list_map.iter() {
-                if !(unsafe { (**chunk_list).users } == *users) {
-                    return false;
+            // pending states are users of their reference chunk list
+            for pending in self.states_pending.iter() {
+                GHASH_ID_ADD_USER!(
+                    chunk_list_map, pending.chunk_list_reference, pending.state, 0);
+            }
+            for (&chunk_list, &(users, state, _)) in chunk_list_map.iter() {
+                let chunk_list_users = self.memory.chunk_list[chunk_list].users;
+                if chunk_list_users != users {
+                    errors.push(ValidationError::ChunkListUsersMismatch {
+                        state,
+                        users: chunk_list_users,
+                        users_calc: users,
+                    });
                 }
             }
-            if !(self.memory.chunk_list.len() == chunk_list_map.len()) {
-                return false;
+            if self.memory.chunk_list.len() != chunk_list_map.len() {
+                errors.push(ValidationError::ChunkListLeak {
+                    allocated: self.memory.chunk_list.len(),
+                    used: chunk_list_map.len(),
+                });
             }
 
             // count chunk's
-            for (chunk_list, _users) in chunk_list_map.iter() {
-                for cref in unsafe { (**chunk_list) .chunk_refs.iter() } {
-                    GHASH_PTR_ADD_USER!(chunk_map, cref.link);
-                    totrefs += 1;
+            for (&chunk_list, &(_, state, _)) in chunk_list_map.iter() {
+                for (chunk_index, &chunk) in self.memory.chunk_list[chunk_list].chunk_refs.iter().enumerate() {
+                    GHASH_ID_ADD_USER!(chunk_map, chunk, state, chunk_index);
                 }
             }
-            if self.memory.chunk.len() != chunk_map.len() {
-                return false;
+            // each delta chunk is a user of its base
+            {
+                let mut chunk_delta_base_array: Vec<(BChunkId, StateId, usize)> = Vec::new();
+                for (&chunk, &(_, state, chunk_index)) in chunk_map.iter() {
+                    if let Some(delta) = chunks[chunk].delta() {
+                        chunk_delta_base_array.push((delta.base, state, chunk_index));
+                    }
+                }
+                for (chunk_base, state, chunk_index) in chunk_delta_base_array {
+                    GHASH_ID_ADD_USER!(chunk_map, chunk_base, state, chunk_index);
+                }
             }
-            if self.memory.chunk_ref.len() != totrefs {
-                return false;
+            if chunks.len() != chunk_map.len() {
+                errors.push(ValidationError::ChunkLeak {
+                    allocated: chunks.len(),
+                    used: chunk_map.len(),
+                });
             }
 
-            for (chunk, users) in chunk_map.iter() {
-                if !(unsafe { (**chunk).users } == *users) {
-                    return false;
+            for (&chunk, &(users, state, chunk_index)) in chunk_map.iter() {
+                let chunk_users = chunks[chunk].users;
+                if chunk_users != users {
+                    errors.push(ValidationError::ChunkUsersMismatch {
+                        state,
+                        chunk_index,
+                        users: chunk_users,
+                        users_calc: users,
+                    });
                 }
             }
         }
-        return true;
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
 
-}
+    /// Return true when the internal state of the store is consistent,
+    /// see `BArrayStore.validate` for details on any errors.
+    pub fn is_valid(
+        &self,
+    ) -> bool {
+        self.validate().is_ok()
+    }
 
-impl Drop for BArrayStore {
-    fn drop(&mut self) {
-        self.free_data();
+    /// Write the relationship between states, chunk lists & chunks,
+    /// use to investigate why some data doesn't de-duplicate well.
+    ///
+    /// * `w` Destination for the dump.
+    /// * `format` See `DumpFormat`.
+    ///
+    /// States are numbered in the order they were added (see `BArrayStore.states`),
+    /// chunks and chunk lists are numbered in the order they're first found
+    /// when stepping over the states, chunks used by more than one state are marked as shared.
+    /// Delta chunks reference the chunk they're based on.
+    #[cfg(feature = "std")]
+    pub fn debug_dump<W: ::std::io::Write>(
+        &self,
+        mut w: W,
+        format: DumpFormat,
+    ) -> ::std::io::Result<()> {
+        let chunks: &Arena<BChunk> = &self.memory.chunk;
+
+        let mut chunk_list_index: Map<BChunkListId, usize> = Map::new();
+        let mut chunk_list_array: Vec<&BChunkList> = Vec::new();
+
+        let mut chunk_index: Map<BChunkId, usize> = Map::new();
+        let mut chunk_array: Vec<&BChunk> = Vec::new();
+        // number of states using each chunk & the last state to count it
+        // (a chunk may be used multiple times in the same state).
+        let mut chunk_states: Vec<usize> = Vec::new();
+        let mut chunk_state_last: Vec<usize> = Vec::new();
+
+        let mut state_array: Vec<(usize, Vec<usize>)> = Vec::new();
+
+        for (state_index, state) in self.states().enumerate() {
+            let state = &self.states[state.0];
+            let chunk_list: &BChunkList = &self.memory.chunk_list[state.chunk_list];
+            let chunk_list_i = *chunk_list_index.entry(state.chunk_list).or_insert_with(|| {
+                chunk_list_array.push(chunk_list);
+                chunk_list_array.len() - 1
+            });
+
+            let mut state_chunks: Vec<usize> = Vec::with_capacity(chunk_list.chunk_refs.len());
+            for &chunk in &chunk_list.chunk_refs {
+                let chunk_i = *chunk_index.entry(chunk).or_insert_with(|| {
+                    chunk_array.push(&chunks[chunk]);
+                    chunk_states.push(0);
+                    chunk_state_last.push(usize::MAX);
+                    chunk_array.len() - 1
+                });
+                if chunk_state_last[chunk_i] != state_index {
+                    chunk_state_last[chunk_i] = state_index;
+                    chunk_states[chunk_i] += 1;
+                }
+                state_chunks.push(chunk_i);
+            }
+            state_array.push((chunk_list_i, state_chunks));
+        }
+
+        // delta chunk bases may not be used directly by any state.
+        let mut chunk_i = 0;
+        while chunk_i < chunk_array.len() {
+            let chunk: &BChunk = chunk_array[chunk_i];
+            if let Some(delta) = chunk.delta() {
+                chunk_index.entry(delta.base).or_insert_with(|| {
+                    chunk_array.push(&chunks[delta.base]);
+                    chunk_states.push(0);
+                    chunk_state_last.push(usize::MAX);
+                    chunk_array.len() - 1
+                });
+            }
+            chunk_i += 1;
+        }
+
+        macro_rules! key_as_string {
+            ($key:expr) => {
+                if $key == HASH_TABLE_KEY_UNSET {
+                    "-".to_string()
+                } else {
+                    format!("{:016x}", $key)
+                }
+            }
+        }
+
+        match format {
+            DumpFormat::Dot => {
+                writeln!(w, "digraph BArrayStore {{")?;
+                writeln!(w, "    rankdir=LR;")?;
+                writeln!(w, "    node [shape=box, fontname=\"monospace\"];")?;
+
+                for (state_index, &(chunk_list_i, _)) in state_array.iter().enumerate() {
+                    let chunk_list = chunk_list_array[chunk_list_i];
+                    writeln!(
+                        w, "    state{} [label=\"state {}\\nsize: {}\", shape=ellipse];",
+                        state_index, state_index, chunk_list.total_size,
+                    )?;
+                    writeln!(w, "    state{} -> list{};", state_index, chunk_list_i)?;
+                }
+
+                for (chunk_list_i, chunk_list) in chunk_list_array.iter().enumerate() {
+                    writeln!(
+                        w, "    list{} [label=\"chunk_list {}\\nusers: {}\\nchunks: {}\\nsize: {}\"];",
+                        chunk_list_i, chunk_list_i,
+                        chunk_list.users, chunk_list.chunk_refs.len(), chunk_list.total_size,
+                    )?;
+                    for (i, chunk) in chunk_list.chunk_refs.iter().enumerate() {
+                        writeln!(
+                            w, "    list{} -> chunk{} [label=\"{}\"];",
+                            chunk_list_i, chunk_index[chunk], i,
+                        )?;
+                    }
+                }
+
+                for (chunk_i, chunk) in chunk_array.iter().enumerate() {
+                    writeln!(
+                        w, "    chunk{} [label=\"chunk {}\\nsize: {}\\nusers: {}\\nkey: {}\"{}];",
+                        chunk_i, chunk_i,
+                        chunk.data_len(), chunk.users, key_as_string!(chunk.key),
+                        if chunk_states[chunk_i] > 1 {
+                            ", style=filled, fillcolor=\"#ffcc66\""
+                        } else {
+                            ""
+                        },
+                    )?;
+                    if let Some(delta) = chunk.delta() {
+                        writeln!(
+                            w, "    chunk{} -> chunk{} [style=dashed, label=\"{}: {}\"];",
+                            chunk_i, chunk_index[&delta.base],
+                            match delta.kind {
+                                BChunkDeltaKind::Patch { .. } => "patch",
+                                BChunkDeltaKind::Numeric { .. } => "numeric",
+                            },
+                            bchunk_data_size_stored(chunk),
+                        )?;
+                    }
+                }
+
+                writeln!(w, "}}")?;
+            },
+            DumpFormat::Table => {
+                writeln!(
+                    w, "states: {}, chunk_lists: {}, chunks: {} (shared: {})",
+                    state_array.len(), chunk_list_array.len(), chunk_array.len(),
+                    chunk_states.iter().filter(|n| **n > 1).count(),
+                )?;
+                writeln!(
+                    w, "size expanded: {}, size compacted: {}",
+                    self.calc_size_expanded_get(), self.calc_size_compacted_get(),
+                )?;
+
+                writeln!(w)?;
+                writeln!(w, "   state chunk_list         size  chunks")?;
+                for (state_index, &(chunk_list_i, ref state_chunks)) in state_array.iter().enumerate() {
+                    let chunk_list = chunk_list_array[chunk_list_i];
+                    let chunks_as_string: Vec<String> = state_chunks.iter().map(|chunk_i| {
+                        if chunk_states[*chunk_i] > 1 {
+                            format!("{}*", chunk_i)
+                        } else {
+                            format!("{}", chunk_i)
+                        }
+                    }).collect();
+                    writeln!(
+                        w, "{:>8} {:>10} {:>12}  {}",
+                        state_index, chunk_list_i, chunk_list.total_size, chunks_as_string.join(" "),
+                    )?;
+                }
+
+                writeln!(w)?;
+                writeln!(w, "chunk_list  users chunks")?;
+                for (chunk_list_i, chunk_list) in chunk_list_array.iter().enumerate() {
+                    writeln!(
+                        w, "{:>10} {:>6} {:>6}",
+                        chunk_list_i, chunk_list.users, chunk_list.chunk_refs.len(),
+                    )?;
+                }
+
+                writeln!(w)?;
+                writeln!(w, "   chunk       size  users states              key    delta  shared")?;
+                for (chunk_i, chunk) in chunk_array.iter().enumerate() {
+                    let delta_as_string = {
+                        if let Some(delta) = chunk.delta() {
+                            format!("{}", chunk_index[&delta.base])
+                        } else {
+                            "-".to_string()
+                        }
+                    };
+                    writeln!(
+                        w, "{:>8} {:>10} {:>6} {:>6} {:>16} {:>8}  {}",
+                        chunk_i, chunk.data_len(), chunk.users, chunk_states[chunk_i],
+                        key_as_string!(chunk.key), delta_as_string,
+                        if chunk_states[chunk_i] > 1 { "*" } else { "" },
+                    )?;
+                }
+            },
+        }
+
+        Ok(())
     }
+
 }
 
 /// # Debugging API (for testing).
 /// []( { )
 
+/// Output format for `BArrayStore.debug_dump`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Graphviz graph of states, chunk lists & chunks,
+    /// view with: `dot -Tsvg dump.dot -o dump.svg`.
+    Dot,
+    /// Plain text tables, one row for each state, chunk list & chunk.
+    Table,
+}
+
+/// A single problem found by `BArrayStore.validate`.
+///
+/// Chunks are identified by the first state found using them
+/// and their index in that state's chunk list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The sum of chunk sizes doesn't match the `total_size` of the state's chunk list.
+    SizeMismatch {
+        state: StateId,
+        total_size: usize,
+        chunks_size: usize,
+    },
+    /// The stored offset of a chunk doesn't match the size of the chunks before it,
+    /// `None` when the offset is missing.
+    ChunkOffsetMismatch {
+        state: StateId,
+        chunk_index: usize,
+        offset: Option<usize>,
+        offset_calc: usize,
+    },
+    /// A chunk is smaller than the minimum chunk size and should have been merged.
+    ChunkUnmerged {
+        state: StateId,
+        chunk_index: usize,
+        size: usize,
+    },
+    /// The chunk list's user count doesn't match the number of states using it.
+    ChunkListUsersMismatch {
+        state: StateId,
+        users: isize,
+        users_calc: isize,
+    },
+    /// The chunk's user count doesn't match the number of chunk lists using it.
+    ChunkUsersMismatch {
+        state: StateId,
+        chunk_index: usize,
+        users: isize,
+        users_calc: isize,
+    },
+    /// Stepping over states in the order they were added doesn't find all states
+    /// (`states_ordered` are found before an invalid link).
+    StateOrderMismatch {
+        states: usize,
+        states_ordered: usize,
+    },
+    /// The state's parent doesn't list it as a child (once),
+    /// or one of its children doesn't have it as a parent.
+    StateParentMismatch {
+        state: StateId,
+    },
+    /// Chunk lists are allocated which aren't used by any state.
+    ChunkListLeak {
+        allocated: usize,
+        used: usize,
+    },
+    /// Chunks are allocated which aren't used by any chunk list.
+    ChunkLeak {
+        allocated: usize,
+        used: usize,
+    },
+}
+
+impl ::core::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        match *self {
+            ValidationError::SizeMismatch { state, total_size, chunks_size } => {
+                write!(f, "state {:?}: total_size {} != chunks size {}",
+                       state, total_size, chunks_size)
+            },
+            ValidationError::ChunkOffsetMismatch { state, chunk_index, offset, offset_calc } => {
+                write!(f, "state {:?}: chunk {} offset {:?} != {}",
+                       state, chunk_index, offset, offset_calc)
+            },
+            ValidationError::ChunkUnmerged { state, chunk_index, size } => {
+                write!(f, "state {:?}: chunk {} is below the minimum size and wasn't merged ({} bytes)",
+                       state, chunk_index, size)
+            },
+            ValidationError::ChunkListUsersMismatch { state, users, users_calc } => {
+                write!(f, "state {:?}: chunk_list users {} != {} states",
+                       state, users, users_calc)
+            },
+            ValidationError::ChunkUsersMismatch { state, chunk_index, users, users_calc } => {
+                write!(f, "state {:?}: chunk {} users {} != {} chunk lists",
+                       state, chunk_index, users, users_calc)
+            },
+            ValidationError::StateOrderMismatch { states, states_ordered } => {
+                write!(f, "state order: {} states, {} found in order", states, states_ordered)
+            },
+            ValidationError::StateParentMismatch { state } => {
+                write!(f, "state {:?}: parent & children don't match", state)
+            },
+            ValidationError::ChunkListLeak { allocated, used } => {
+                write!(f, "chunk_list leak: {} allocated, {} used", allocated, used)
+            },
+            ValidationError::ChunkLeak { allocated, used } => {
+                write!(f, "chunk leak: {} allocated, {} used", allocated, used)
+            },
+        }
+    }
+}
+
 // only for test validation
-fn bchunk_list_size(chunk_list: PtrMut<BChunkList>) -> usize {
+fn bchunk_list_size(chunks: &Arena<BChunk>, chunk_list: &BChunkList) -> usize {
     let mut total_size: usize = 0;
-    for cref in chunk_list.chunk_refs.iter() {
-        total_size += cref.link.data.len();
+    for &chunk in &chunk_list.chunk_refs {
+        total_size += chunks[chunk].data_len();
     }
     return total_size;
 }
 
 // []( } )
+
+#[cfg(test)]
+#[path="tests_validate.rs"]
+mod test;
+
+#[cfg(test)]
+#[path="tests_scratch.rs"]
+mod test_scratch;
@@ -0,0 +1,489 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+//! Serialization using `serde` (requires the `serde` feature).
+//!
+//! Stores are written with each chunk & chunk list stored once (referenced by index),
+//! so chunks shared between states remain shared when read back.
+//! State ids are kept, so any `StateId` from the store is valid in the store read back.
+
+use alloc::borrow::Cow;
+use alloc::sync::Arc;
+
+#[cfg(not(any(feature = "std", test)))]
+use alloc::vec::Vec;
+
+use serde::{
+    de,
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+
+use map::Map;
+
+use arena::{
+    Arena,
+    ArenaId,
+    GENERATION_RETIRED,
+};
+use {
+    BArrayState,
+    BArrayStore,
+    BChunk,
+    BChunkData,
+    BChunkDelta,
+    BChunkDeltaKind,
+    BChunkId,
+    BChunkList,
+    BChunkListId,
+    BArrayStatePending,
+    NumericType,
+    StateId,
+    BCHUNK_HASH_TABLE_ACCUMULATE_STEPS,
+    BCHUNK_SIZE_MAX_MUL,
+    bchunk_list_append_only,
+    bchunk_list_new,
+    bchunk_new,
+};
+
+/// Bytes, written using `Serializer::serialize_bytes`.
+struct Bytes<'a>(Cow<'a, [u8]>);
+
+impl<'a> Serialize for Bytes<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0[..])
+    }
+}
+
+impl<'de, 'a> Deserialize<'de> for Bytes<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Bytes<'a>, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> de::Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                f.write_str("bytes")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                Ok(v.to_vec())
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                Ok(v)
+            }
+
+            // formats without a bytes type write a sequence.
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+                let mut v: Vec<u8> = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(b) = seq.next_element()? {
+                    v.push(b);
+                }
+                Ok(v)
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BytesVisitor).map(|v| Bytes(Cow::Owned(v)))
+    }
+}
+
+impl<T> Serialize for ArenaId<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.index() as u32, self.generation()).serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for ArenaId<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ArenaId<T>, D::Error> {
+        let (index, generation) = <(u32, u32)>::deserialize(deserializer)?;
+        Ok(ArenaId::from_parts(index, generation))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum BChunkSerde<'a> {
+    Data(Bytes<'a>),
+    Patch {
+        // index of the base chunk (never a delta).
+        base: usize,
+        data_len: usize,
+        runs: Cow<'a, [(usize, usize)]>,
+        data: Bytes<'a>,
+    },
+    Numeric {
+        // index of the base chunk (never a delta).
+        base: usize,
+        data_len: usize,
+        numeric_type: NumericType,
+        runs: Cow<'a, [usize]>,
+        data: Bytes<'a>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct BArrayStateSerde<'a> {
+    // index into `chunk_lists`.
+    chunk_list: usize,
+    metadata: Bytes<'a>,
+    // parents are set from their children.
+    children: Cow<'a, [StateId]>,
+}
+
+/// The data written for a `BArrayStore`.
+#[derive(Serialize, Deserialize)]
+struct BArrayStoreSerde<'a> {
+    stride: usize,
+    chunk_count: usize,
+    delta_strides_max: usize,
+    delta_numeric: Option<NumericType>,
+    parent_tracking: bool,
+
+    // all chunks, delta chunks are always after their base.
+    chunks: Vec<BChunkSerde<'a>>,
+    // all chunk lists, as indices into `chunks`.
+    chunk_lists: Vec<Vec<usize>>,
+    // the slots of `BArrayStore.states` as `(generation, state)` (`None` for unused slots).
+    states: Vec<(u32, Option<BArrayStateSerde<'a>>)>,
+    // all states in the order they were added, see `BArrayStore.states`.
+    states_order: Vec<StateId>,
+    // `BArrayStore.states_pending` as `(state, chunk_list_reference)`.
+    states_pending: Vec<(StateId, usize)>,
+}
+
+impl Serialize for BArrayStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let chunks: &Arena<BChunk> = &self.memory.chunk;
+
+        // write chunks which aren't deltas first, so bases are always read before their deltas.
+        let mut chunk_index: Map<BChunkId, usize> = Map::new();
+        let mut chunks_serde: Vec<BChunkSerde> = Vec::with_capacity(chunks.len());
+        for (chunk_id, chunk) in chunks.iter() {
+            if chunk.delta().is_none() {
+                chunk_index.insert(chunk_id, chunks_serde.len());
+                chunks_serde.push(BChunkSerde::Data(Bytes(Cow::Borrowed(&chunk.array()[..]))));
+            }
+        }
+        for (chunk_id, chunk) in chunks.iter() {
+            if let Some(delta) = chunk.delta() {
+                let base = chunk_index[&delta.base];
+                chunk_index.insert(chunk_id, chunks_serde.len());
+                chunks_serde.push(match delta.kind {
+                    BChunkDeltaKind::Patch { ref runs, ref data } => {
+                        BChunkSerde::Patch {
+                            base,
+                            data_len: delta.data_len,
+                            runs: Cow::Borrowed(&runs[..]),
+                            data: Bytes(Cow::Borrowed(&data[..])),
+                        }
+                    },
+                    BChunkDeltaKind::Numeric { numeric_type, ref runs, ref data } => {
+                        BChunkSerde::Numeric {
+                            base,
+                            data_len: delta.data_len,
+                            numeric_type,
+                            runs: Cow::Borrowed(&runs[..]),
+                            data: Bytes(Cow::Borrowed(&data[..])),
+                        }
+                    },
+                });
+            }
+        }
+
+        let mut chunk_list_index: Map<BChunkListId, usize> = Map::new();
+        let mut chunk_lists_serde: Vec<Vec<usize>> = Vec::with_capacity(self.memory.chunk_list.len());
+        for (chunk_list_id, chunk_list) in self.memory.chunk_list.iter() {
+            chunk_list_index.insert(chunk_list_id, chunk_lists_serde.len());
+            chunk_lists_serde.push(
+                chunk_list.chunk_refs.iter().map(|chunk| chunk_index[chunk]).collect());
+        }
+
+        BArrayStoreSerde {
+            stride: self.info.chunk_stride,
+            chunk_count: self.info.chunk_byte_size / self.info.chunk_stride,
+            delta_strides_max: self.info.delta_strides_max,
+            delta_numeric: self.info.delta_numeric,
+            parent_tracking: self.info.parent_tracking,
+            chunks: chunks_serde,
+            chunk_lists: chunk_lists_serde,
+            states: self.states.slots().map(|(generation, state)| {
+                (generation, state.map(|state| BArrayStateSerde {
+                    chunk_list: chunk_list_index[&state.chunk_list],
+                    metadata: Bytes(Cow::Borrowed(&state.metadata[..])),
+                    children: Cow::Borrowed(&state.state_children[..]),
+                }))
+            }).collect(),
+            states_order: self.states().collect(),
+            states_pending: self.states_pending.iter().map(|pending| {
+                (pending.state, chunk_list_index[&pending.chunk_list_reference])
+            }).collect(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BArrayStore {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<BArrayStore, D::Error> {
+        let store_serde = BArrayStoreSerde::deserialize(deserializer)?;
+        BArrayStore::from_serde(store_serde).map_err(de::Error::custom)
+    }
+}
+
+/// Return true when `BArrayStore::new` can be called with these arguments
+/// without the chunk sizes overflowing (needed for untrusted input).
+fn barray_store_size_valid(
+    stride: usize,
+    chunk_count: usize,
+) -> bool {
+    let accum_steps = BCHUNK_HASH_TABLE_ACCUMULATE_STEPS - 1;
+    let accum_read_ahead_len = ((accum_steps * (accum_steps + 1)) / 2) + 1;
+    let chunk_count_max = match chunk_count.checked_mul(BCHUNK_SIZE_MAX_MUL) {
+        Some(chunk_count_max) => chunk_count_max,
+        None => return false,
+    };
+    ::core::cmp::max(chunk_count_max, accum_read_ahead_len).checked_mul(stride).is_some()
+}
+
+impl BArrayStore {
+    /// Create a store from deserialized data,
+    /// checking it's valid so invalid input can't cause a panic later on.
+    fn from_serde(
+        store_serde: BArrayStoreSerde,
+    ) -> Result<BArrayStore, &'static str> {
+        if store_serde.stride == 0 || store_serde.chunk_count == 0 {
+            return Err("stride & chunk_count must be non-zero");
+        }
+        if !barray_store_size_valid(store_serde.stride, store_serde.chunk_count) {
+            return Err("stride & chunk_count are too large");
+        }
+        let stride = store_serde.stride;
+        let mut bs = BArrayStore::new(stride, store_serde.chunk_count);
+        bs.delta_strides_max_set(store_serde.delta_strides_max);
+        if let Some(numeric_type) = store_serde.delta_numeric {
+            if !stride.is_multiple_of(numeric_type.size()) {
+                return Err("stride isn't a multiple of the numeric type size");
+            }
+            bs.delta_numeric_set(Some(numeric_type));
+        }
+        bs.parent_tracking_set(store_serde.parent_tracking);
+
+        let mut chunk_ids: Vec<BChunkId> = Vec::with_capacity(store_serde.chunks.len());
+        for chunk_serde in store_serde.chunks {
+            let (base_index, data_len, kind) = match chunk_serde {
+                BChunkSerde::Data(data) => {
+                    if data.0.is_empty() || data.0.len() % stride != 0 {
+                        return Err("chunk size isn't a multiple of the stride");
+                    }
+                    chunk_ids.push(bchunk_new(&mut bs.memory.chunk, data.0.into_owned()));
+                    continue;
+                },
+                BChunkSerde::Patch { base, data_len, runs, data } => {
+                    // runs must be sorted, not overlap & stride aligned (as they're written).
+                    let mut data_expect_len: usize = 0;
+                    let mut run_end_prev: usize = 0;
+                    for &(offset, len) in runs.iter() {
+                        if len == 0 || offset % stride != 0 || len % stride != 0 {
+                            return Err("delta run isn't a multiple of the stride");
+                        }
+                        if offset < run_end_prev {
+                            return Err("delta runs overlap or aren't sorted");
+                        }
+                        match offset.checked_add(len) {
+                            Some(end) if end <= data_len => { run_end_prev = end; },
+                            _ => { return Err("delta run out of range"); },
+                        }
+                        data_expect_len += len;
+                    }
+                    if data_expect_len != data.0.len() {
+                        return Err("delta data doesn't match its runs");
+                    }
+                    (base, data_len, BChunkDeltaKind::Patch {
+                        runs: runs.into_owned(),
+                        data: data.0.into_owned(),
+                    })
+                },
+                BChunkSerde::Numeric { base, data_len, numeric_type, runs, data } => {
+                    if !stride.is_multiple_of(numeric_type.size()) {
+                        return Err("stride isn't a multiple of the numeric type size");
+                    }
+                    let runs_strides: usize = runs.iter().fold(0, |acc, n| acc.saturating_add(*n));
+                    if  runs_strides.checked_mul(stride) != Some(data_len) ||
+                        runs.len().checked_mul(stride) != Some(data.0.len())
+                    {
+                        return Err("delta data doesn't match its runs");
+                    }
+                    (base, data_len, BChunkDeltaKind::Numeric {
+                        numeric_type,
+                        runs: runs.into_owned(),
+                        data: data.0.into_owned(),
+                    })
+                },
+            };
+
+            let base: BChunkId = match chunk_ids.get(base_index) {
+                Some(&base) => base,
+                None => { return Err("delta base must be written before the delta"); },
+            };
+            if bs.memory.chunk[base].delta().is_some() {
+                return Err("delta base can't be a delta");
+            }
+            if bs.memory.chunk[base].array().len() != data_len {
+                return Err("delta size doesn't match its base");
+            }
+            bs.memory.chunk[base].users += 1;
+            chunk_ids.push(bs.memory.chunk.alloc(
+                BChunk {
+                    data: BChunkData::Delta(Arc::new(BChunkDelta {
+                        base,
+                        data_len,
+                        kind,
+                    })),
+                    users: 0,
+                    key: ::HASH_TABLE_KEY_UNSET,
+                    #[cfg(feature = "std")]
+                    pooled: false,
+                }
+            ));
+        }
+
+        let mut chunk_list_ids: Vec<BChunkListId> = Vec::with_capacity(store_serde.chunk_lists.len());
+        for chunk_list_serde in store_serde.chunk_lists {
+            let mut chunk_list: BChunkList = bchunk_list_new(0);
+            for chunk_index in chunk_list_serde {
+                let chunk: BChunkId = match chunk_ids.get(chunk_index) {
+                    Some(&chunk) => chunk,
+                    None => { return Err("chunk index out of range"); },
+                };
+                bchunk_list_append_only(&mut bs.memory.chunk, &mut chunk_list, chunk);
+            }
+            chunk_list.total_size = ::bchunk_list_offset_end(&bs.memory.chunk, &chunk_list);
+            chunk_list_ids.push(bs.memory.chunk_list.alloc(chunk_list));
+        }
+
+        let mut states_slots: Vec<(u32, Option<BArrayState>)> = Vec::with_capacity(store_serde.states.len());
+        for (generation, state_serde) in store_serde.states {
+            let state: Option<BArrayState> = match state_serde {
+                Some(state_serde) => {
+                    if generation == GENERATION_RETIRED {
+                        return Err("state generation out of range");
+                    }
+                    let chunk_list: BChunkListId = match chunk_list_ids.get(state_serde.chunk_list) {
+                        Some(&chunk_list) => chunk_list,
+                        None => { return Err("chunk list index out of range"); },
+                    };
+                    bs.memory.chunk_list[chunk_list].users += 1;
+                    Some(BArrayState {
+                        chunk_list,
+                        metadata: state_serde.metadata.0.into_owned(),
+                        // set from `states_order`.
+                        state_prev: None,
+                        state_next: None,
+                        // checked once all states exist.
+                        state_parent: None,
+                        state_children: state_serde.children.into_owned(),
+                        // set from `states_pending`.
+                        pending: false,
+                    })
+                },
+                None => None,
+            };
+            states_slots.push((generation, state));
+        }
+        bs.states = Arena::from_slots(states_slots);
+
+        // the index of each state in the order they were added.
+        let mut states_order_index: Map<StateId, usize> = Map::new();
+        for state in store_serde.states_order {
+            if !bs.states.contains(state.0) {
+                return Err("ordered state doesn't exist");
+            }
+            if states_order_index.insert(state, states_order_index.len()).is_some() {
+                return Err("ordered state found twice");
+            }
+            bs.states[state.0].state_prev = bs.state_last;
+            if let Some(state_last) = bs.state_last {
+                bs.states[state_last.0].state_next = Some(state);
+            } else {
+                bs.state_first = Some(state);
+            }
+            bs.state_last = Some(state);
+        }
+        if states_order_index.len() != bs.states.len() {
+            return Err("state order doesn't contain all states");
+        }
+
+        // parents are always added before their children (which also prevents cycles).
+        let mut state_parents: Vec<(StateId, StateId)> = Vec::new();
+        for (state, state_data) in bs.states.iter() {
+            let state = StateId(state);
+            for state_child in &state_data.state_children {
+                match states_order_index.get(state_child) {
+                    Some(&index) if index > states_order_index[&state] => {},
+                    _ => { return Err("child state doesn't exist or was added before its parent"); },
+                }
+                state_parents.push((*state_child, state));
+            }
+        }
+        for (state_child, state) in state_parents {
+            if bs.states[state_child.0].state_parent.is_some() {
+                return Err("child state found twice");
+            }
+            bs.states[state_child.0].state_parent = Some(state);
+        }
+
+        for (state, chunk_list_index) in store_serde.states_pending {
+            if !bs.states.contains(state.0) {
+                return Err("pending state doesn't exist");
+            }
+            if bs.states[state.0].pending {
+                return Err("pending state found twice");
+            }
+            let chunk_list_reference: BChunkListId = match chunk_list_ids.get(chunk_list_index) {
+                Some(&chunk_list) => chunk_list,
+                None => { return Err("chunk list index out of range"); },
+            };
+            bs.memory.chunk_list[chunk_list_reference].users += 1;
+            bs.states_pending.push_back(BArrayStatePending {
+                state,
+                chunk_list_reference,
+            });
+            bs.states[state.0].pending = true;
+        }
+
+        if bs.memory.chunk_list.iter().any(|(_, chunk_list)| chunk_list.users == 0) {
+            return Err("chunk list isn't used by any state");
+        }
+        if bs.memory.chunk.iter().any(|(_, chunk)| chunk.users == 0) {
+            return Err("chunk isn't used by any chunk list");
+        }
+
+        Ok(bs)
+    }
+
+    /// Serialize the contents of `state` as bytes (without de-duplication),
+    /// useful to store a single state in a format that doesn't depend on this crate.
+    ///
+    /// Read back using `BArrayStore.state_deserialize`.
+    pub fn state_serialize<S: Serializer>(
+        &self,
+        state: StateId,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Bytes(Cow::Owned(self.state_data_get_alloc(state))).serialize(serializer)
+    }
+
+    /// Add a state from bytes written by `BArrayStore.state_serialize`,
+    /// the arguments & return value match `BArrayStore.state_add`.
+    pub fn state_deserialize<'de, D: Deserializer<'de>>(
+        &mut self,
+        deserializer: D,
+        state_reference: Option<StateId>,
+    ) -> Result<StateId, D::Error> {
+        let data = Bytes::deserialize(deserializer)?;
+        if data.0.len() % self.info.chunk_stride != 0 {
+            return Err(de::Error::custom("size isn't a multiple of the stride"));
+        }
+        Ok(self.state_add(&data.0[..], state_reference))
+    }
+}
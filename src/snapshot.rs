@@ -0,0 +1,158 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+//! Group states from many array stores, so they can be added & removed together.
+//!
+//! A snapshot represents a single history step (an undo step for example),
+//! made up of named arrays which may have different strides.
+
+use map::{
+    Map,
+    Set,
+};
+use {
+    BArrayStoreAtSize,
+    StateId,
+};
+
+#[cfg(not(any(feature = "std", test)))]
+use alloc::{
+    string::String,
+    string::ToString,
+    vec::Vec,
+};
+
+///
+/// An array to add to a snapshot, see `BArrayStoreAtSize::snapshot_add`.
+///
+pub struct BArraySnapshotArray<'a> {
+    /// Identifier, used to find the array to de-duplicate against in the previous snapshot.
+    pub key: &'a str,
+    /// Used to look-up the store, see `BArrayStoreAtSize::ensure`.
+    pub stride: usize,
+    pub chunk_count: usize,
+    pub data: &'a [u8],
+}
+
+struct BArraySnapshotState {
+    // (stride, chunk_count)
+    store_key: (usize, usize),
+    state: StateId,
+}
+
+///
+/// States for a single history step, created by `BArrayStoreAtSize::snapshot_add`.
+///
+/// This must be freed using `BArrayStoreAtSize::snapshot_remove`,
+/// otherwise its states remain in the store until it's cleared.
+///
+pub struct BArraySnapshot {
+    states: Map<String, BArraySnapshotState>,
+}
+
+impl BArraySnapshot {
+    /// Return the state stored for `key`.
+    pub fn state_get(
+        &self,
+        key: &str,
+    ) -> Option<StateId> {
+        self.states.get(key).map(|s| s.state)
+    }
+
+    /// Return the number of arrays in this snapshot.
+    pub fn len(
+        &self,
+    ) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(
+        &self,
+    ) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Iterate over the keys of all arrays (in no particular order).
+    pub fn keys<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.states.keys().map(|k| k.as_str())
+    }
+}
+
+impl BArrayStoreAtSize {
+    /// Add a snapshot made up of `arrays`,
+    /// each array uses the state with the same key in `snapshot_reference` as a reference
+    /// (when it was stored using the same stride & chunk count).
+    ///
+    /// Keys must be unique, otherwise the duplicate key is returned (and nothing is added).
+    pub fn snapshot_add<'a>(
+        &mut self,
+        snapshot_reference: Option<&BArraySnapshot>,
+        arrays: &[BArraySnapshotArray<'a>],
+    ) -> Result<BArraySnapshot, &'a str> {
+        {
+            let mut keys: Set<&str> = Set::new();
+            for array in arrays {
+                if !keys.insert(array.key) {
+                    return Err(array.key);
+                }
+            }
+        }
+
+        let mut states: Map<String, BArraySnapshotState> = Map::new();
+        for array in arrays {
+            let store_key = (array.stride, array.chunk_count);
+            let state_reference: Option<StateId> = {
+                match snapshot_reference.and_then(|snapshot| snapshot.states.get(array.key)) {
+                    Some(s) if s.store_key == store_key => Some(s.state),
+                    _ => None,
+                }
+            };
+            let state = self.ensure(array.stride, array.chunk_count).state_add(
+                array.data, state_reference);
+            states.insert(array.key.to_string(), BArraySnapshotState {
+                store_key,
+                state,
+            });
+        }
+
+        Ok(BArraySnapshot {
+            states,
+        })
+    }
+
+    /// Return the contents of the array stored for `key` in `snapshot`.
+    ///
+    /// Panics when `snapshot` wasn't added to this store (or has been removed).
+    pub fn snapshot_data_get_alloc(
+        &self,
+        snapshot: &BArraySnapshot,
+        key: &str,
+    ) -> Option<Vec<u8>> {
+        snapshot.states.get(key).map(|s| {
+            let bs = self.get(s.store_key.0, s.store_key.1);
+            assert!(bs.is_some_and(|bs| bs.contains(s.state)), "Snapshot not found in this store");
+            bs.unwrap().state_data_get_alloc(s.state)
+        })
+    }
+
+    /// Remove all states in `snapshot`.
+    ///
+    /// Snapshots can be removed in any order.
+    ///
+    /// Panics when `snapshot` wasn't added to this store (nothing is removed).
+    pub fn snapshot_remove(
+        &mut self,
+        snapshot: BArraySnapshot,
+    ) {
+        // check all states first, so a snapshot from another store can't be partially removed.
+        for s in snapshot.states.values() {
+            let bs = self.get(s.store_key.0, s.store_key.1);
+            assert!(bs.is_some_and(|bs| bs.contains(s.state)), "Snapshot not found in this store");
+        }
+        for (_, s) in snapshot.states {
+            self.get_mut(s.store_key.0, s.store_key.1).unwrap().state_remove(s.state);
+        }
+    }
+}
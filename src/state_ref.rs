@@ -0,0 +1,127 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+//! Read-only access to a state, see `StateRef`.
+
+use ::alloc::borrow::Cow;
+
+use {
+    bchunk_data,
+    BArrayStore,
+    BChunkList,
+    StateId,
+};
+
+#[cfg(not(any(feature = "std", test)))]
+use alloc::vec::Vec;
+
+///
+/// A state borrowed from a `BArrayStore`, returned by `BArrayStore.state_ref`.
+///
+/// The store can't be changed while this exists,
+/// so the state can't be removed while it's being read:
+///
+/// ```compile_fail
+/// let mut bs = block_array_cow::BArrayStore::new(1, 8);
+/// let state = bs.state_add(b"The quick brown fox", None);
+/// let state_ref = bs.state_ref(state);
+/// bs.state_remove(state);
+/// assert_eq!(19, state_ref.len());
+/// ```
+///
+#[derive(Clone, Copy)]
+pub struct StateRef<'a> {
+    bs: &'a BArrayStore,
+    state: StateId,
+}
+
+impl<'a> StateRef<'a> {
+    fn chunk_list(
+        &self,
+    ) -> &'a BChunkList {
+        let bs: &'a BArrayStore = self.bs;
+        &bs.memory.chunk_list[bs.states[self.state.0].chunk_list]
+    }
+
+    /// Return the handle of this state.
+    pub fn id(
+        &self,
+    ) -> StateId {
+        self.state
+    }
+
+    /// Return the size of the state in bytes, see `BArrayStore.state_size_get`.
+    pub fn len(
+        &self,
+    ) -> usize {
+        self.chunk_list().total_size
+    }
+
+    pub fn is_empty(
+        &self,
+    ) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the contents of each chunk in order,
+    /// this avoids copying the array (besides chunks stored as a delta).
+    pub fn chunks(
+        &self,
+    ) -> impl Iterator<Item = Cow<'a, [u8]>> + 'a {
+        let chunks = &self.bs.memory.chunk;
+        self.chunk_list().chunk_refs.iter().map(move |&chunk| bchunk_data(chunks, &chunks[chunk]))
+    }
+
+    /// Fill in `data` (which must be `StateRef.len` bytes), see `BArrayStore.state_data_get`.
+    pub fn copy_to(
+        &self,
+        data: &mut [u8],
+    ) {
+        self.bs.state_data_get(self.state, data);
+    }
+
+    /// Fill in `data` starting at `offset` (in bytes), see `BArrayStore.state_data_get_range`.
+    pub fn read_range(
+        &self,
+        offset: usize,
+        data: &mut [u8],
+    ) {
+        self.bs.state_data_get_range(self.state, offset, data);
+    }
+
+    /// Allocate an array with the contents of this state, see `BArrayStore.state_data_get_alloc`.
+    pub fn to_vec(
+        &self,
+    ) -> Vec<u8> {
+        self.bs.state_data_get_alloc(self.state)
+    }
+
+    /// Return the metadata of this state, see `BArrayStore.state_metadata_set`.
+    pub fn metadata(
+        &self,
+    ) -> &'a [u8] {
+        self.bs.state_metadata_get(self.state)
+    }
+}
+
+impl BArrayStore {
+    /// Return a view of `state` which borrows the store,
+    /// so the state can't be removed (or the store changed) while it's in use.
+    pub fn state_ref<'a>(
+        &'a self,
+        state: StateId,
+    ) -> StateRef<'a> {
+        assert!(self.contains(state), "State not found {:?}", state);
+        StateRef {
+            bs: self,
+            state,
+        }
+    }
+
+    /// Return views of all states, in the order they were added (see `BArrayStore.states`).
+    pub fn state_refs<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = StateRef<'a>> + 'a {
+        self.states().map(move |state| StateRef { bs: self, state })
+    }
+}
@@ -0,0 +1,102 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+use arena::{
+    Arena,
+    ArenaId,
+    GENERATION_RETIRED,
+};
+
+struct TestElem {
+    value: usize,
+    link: Option<ArenaId<TestElem>>,
+}
+
+#[test]
+fn test_arena() {
+    let total = 128;
+    let mut p: Arena<TestElem> = Arena::new();
+
+    for _ in 0..2 {
+        let mut a = p.alloc(TestElem { value: 0, link: None });
+        for i in 1..total {
+            a = p.alloc(TestElem { value: i, link: Some(a) });
+        }
+        assert_eq!(total, p.len());
+        assert_eq!(total, p.iter().count());
+
+        for i in (0..total).rev() {
+            assert_eq!(p[a].value, i);
+            let a_next = p[a].link;
+            p.free(a);
+            assert!(!p.contains(a));
+            if let Some(a_next) = a_next {
+                a = a_next;
+            }
+        }
+        assert!(p.is_empty());
+    }
+}
+
+#[test]
+fn test_arena_reuse() {
+    let mut p: Arena<usize> = Arena::new();
+    let a = p.alloc(1);
+    p.free(a);
+    // the slot is reused, the old id isn't valid.
+    let b = p.alloc(2);
+    assert_eq!(a.index(), b.index());
+    assert!(p.get(a).is_none());
+    assert_eq!(Some(&2), p.get(b));
+
+    p.clear();
+    assert!(!p.contains(b));
+    let c = p.alloc(3);
+    assert!(b != c);
+    assert_eq!(1, p.ids().count());
+}
+
+#[test]
+fn test_arena_from_slots() {
+    let mut p: Arena<usize> = Arena::new();
+    let ids: Vec<ArenaId<usize>> = (0..8).map(|i| p.alloc(i)).collect();
+    p.free(ids[2]);
+    p.free(ids[5]);
+    let c = p.alloc(10);
+
+    let mut p_copy: Arena<usize> = Arena::from_slots(p.slots().map(|(g, v)| (g, v.cloned())));
+    assert_eq!(p.len(), p_copy.len());
+    for (id, value) in p.iter() {
+        assert_eq!(Some(value), p_copy.get(id));
+    }
+    assert!(!p_copy.contains(ids[2]));
+    assert!(!p_copy.contains(ids[5]));
+    assert_eq!(10, p_copy[c]);
+
+    // free slots are reused the same way.
+    assert_eq!(p.alloc(11), p_copy.alloc(11));
+}
+
+#[test]
+fn test_arena_retire() {
+    // slots are retired instead of the generation wrapping around.
+    let mut p: Arena<usize> = Arena::from_slots(vec![
+        (GENERATION_RETIRED - 1, Some(1)),
+        (GENERATION_RETIRED - 1, Some(2)),
+        (GENERATION_RETIRED, None),
+    ]);
+    let a = ArenaId::from_parts(0, GENERATION_RETIRED - 1);
+    let b = ArenaId::from_parts(1, GENERATION_RETIRED - 1);
+    assert_eq!(1, p[a]);
+    p.free(a);
+    assert!(!p.contains(a));
+    let c = p.alloc(3);
+    assert_eq!(3, c.index());
+
+    p.clear();
+    assert!(!p.contains(b));
+    let d = p.alloc(4);
+    assert_eq!(3, d.index());
+    assert!(c != d);
+    assert_eq!(4, p.slots().count());
+}
@@ -0,0 +1,61 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+use super::*;
+
+#[test]
+fn test_capi_state_id() {
+    assert_eq!(None, state_id_from_c(STATE_ID_NONE));
+    for &(index, generation) in &[(0, 0), (0, 1), (7, 3), (::core::u32::MAX - 1, ::core::u32::MAX)] {
+        let state = StateId(ArenaId::from_parts(index, generation));
+        let state_c = state_id_to_c(state);
+        assert!(state_c != STATE_ID_NONE);
+        assert_eq!(Some(state), state_id_from_c(state_c));
+    }
+}
+
+#[test]
+fn test_capi_store() { unsafe {
+    let data_src: Vec<u8> = (0..1024).map(|i| (i % 251) as u8).collect();
+    let bs = barray_store_new(4, 32);
+    let state_a = barray_store_state_add(bs, data_src.as_ptr() as *const c_void, data_src.len(), STATE_ID_NONE);
+    let state_b = barray_store_state_add(bs, data_src.as_ptr() as *const c_void, data_src.len(), state_a);
+    let state_empty = barray_store_state_add(bs, ::core::ptr::null(), 0, state_a);
+    assert!(barray_store_is_valid(bs));
+    assert_eq!(data_src.len(), barray_store_calc_size_compacted_get(bs));
+    assert_eq!(data_src.len() * 2, barray_store_calc_size_expanded_get(bs));
+
+    let mut data_dst: Vec<u8> = vec![0; barray_store_state_size_get(bs, state_b)];
+    barray_store_state_data_get(bs, state_b, data_dst.as_mut_ptr() as *mut c_void);
+    assert_eq!(data_src, data_dst);
+    assert_eq!(0, barray_store_state_size_get(bs, state_empty));
+    barray_store_state_data_get(bs, state_empty, ::core::ptr::null_mut());
+
+    barray_store_state_remove(bs, state_a);
+    barray_store_state_remove(bs, state_empty);
+    assert_eq!(data_src.len(), barray_store_calc_size_expanded_get(bs));
+    barray_store_clear(bs);
+    assert_eq!(0, barray_store_calc_size_compacted_get(bs));
+    barray_store_free(bs);
+
+    // invalid sizes.
+    assert!(barray_store_new(0, 32).is_null());
+    assert!(barray_store_new(4, 0).is_null());
+} }
+
+#[test]
+fn test_capi_header() {
+    // the header is written by hand, check all functions are declared.
+    let header = include_str!("../capi/block_array_cow.h");
+    let source = include_str!("capi.rs");
+    let mut functions_len = 0;
+    for line in source.lines() {
+        if let Some(index) = line.find("extern \"C\" fn ") {
+            let name = &line[(index + "extern \"C\" fn ".len())..line.find('(').unwrap()];
+            assert!(header.contains(&format!(" *{}(", name)) || header.contains(&format!(" {}(", name)),
+                    "{} isn't declared in the header", name);
+            functions_len += 1;
+        }
+    }
+    assert_eq!(10, functions_len);
+}
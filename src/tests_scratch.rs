@@ -0,0 +1,71 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+// Check scratch memory is reused between states and freed by `BArrayStore.shrink_scratch`.
+
+use super::*;
+
+#[test]
+fn shrink_scratch() {
+    let mut bs = BArrayStore::new(1, 8);
+    let data_src_a: Vec<u8> = (0..255).collect();
+    let mut data_src_b = data_src_a.clone();
+    data_src_b.reverse();
+    let mut data_src_c = data_src_a.clone();
+    data_src_c.insert(100, 1);
+
+    // nothing to de-duplicate against.
+    let state_a = bs.state_add(&data_src_a, None);
+    assert_eq!(0, bs.scratch.capacity());
+
+    // scratch memory is reused.
+    let state_b = bs.state_add(&data_src_b, Some(state_a));
+    let capacity = bs.scratch.capacity();
+    assert!(capacity != 0);
+    let state_c = bs.state_add(&data_src_c, Some(state_a));
+    assert_eq!(capacity, bs.scratch.capacity());
+
+    // then freed and allocated again.
+    bs.shrink_scratch();
+    assert_eq!(0, bs.scratch.capacity());
+    let state_d = bs.state_add(&data_src_c, Some(state_b));
+    assert!(bs.scratch.capacity() != 0);
+    bs.shrink_scratch();
+    assert_eq!(0, bs.scratch.capacity());
+    assert_eq!(Ok(()), bs.validate());
+
+    assert_eq!(bs.state_data_get_alloc(state_b), data_src_b);
+    assert_eq!(bs.state_data_get_alloc(state_c), data_src_c);
+    assert_eq!(bs.state_data_get_alloc(state_d), data_src_c);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn shrink_scratch_threads() {
+    let mut bs = BArrayStore::new(1, 32);
+    bs.threads_set(4);
+    let data_src_a: Vec<u8> = (0..(1 << 18)).map(|i| ((i * 7919) ^ (i >> 5)) as u8).collect();
+    let mut data_src_b = data_src_a.clone();
+    data_src_b[1 << 10] ^= 1;
+    data_src_b[(1 << 18) - (1 << 10)] ^= 1;
+    let mut data_src_c = data_src_a.clone();
+    data_src_c.insert(1 << 10, 1);
+    data_src_c.insert((1 << 18) - (1 << 10), 1);
+
+    let state_a = bs.state_add(&data_src_a, None);
+
+    // per-thread scratch memory is reused.
+    let state_b = bs.state_add(&data_src_b, Some(state_a));
+    let capacity = bs.scratch.capacity();
+    assert!(bs.scratch.thread_hash_arrays.len() > 1);
+    assert!(bs.scratch.thread_lookup_arrays.len() > 1);
+    let state_c = bs.state_add(&data_src_c, Some(state_a));
+    assert_eq!(capacity, bs.scratch.capacity());
+
+    bs.shrink_scratch();
+    assert_eq!(0, bs.scratch.capacity());
+    assert_eq!(Ok(()), bs.validate());
+
+    assert_eq!(bs.state_data_get_alloc(state_b), data_src_b);
+    assert_eq!(bs.state_data_get_alloc(state_c), data_src_c);
+}
@@ -0,0 +1,143 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+// Corrupt the internal state of a store, checking each `ValidationError` is reported.
+
+use super::*;
+
+const DATA: &[u8] = b"aaaabbbbccccdddd";
+
+fn store_single(
+) -> (BArrayStore, StateId) {
+    let mut bs = BArrayStore::new(1, 4);
+    let state = bs.state_add(DATA, None);
+    assert_eq!(Ok(()), bs.validate());
+    (bs, state)
+}
+
+fn chunk_list_mut(
+    bs: &mut BArrayStore,
+    state: StateId,
+) -> &mut BChunkList {
+    let chunk_list = bs.states[state.0].chunk_list;
+    &mut bs.memory.chunk_list[chunk_list]
+}
+
+fn validate_errors(
+    bs: &BArrayStore,
+) -> Vec<ValidationError> {
+    let errors = bs.validate().unwrap_err();
+    assert!(!bs.is_valid());
+    for err in &errors {
+        assert!(!format!("{}", err).is_empty());
+    }
+    errors
+}
+
+#[test]
+fn test_validate_size_mismatch() {
+    let (mut bs, state) = store_single();
+    chunk_list_mut(&mut bs, state).total_size += 1;
+    assert_eq!(
+        vec![ValidationError::SizeMismatch { state, total_size: DATA.len() + 1, chunks_size: DATA.len() }],
+        validate_errors(&bs));
+}
+
+#[test]
+fn test_validate_chunk_offset_mismatch() {
+    let (mut bs, state) = store_single();
+    chunk_list_mut(&mut bs, state).chunk_offsets[1] += 1;
+    assert_eq!(
+        vec![ValidationError::ChunkOffsetMismatch { state, chunk_index: 1, offset: Some(5), offset_calc: 4 }],
+        validate_errors(&bs));
+
+    // missing offset.
+    let (mut bs, state) = store_single();
+    chunk_list_mut(&mut bs, state).chunk_offsets.pop();
+    assert_eq!(
+        vec![ValidationError::ChunkOffsetMismatch { state, chunk_index: 3, offset: None, offset_calc: 12 }],
+        validate_errors(&bs));
+}
+
+#[test]
+fn test_validate_chunk_unmerged() {
+    let mut bs = BArrayStore::new(1, 32);
+    let data: Vec<u8> = (0..64).collect();
+    let state = bs.state_add(&data[..], None);
+    assert_eq!(Ok(()), bs.validate());
+
+    // replace the chunks with one that's too small to be kept on its own.
+    let chunk_a = bchunk_new_copydata(&mut bs.memory.chunk, &data[..63]);
+    let chunk_b = bchunk_new_copydata(&mut bs.memory.chunk, &data[63..]);
+    let mut chunk_list = bchunk_list_new(data.len());
+    bchunk_list_append_only(&mut bs.memory.chunk, &mut chunk_list, chunk_a);
+    bchunk_list_append_only(&mut bs.memory.chunk, &mut chunk_list, chunk_b);
+    ::core::mem::swap(&mut chunk_list.chunk_refs, &mut chunk_list_mut(&mut bs, state).chunk_refs);
+    ::core::mem::swap(&mut chunk_list.chunk_offsets, &mut chunk_list_mut(&mut bs, state).chunk_offsets);
+    for chunk in chunk_list.chunk_refs {
+        bchunk_decref(&mut bs.memory.chunk, chunk);
+    }
+    assert_eq!(data, bs.state_data_get_alloc(state));
+    assert_eq!(
+        vec![ValidationError::ChunkUnmerged { state, chunk_index: 1, size: 1 }],
+        validate_errors(&bs));
+}
+
+#[test]
+fn test_validate_chunk_list_users_mismatch() {
+    let (mut bs, state) = store_single();
+    chunk_list_mut(&mut bs, state).users += 1;
+    assert_eq!(
+        vec![ValidationError::ChunkListUsersMismatch { state, users: 2, users_calc: 1 }],
+        validate_errors(&bs));
+}
+
+#[test]
+fn test_validate_chunk_users_mismatch() {
+    let (mut bs, state) = store_single();
+    let chunk = chunk_list_mut(&mut bs, state).chunk_refs[2];
+    bs.memory.chunk[chunk].users += 1;
+    assert_eq!(
+        vec![ValidationError::ChunkUsersMismatch { state, chunk_index: 2, users: 2, users_calc: 1 }],
+        validate_errors(&bs));
+}
+
+#[test]
+fn test_validate_state_order_mismatch() {
+    let (mut bs, state) = store_single();
+    bs.state_add(DATA, Some(state));
+    bs.states[state.0].state_next = None;
+    assert_eq!(
+        vec![ValidationError::StateOrderMismatch { states: 2, states_ordered: 1 }],
+        validate_errors(&bs));
+}
+
+#[test]
+fn test_validate_state_parent_mismatch() {
+    let (mut bs, state) = store_single();
+    bs.parent_tracking_set(true);
+    let state_child = bs.state_add(DATA, Some(state));
+    assert_eq!(Ok(()), bs.validate());
+    bs.states[state_child.0].state_parent = None;
+    assert_eq!(
+        vec![ValidationError::StateParentMismatch { state }],
+        validate_errors(&bs));
+}
+
+#[test]
+fn test_validate_chunk_list_leak() {
+    let (mut bs, _) = store_single();
+    bs.memory.chunk_list.alloc(bchunk_list_new(0));
+    assert_eq!(
+        vec![ValidationError::ChunkListLeak { allocated: 2, used: 1 }],
+        validate_errors(&bs));
+}
+
+#[test]
+fn test_validate_chunk_leak() {
+    let (mut bs, _) = store_single();
+    bchunk_new_copydata(&mut bs.memory.chunk, DATA);
+    assert_eq!(
+        vec![ValidationError::ChunkLeak { allocated: 5, used: 4 }],
+        validate_errors(&bs));
+}
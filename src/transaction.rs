@@ -0,0 +1,127 @@
+// Apache License, Version 2.0
+// (c) Campbell Barton, 2017
+
+//! Add & remove states as a single operation, see `BArrayStore::transaction`.
+//!
+//! Useful when a history step is made up of multiple arrays,
+//! so a failure part way through doesn't leave the store with only some of them.
+
+use {
+    BArrayStore,
+    StateId,
+};
+
+#[cfg(not(any(feature = "std", test)))]
+use alloc::vec::Vec;
+
+///
+/// Pending changes to a `BArrayStore`, see `BArrayStore::transaction`.
+///
+/// States are added immediately (so they can be used as references),
+/// removing states is deferred until the transaction is committed.
+///
+pub struct BArrayTransaction<'a> {
+    bs: &'a mut BArrayStore,
+    states_added: Vec<StateId>,
+    states_removed: Vec<StateId>,
+    committed: bool,
+}
+
+impl<'a> BArrayTransaction<'a> {
+    /// Add a state, see `BArrayStore::state_add`.
+    ///
+    /// The state is removed if the transaction fails.
+    pub fn state_add(
+        &mut self,
+        data: &[u8],
+        state_reference: Option<StateId>,
+    ) -> StateId {
+        let state = self.bs.state_add(data, state_reference);
+        self.states_added.push(state);
+        state
+    }
+
+    /// Remove a state when the transaction is committed, see `BArrayStore::state_remove`.
+    ///
+    /// The state remains accessible until then.
+    pub fn state_remove(
+        &mut self,
+        state: StateId,
+    ) {
+        assert!(self.bs.contains(state));
+        assert!(!self.states_removed.contains(&state), "State removed twice {:?}", state);
+        self.states_removed.push(state);
+    }
+
+    /// Access the store (which includes states added by this transaction).
+    pub fn store(
+        &self,
+    ) -> &BArrayStore {
+        self.bs
+    }
+
+    fn commit(
+        &mut self,
+    ) {
+        // check all states before removing any,
+        // so a panic rolls back the transaction instead of leaving some states removed.
+        for &state in &self.states_removed {
+            assert!(self.bs.contains(state), "State not found {:?}", state);
+        }
+        for &state in &self.states_removed {
+            self.bs.state_remove(state);
+        }
+        self.committed = true;
+    }
+}
+
+impl<'a> Drop for BArrayTransaction<'a> {
+    /// Roll back when the transaction isn't committed
+    /// (an error was returned or a panic occurred).
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        // panicking again while unwinding aborts,
+        // skip states which can't be removed instead.
+        #[cfg(feature = "std")]
+        let panicking = ::std::thread::panicking();
+        #[cfg(not(feature = "std"))]
+        let panicking = false;
+        // remove newest first, so children are removed before their parents.
+        for &state in self.states_added.iter().rev() {
+            if panicking && !self.bs.contains(state) {
+                continue;
+            }
+            self.bs.state_remove(state);
+        }
+    }
+}
+
+impl BArrayStore {
+    /// Run `f` with a transaction for adding & removing states,
+    /// changes are only kept when `f` returns `Ok`.
+    ///
+    /// When `f` returns an error (or panics),
+    /// states added by the transaction are removed & removals are skipped,
+    /// leaving the contents of the store unchanged.
+    pub fn transaction<T, E, F>(
+        &mut self,
+        f: F,
+    ) -> Result<T, E>
+        where
+        F: FnOnce(&mut BArrayTransaction) -> Result<T, E>,
+    {
+        let mut tx = BArrayTransaction {
+            bs: self,
+            states_added: Vec::new(),
+            states_removed: Vec::new(),
+            committed: false,
+        };
+        let result = f(&mut tx);
+        if result.is_ok() {
+            tx.commit();
+        }
+        result
+    }
+}
@@ -69,19 +69,13 @@ impl Rng {
 
     pub fn get_vec<T: RandGen>(&mut self, len: usize) -> Vec<T> {
         let mut v: Vec<T> = Vec::with_capacity(len);
-        unsafe { v.set_len(len) };
-        self.fill(&mut v[..]);
+        for _ in 0..len {
+            v.push(T::rand_value(self));
+        }
         return v;
     }
 }
 
-#[inline]
-pub fn slice_u8_from_any_mut<T: Sized>(p: &mut T) -> &mut [u8] {
-    unsafe {
-        ::std::slice::from_raw_parts_mut((p as *mut T) as *mut u8, ::std::mem::size_of::<T>())
-    }
-}
-
 pub trait RandGen {
     fn rand_value(r: &mut Rng) -> Self;
 }
@@ -103,9 +97,9 @@ macro_rules! rand_gen_any_impl {
         impl RandGen for $t {
             #[inline]
             fn rand_value(r: &mut Rng) -> Self {
-                let mut v: $t = unsafe { ::std::mem::uninitialized() };
-                r.fill(slice_u8_from_any_mut(&mut v));
-                v
+                let mut v = [0_u8; ::std::mem::size_of::<$t>()];
+                r.fill(&mut v[..]);
+                <$t>::from_ne_bytes(v)
             }
         }
     )*)
@@ -1,16 +1,25 @@
 // Licensed: Apache 2.0
 
 extern crate block_array_cow;
+#[cfg(feature = "serde")]
+extern crate serde_json;
 
 mod rand;
 
+#[cfg(feature = "std")]
 use block_array_cow::{
-    BArrayStore,
-    BArrayState,
+    BArrayChunkPool,
+    DumpFormat,
 };
 
-use ::std::ptr::{
-    null_mut,
+use block_array_cow::{
+    BArrayStore,
+    BArrayStoreAtSize,
+    BArraySnapshot,
+    BArraySnapshotArray,
+    NumericType,
+    StateId,
+    StateRef,
 };
 
 const DEBUG_PRINT: bool = false;
@@ -71,11 +80,11 @@ fn testchunk_as_data_array(
 struct TestBuffer {
     data: Vec<u8>,
     // for reference
-    state: *mut BArrayState,
+    state: Option<StateId>,
 }
 
 fn testbuffer_list_add(cl: &mut Vec<TestBuffer>, data: Vec<u8>) {
-    cl.push(TestBuffer { data: data, state: null_mut() });
+    cl.push(TestBuffer { data: data, state: None });
 }
 
 fn testbuffer_list_add_copydata(cl: &mut Vec<TestBuffer>, data: &[u8]) {
@@ -155,9 +164,9 @@ macro_rules! testbuffer_strings {
     }
 }
 
-fn testbuffer_item_validate(tb: &TestBuffer) -> bool {
+fn testbuffer_item_validate(bs: &BArrayStore, tb: &TestBuffer) -> bool {
     let mut ok = true;
-    let data_state = BArrayStore::state_data_get_alloc(tb.state);
+    let data_state = bs.state_data_get_alloc(tb.state.unwrap());
     if tb.data.len() != data_state.len() {
         ok = false;
     } else if &data_state[..] != &tb.data[..] {
@@ -168,10 +177,10 @@ fn testbuffer_item_validate(tb: &TestBuffer) -> bool {
 }
 
 fn testbuffer_list_validate(
-    cl: &mut Vec<TestBuffer>,
+    bs: &BArrayStore, cl: &mut Vec<TestBuffer>,
 ) -> bool {
     for tb in cl {
-        if !testbuffer_item_validate(tb) {
+        if !testbuffer_item_validate(bs, tb) {
             return false;
         }
     }
@@ -190,10 +199,10 @@ fn testbuffer_list_data_randomize(cl: &mut Vec<TestBuffer>, mut random_seed: u32
 fn testbuffer_list_store_populate(
     bs: &mut BArrayStore, cl: &mut Vec<TestBuffer>,
 ) {
-    let mut state_prev: Option<*const BArrayState> = None;
+    let mut state_prev: Option<StateId> = None;
     for tb in cl {
-        tb.state = bs.state_add(&tb.data[..], state_prev);
-        state_prev = Some(tb.state);
+        tb.state = Some(bs.state_add(&tb.data[..], state_prev));
+        state_prev = tb.state;
     }
 }
 
@@ -201,8 +210,8 @@ fn testbuffer_list_store_clear(
     bs: &mut BArrayStore, cl: &mut Vec<TestBuffer>,
 ) {
     for tb in cl {
-        bs.state_remove(tb.state);
-        tb.state = null_mut();
+        bs.state_remove(tb.state.unwrap());
+        tb.state = None;
     }
 }
 
@@ -216,7 +225,7 @@ fn testbuffer_run_tests_single(
     bs: &mut BArrayStore, cl: &mut Vec<TestBuffer>,
 ) {
     testbuffer_list_store_populate(bs, cl);
-    assert!(testbuffer_list_validate(cl));
+    assert!(testbuffer_list_validate(bs, cl));
     assert!(bs.is_valid());
     if DEBUG_PRINT {
         print_mem_saved("data", bs);
@@ -263,7 +272,7 @@ fn nop_state() {
     let mut bs = BArrayStore::new(1, 32);
     let data = b"test";
     let state = bs.state_add(data, None);
-    assert_eq!(4, BArrayStore::state_size_get(state));
+    assert_eq!(4, bs.state_size_get(state));
     bs.state_remove(state);
     bs.clear();
 }
@@ -273,7 +282,7 @@ fn single() {
     let mut bs = BArrayStore::new(1, 32);
     let data_src = b"test";
     let state = bs.state_add(data_src, None);
-    let data_dst = BArrayStore::state_data_get_alloc(state);
+    let data_dst = bs.state_data_get_alloc(state);
     assert_eq!(data_src.len(), data_dst.len());
     assert_eq!(data_src, &data_dst[..]);
 }
@@ -290,10 +299,10 @@ fn double_nop() {
     assert_eq!(bs.calc_size_expanded_get(), data_src.len() * 2);
 
     let mut data_dst;
-    data_dst = BArrayStore::state_data_get_alloc(state_a);
+    data_dst = bs.state_data_get_alloc(state_a);
     assert_eq!(data_src, &data_dst[..]);
 
-    data_dst = BArrayStore::state_data_get_alloc(state_b);
+    data_dst = bs.state_data_get_alloc(state_b);
     assert_eq!(data_src, &data_dst[..]);
 }
 
@@ -310,13 +319,1131 @@ fn double_diff() {
     assert_eq!(bs.calc_size_expanded_get(), data_src_a.len() * 2);
 
     let mut data_dst;
-    data_dst = BArrayStore::state_data_get_alloc(state_a);
+    data_dst = bs.state_data_get_alloc(state_a);
     assert_eq!(data_src_a, &data_dst[..]);
 
-    data_dst = BArrayStore::state_data_get_alloc(state_b);
+    data_dst = bs.state_data_get_alloc(state_b);
     assert_eq!(data_src_b, &data_dst[..]);
 }
 
+#[test]
+fn validate() {
+    let mut bs = BArrayStore::new(1, 4);
+    assert_eq!(Ok(()), bs.validate());
+
+    let state_a = bs.state_add(b"aaaabbbbccccdddd", None);
+    let state_b = bs.state_add(b"aaaabbbb####dddd", Some(state_a));
+    let state_c = bs.state_add(b"aaaabbbb####dddd", Some(state_b));
+    assert_eq!(Ok(()), bs.validate());
+
+    bs.state_remove(state_b);
+    assert_eq!(Ok(()), bs.validate());
+    bs.state_remove(state_a);
+    bs.state_remove(state_c);
+    assert_eq!(Ok(()), bs.validate());
+    assert!(bs.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn debug_dump() {
+    let mut bs = BArrayStore::new(1, 4);
+    let data_src_a = b"aaaabbbbccccdddd";
+    let data_src_b = b"aaaabbbb####dddd";
+
+    let state_a = bs.state_add(data_src_a, None);
+    let state_b = bs.state_add(data_src_b, Some(state_a));
+    assert!(bs.is_valid());
+
+    let mut dot: Vec<u8> = Vec::new();
+    bs.debug_dump(&mut dot, DumpFormat::Dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    assert!(dot.starts_with("digraph BArrayStore {"));
+    assert!(dot.contains("state0 -> list0;"));
+    assert!(dot.contains("state1 -> list1;"));
+    // 'aaaa', 'bbbb' & 'dddd' are shared.
+    assert_eq!(dot.matches("fillcolor").count(), 3);
+
+    let mut table: Vec<u8> = Vec::new();
+    bs.debug_dump(&mut table, DumpFormat::Table).unwrap();
+    let table = String::from_utf8(table).unwrap();
+    assert!(table.starts_with("states: 2, chunk_lists: 2, chunks: 5 (shared: 3)"));
+
+    // states are numbered in the order they were added (not by their reused slots).
+    bs.state_remove(state_a);
+    let state_c = bs.state_add(b"aaaabbbb", Some(state_b));
+    let mut dot: Vec<u8> = Vec::new();
+    bs.debug_dump(&mut dot, DumpFormat::Dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    assert!(dot.contains("state0 [label=\"state 0\\nsize: 16\""));
+    assert!(dot.contains("state1 [label=\"state 1\\nsize: 8\""));
+
+    bs.state_remove(state_b);
+    bs.state_remove(state_c);
+
+    let mut table: Vec<u8> = Vec::new();
+    bs.debug_dump(&mut table, DumpFormat::Table).unwrap();
+    let table = String::from_utf8(table).unwrap();
+    assert!(table.starts_with("states: 0, chunk_lists: 0, chunks: 0 (shared: 0)"));
+}
+
+#[test]
+fn compact() {
+    let mut bs = BArrayStore::new(1, 8);
+    let data_src_a: Vec<u8> = (0..64).collect();
+    let mut data_src_b: Vec<u8> = data_src_a.clone();
+    // an insertion causes chunks which don't align with the chunk size.
+    data_src_b.insert(12, 200);
+    data_src_b.insert(13, 201);
+    data_src_b.insert(14, 202);
+
+    let state_a = bs.state_add(&data_src_a[..], None);
+    let state_b = bs.state_add(&data_src_b[..], Some(state_a));
+    bs.state_remove(state_a);
+
+    // without a reference, chunks are aligned but can't be de-duplicated.
+    let state_c = bs.state_add(&data_src_b[..], None);
+    assert_eq!(bs.calc_size_compacted_get(), data_src_b.len() * 2);
+
+    bs.compact();
+    assert_eq!(Ok(()), bs.validate());
+    assert_eq!(bs.calc_size_compacted_get(), data_src_b.len());
+
+    assert_eq!(bs.state_data_get_alloc(state_b), data_src_b);
+    assert_eq!(bs.state_data_get_alloc(state_c), data_src_b);
+
+    // nothing left to do.
+    bs.compact_state(state_b);
+    assert_eq!(Ok(()), bs.validate());
+    assert_eq!(bs.calc_size_compacted_get(), data_src_b.len());
+}
+
+#[test]
+fn delta() {
+    let stride = 4;
+    let chunk_count = 64;
+    let data_src_a: Vec<u8> = (0..(stride * chunk_count * 16)).map(|i| (i % 251) as u8).collect();
+    let mut data_src_b: Vec<u8> = data_src_a.clone();
+    // change a single element in every other chunk.
+    for i in 0..8 {
+        let offset = (i * 2 * chunk_count * stride) + (stride * 7);
+        data_src_b[offset] = !data_src_b[offset];
+    }
+    let mut data_src_c: Vec<u8> = data_src_b.clone();
+    for i in 0..8 {
+        let offset = (i * 2 * chunk_count * stride) + (stride * 9);
+        data_src_c[offset] = !data_src_c[offset];
+    }
+
+    let size_compacted_no_delta = {
+        let mut bs = BArrayStore::new(stride, chunk_count);
+        let state_a = bs.state_add(&data_src_a[..], None);
+        let state_b = bs.state_add(&data_src_b[..], Some(state_a));
+        bs.state_add(&data_src_c[..], Some(state_b));
+        bs.calc_size_compacted_get()
+    };
+
+    let mut bs = BArrayStore::new(stride, chunk_count);
+    bs.delta_strides_max_set(4);
+    let state_a = bs.state_add(&data_src_a[..], None);
+    let state_b = bs.state_add(&data_src_b[..], Some(state_a));
+    let state_c = bs.state_add(&data_src_c[..], Some(state_b));
+    assert_eq!(Ok(()), bs.validate());
+    assert!(
+        (bs.calc_size_compacted_get() - data_src_a.len()) <
+        (size_compacted_no_delta - data_src_a.len()) / 4);
+
+    assert_eq!(bs.state_data_get_alloc(state_a), data_src_a);
+    assert_eq!(bs.state_data_get_alloc(state_b), data_src_b);
+    assert_eq!(bs.state_data_get_alloc(state_c), data_src_c);
+
+    // the base chunks must be kept while deltas use them.
+    bs.state_remove(state_a);
+    assert_eq!(Ok(()), bs.validate());
+    bs.compact();
+    assert_eq!(Ok(()), bs.validate());
+    assert_eq!(bs.state_data_get_alloc(state_b), data_src_b);
+    assert_eq!(bs.state_data_get_alloc(state_c), data_src_c);
+    bs.state_remove(state_b);
+    bs.state_remove(state_c);
+    assert_eq!(Ok(()), bs.validate());
+    assert_eq!(0, bs.calc_size_compacted_get());
+}
+
+#[test]
+fn delta_table_lookup_first() {
+    // chunks which moved should be found by the table lookup,
+    // instead of being stored as deltas of the chunks at the same position.
+    let stride = 4;
+    let chunk_count = 64;
+    let chunk_size = stride * chunk_count;
+    let data_src_a: Vec<u8> = (0..(chunk_size * 16)).map(|i| ((i * 7) % 251) as u8).collect();
+    let mut data_src_b: Vec<u8> = Vec::with_capacity(data_src_a.len());
+    for chunk_index in &[0, 1, 2, 3, 5, 4, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15] {
+        data_src_b.extend_from_slice(&data_src_a[(chunk_index * chunk_size)..((chunk_index + 1) * chunk_size)]);
+    }
+    // change a single element, stored as a delta.
+    data_src_b[(chunk_size * 10) + (stride * 3)] ^= 0xff;
+
+    let mut bs = BArrayStore::new(stride, chunk_count);
+    bs.delta_strides_max_set(4);
+    let state_a = bs.state_add(&data_src_a[..], None);
+    let state_b = bs.state_add(&data_src_b[..], Some(state_a));
+    assert_eq!(Ok(()), bs.validate());
+    assert_eq!(bs.state_data_get_alloc(state_b), data_src_b);
+    // only the changed stride (and its range) is stored.
+    assert_eq!(
+        bs.calc_size_compacted_get(),
+        data_src_a.len() + stride + ::std::mem::size_of::<(usize, usize)>());
+}
+
+fn delta_numeric_helper(
+    numeric_type: NumericType, stride: usize, chunk_count: usize,
+    data_src_a: &[u8], data_src_b: &[u8],
+) -> (usize, usize) {
+    let size_compacted_no_delta = {
+        let mut bs = BArrayStore::new(stride, chunk_count);
+        let state_a = bs.state_add(data_src_a, None);
+        bs.state_add(data_src_b, Some(state_a));
+        bs.calc_size_compacted_get()
+    };
+
+    let mut bs = BArrayStore::new(stride, chunk_count);
+    bs.delta_numeric_set(Some(numeric_type));
+    let state_a = bs.state_add(data_src_a, None);
+    let state_b = bs.state_add(data_src_b, Some(state_a));
+    assert_eq!(Ok(()), bs.validate());
+    assert_eq!(&bs.state_data_get_alloc(state_a)[..], data_src_a);
+    assert_eq!(&bs.state_data_get_alloc(state_b)[..], data_src_b);
+
+    // adding the same data again should match exactly.
+    let state_c = bs.state_add(data_src_b, Some(state_b));
+    assert_eq!(&bs.state_data_get_alloc(state_c)[..], data_src_b);
+
+    bs.state_remove(state_a);
+    assert_eq!(Ok(()), bs.validate());
+    assert_eq!(&bs.state_data_get_alloc(state_b)[..], data_src_b);
+
+    (bs.calc_size_compacted_get(), size_compacted_no_delta)
+}
+
+#[test]
+fn delta_numeric_float32() {
+    // translate 3D coordinates.
+    let coords_a: Vec<f32> = (0..(3 * 1024)).map(|i| (i / 3) as f32).collect();
+    let coords_b: Vec<f32> = coords_a.chunks(3).flat_map(|co| {
+        vec![co[0] + 0.5, co[1], co[2] - 2.0]
+    }).collect();
+    let data_src_a: Vec<u8> = coords_a.iter().flat_map(|f| f.to_bits().to_ne_bytes().to_vec()).collect();
+    let data_src_b: Vec<u8> = coords_b.iter().flat_map(|f| f.to_bits().to_ne_bytes().to_vec()).collect();
+
+    let (size_compacted, size_compacted_no_delta) = delta_numeric_helper(
+        NumericType::Float32, 12, 64, &data_src_a[..], &data_src_b[..]);
+    assert_eq!(size_compacted_no_delta, data_src_a.len() * 2);
+    // the same offset is used for every coordinate,
+    // so each chunk is stored as a single run of differences.
+    let chunks_len = data_src_a.len() / (12 * 64);
+    assert_eq!(
+        size_compacted,
+        data_src_a.len() + (chunks_len * (12 + ::std::mem::size_of::<usize>())));
+}
+
+#[test]
+fn delta_numeric_int32() {
+    let values_a: Vec<i32> = (0..4096).map(|i| (i * 7919) % 1000).collect();
+    let mut values_b: Vec<i32> = values_a.iter().map(|i| i - 100).collect();
+    // some differences which don't repeat.
+    for i in 0..8 {
+        values_b[i * 300] = i as i32;
+    }
+    let data_src_a: Vec<u8> = values_a.iter().flat_map(|i| i.to_ne_bytes().to_vec()).collect();
+    let data_src_b: Vec<u8> = values_b.iter().flat_map(|i| i.to_ne_bytes().to_vec()).collect();
+
+    let (size_compacted, size_compacted_no_delta) = delta_numeric_helper(
+        NumericType::Int32, 8, 64, &data_src_a[..], &data_src_b[..]);
+    assert_eq!(size_compacted_no_delta, data_src_a.len() * 2);
+    assert!(size_compacted < (data_src_a.len() * 2) - (data_src_a.len() / 2));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn threads() {
+    let mut rng = rand::Rng::new(4321);
+    let data_src_a: Vec<u8> = rng.get_vec(1 << 19);
+    let mut data_src_b = data_src_a.clone();
+    // insertions so chunks don't align with the reference.
+    for i in 0..16 {
+        data_src_b.insert(i * 30011, i as u8);
+    }
+    let mut data_src_c = data_src_b.clone();
+    // re-order blocks, so chunks are found at different offsets.
+    {
+        let (head, tail) = data_src_c.split_at_mut(1 << 18);
+        head[..(1 << 17)].swap_with_slice(&mut tail[..(1 << 17)]);
+    }
+    data_src_c.truncate(data_src_c.len() - 1001);
+
+    let mut data_src_d = data_src_c.clone();
+    // changes within chunks (stored as deltas when enabled).
+    for i in 0..64 {
+        data_src_d[i * 4099] ^= 0xff;
+    }
+    // chunks are found using a reference with delta chunks.
+    let mut data_src_e = data_src_d.clone();
+    data_src_e.insert(77777, 1);
+
+    let mut sizes_compacted: Vec<usize> = Vec::new();
+    for &delta_strides_max in &[0, 4] {
+        let mut bs_serial = BArrayStore::new(1, 64);
+        let mut bs_threads = BArrayStore::new(1, 64);
+        bs_threads.threads_set(4);
+
+        let mut dumps: Vec<String> = Vec::new();
+        for bs in &mut [&mut bs_serial, &mut bs_threads] {
+            bs.delta_strides_max_set(delta_strides_max);
+            let state_a = bs.state_add(&data_src_a, None);
+            let state_b = bs.state_add(&data_src_b, Some(state_a));
+            let state_c = bs.state_add(&data_src_c, Some(state_b));
+            let state_d = bs.state_add(&data_src_d, Some(state_c));
+            let state_e = bs.state_add(&data_src_e, Some(state_d));
+            assert_eq!(Ok(()), bs.validate());
+            assert_eq!(bs.state_data_get_alloc(state_b), data_src_b);
+            assert_eq!(bs.state_data_get_alloc(state_c), data_src_c);
+            assert_eq!(bs.state_data_get_alloc(state_d), data_src_d);
+            assert_eq!(bs.state_data_get_alloc(state_e), data_src_e);
+            // most data is de-duplicated.
+            assert!(bs.calc_size_compacted_get() < data_src_a.len() * 2);
+
+            let mut table: Vec<u8> = Vec::new();
+            bs.debug_dump(&mut table, DumpFormat::Table).unwrap();
+            dumps.push(String::from_utf8(table).unwrap());
+        }
+        // threads must not change the result.
+        assert_eq!(dumps[0], dumps[1]);
+        sizes_compacted.push(bs_serial.calc_size_compacted_get());
+    }
+    // delta chunks were used.
+    assert!(sizes_compacted[1] < sizes_compacted[0]);
+}
+
+#[test]
+fn state_data_get_range() {
+    let stride = 4;
+    let mut cl: Vec<TestBuffer> = Vec::new();
+    let mut rng = rand::Rng::new(4321);
+    for _ in 0..32 {
+        testbuffer_list_state_random_data(&mut cl, stride, 0, 256 * stride, 4, &mut rng);
+    }
+
+    let mut bs = BArrayStore::new(stride, 8);
+    testbuffer_list_store_populate(&mut bs, &mut cl);
+    assert_eq!(Ok(()), bs.validate());
+
+    for tb in &cl {
+        let state = tb.state.unwrap();
+        let data_len = tb.data.len();
+        for _ in 0..16 {
+            let offset = rng.get::<usize>() % (data_len + 1);
+            let len = rng.get::<usize>() % ((data_len - offset) + 1);
+            let mut data_dst: Vec<u8> = vec![0; len];
+            bs.state_data_get_range(state, offset, &mut data_dst[..]);
+            assert_eq!(&data_dst[..], &tb.data[offset..(offset + len)]);
+        }
+        // the entire array.
+        let mut data_dst: Vec<u8> = vec![0; data_len];
+        bs.state_data_get_range(state, 0, &mut data_dst[..]);
+        assert_eq!(data_dst, tb.data);
+    }
+
+    testbuffer_list_store_clear(&mut bs, &mut cl);
+}
+
+#[test]
+fn states() {
+    let mut bs = BArrayStore::new(1, 8);
+    assert_eq!(0, bs.state_count());
+    assert_eq!(None, bs.states().next());
+
+    let mut states: Vec<StateId> = Vec::new();
+    for i in 0..8 {
+        let state_reference = states.last().cloned();
+        states.push(bs.state_add(&[i as u8; 16], state_reference));
+    }
+    assert_eq!(states, bs.states().collect::<Vec<StateId>>());
+
+    // remove the first, last & some in-between.
+    for &i in &[0, 7, 3, 4] {
+        bs.state_remove(states[i]);
+        assert!(!bs.contains(states[i]));
+    }
+    let mut states_expect: Vec<StateId> = vec![states[1], states[2], states[5], states[6]];
+    assert_eq!(states_expect, bs.states().collect::<Vec<StateId>>());
+    assert_eq!(4, bs.state_count());
+    assert_eq!(Ok(()), bs.validate());
+
+    // new states are added last (even when the memory of removed states is reused).
+    for i in 0..3 {
+        states_expect.push(bs.state_add(&[i as u8; 4], Some(states[1])));
+    }
+    assert_eq!(states_expect, bs.states().collect::<Vec<StateId>>());
+    assert_eq!(states_expect.len(), bs.state_count());
+    assert!(states_expect.iter().all(|&state| bs.contains(state)));
+    assert_eq!(Ok(()), bs.validate());
+
+    bs.clear();
+    assert_eq!(0, bs.state_count());
+    assert_eq!(None, bs.states().next());
+    assert!(!bs.contains(states_expect[0]));
+    let state = bs.state_add(b"abc", None);
+    assert_eq!(vec![state], bs.states().collect::<Vec<StateId>>());
+}
+
+#[test]
+fn state_parent() {
+    let mut bs = BArrayStore::new(1, 8);
+    let state_untracked_a = bs.state_add(b"aaaabbbbccccdddd", None);
+    let state_untracked_b = bs.state_add(b"aaaabbbbccccdddd", Some(state_untracked_a));
+    assert_eq!(None, bs.state_parent(state_untracked_b));
+    assert!(bs.state_children(state_untracked_a).is_empty());
+
+    bs.parent_tracking_set(true);
+    let state_a = bs.state_add(b"aaaabbbbccccdddd", None);
+    let state_b = bs.state_add(b"aaaabbbb####dddd", Some(state_a));
+    let state_c = bs.state_add(b"aaaabbbb####----", Some(state_b));
+    let state_d = bs.state_add_deferred(b"aaaabbbb########", Some(state_b));
+    let state_e = bs.state_add(b"aaaabbbbcccc....", Some(state_a));
+    assert_eq!(None, bs.state_parent(state_a));
+    assert_eq!(Some(state_a), bs.state_parent(state_b));
+    assert_eq!(Some(state_b), bs.state_parent(state_c));
+    assert_eq!(Some(state_b), bs.state_parent(state_d));
+    assert_eq!(&[state_b, state_e], bs.state_children(state_a));
+    assert_eq!(&[state_c, state_d], bs.state_children(state_b));
+    assert_eq!(Ok(()), bs.validate());
+
+    // children of removed states are re-parented.
+    bs.state_remove(state_b);
+    assert_eq!(Some(state_a), bs.state_parent(state_c));
+    assert_eq!(Some(state_a), bs.state_parent(state_d));
+    assert_eq!(&[state_e, state_c, state_d], bs.state_children(state_a));
+    assert_eq!(Ok(()), bs.validate());
+
+    bs.state_remove(state_a);
+    assert_eq!(None, bs.state_parent(state_c));
+    assert_eq!(None, bs.state_parent(state_e));
+    assert_eq!(Ok(()), bs.validate());
+    bs.dedup_pending(0);
+    assert_eq!(&b"aaaabbbb########"[..], &bs.state_data_get_alloc(state_d)[..]);
+    assert_eq!(Ok(()), bs.validate());
+}
+
+#[test]
+fn transaction() {
+    let mut bs = BArrayStore::new(1, 8);
+    bs.parent_tracking_set(true);
+    let state_a = bs.state_add(b"aaaabbbbccccdddd", None);
+    let state_b = bs.state_add(b"aaaabbbb####dddd", Some(state_a));
+
+    // commit.
+    let state_c = bs.transaction(|tx| -> Result<StateId, ()> {
+        let state_c = tx.state_add(b"aaaabbbb####----", Some(state_b));
+        tx.state_remove(state_a);
+        // removing is deferred.
+        assert!(tx.store().contains(state_a));
+        Ok(state_c)
+    }).unwrap();
+    assert!(!bs.contains(state_a));
+    assert_eq!(vec![state_b, state_c], bs.states().collect::<Vec<StateId>>());
+    assert_eq!(&b"aaaabbbb####----"[..], &bs.state_data_get_alloc(state_c)[..]);
+    assert_eq!(Ok(()), bs.validate());
+
+    // roll back, the store contents must be unchanged.
+    let size_compacted = bs.calc_size_compacted_get();
+    let size_expanded = bs.calc_size_expanded_get();
+    let mut states_added: Vec<StateId> = Vec::new();
+    let result: Result<(), &str> = bs.transaction(|tx| {
+        let state_d = tx.state_add(b"aaaabbbb####++++", Some(state_c));
+        let state_e = tx.state_add(b"xxxxyyyy####++++", Some(state_d));
+        tx.state_remove(state_b);
+        tx.state_remove(state_d);
+        states_added.extend_from_slice(&[state_d, state_e]);
+        Err("failed")
+    });
+    assert_eq!(Err("failed"), result);
+    assert!(states_added.iter().all(|&state| !bs.contains(state)));
+    assert_eq!(vec![state_b, state_c], bs.states().collect::<Vec<StateId>>());
+    assert!(bs.state_children(state_c).is_empty());
+    assert_eq!(size_compacted, bs.calc_size_compacted_get());
+    assert_eq!(size_expanded, bs.calc_size_expanded_get());
+    assert_eq!(Ok(()), bs.validate());
+
+    // roll back on panic.
+    let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+        let _: Result<(), ()> = bs.transaction(|tx| {
+            tx.state_add(b"aaaabbbb####....", Some(state_c));
+            panic!("failed");
+        });
+    }));
+    assert!(result.is_err());
+    assert_eq!(vec![state_b, state_c], bs.states().collect::<Vec<StateId>>());
+    assert_eq!(size_compacted, bs.calc_size_compacted_get());
+    assert_eq!(Ok(()), bs.validate());
+}
+
+#[test]
+fn fork() {
+    let mut bs = BArrayStore::new(1, 8);
+    let state_a = bs.state_add(b"aaaabbbbccccdddd", None);
+    let state_b = bs.state_add(b"aaaabbbb####dddd", Some(state_a));
+    let state_c = bs.state_add(b"aaaabbbb####----", Some(state_b));
+
+    let mut bs_fork = bs.fork();
+    assert_eq!(Ok(()), bs_fork.validate());
+    assert_eq!(vec![state_a, state_b, state_c], bs_fork.states().collect::<Vec<StateId>>());
+    assert_eq!(bs.calc_size_compacted_get(), bs_fork.calc_size_compacted_get());
+    assert_eq!(bs.calc_size_expanded_get(), bs_fork.calc_size_expanded_get());
+
+    // save in the background while the original store changes.
+    let handle = ::std::thread::spawn(move || {
+        let state_d = bs_fork.state_add(b"aaaabbbb####++++", Some(state_c));
+        bs_fork.state_remove(state_b);
+        assert_eq!(Ok(()), bs_fork.validate());
+        return (
+            bs_fork.state_data_get_alloc(state_a),
+            bs_fork.state_data_get_alloc(state_c),
+            bs_fork.state_data_get_alloc(state_d),
+        );
+    });
+
+    bs.state_remove(state_a);
+    bs.state_remove(state_c);
+    let state_d = bs.state_add(b"xxxxbbbb####dddd", Some(state_b));
+    bs.compact();
+    assert_eq!(Ok(()), bs.validate());
+    assert_eq!(vec![state_b, state_d], bs.states().collect::<Vec<StateId>>());
+    assert_eq!(&b"aaaabbbb####dddd"[..], &bs.state_data_get_alloc(state_b)[..]);
+    assert_eq!(&b"xxxxbbbb####dddd"[..], &bs.state_data_get_alloc(state_d)[..]);
+
+    let (data_a, data_c, data_d) = handle.join().unwrap();
+    assert_eq!(&b"aaaabbbbccccdddd"[..], &data_a[..]);
+    assert_eq!(&b"aaaabbbb####----"[..], &data_c[..]);
+    assert_eq!(&b"aaaabbbb####++++"[..], &data_d[..]);
+}
+
+#[test]
+fn fork_random_data() {
+    let mut rng = rand::Rng::new(1331);
+    let mut cl: Vec<TestBuffer> = Vec::new();
+    let mut bs = BArrayStore::new(4, 16);
+    for _ in 0..32 {
+        testbuffer_list_state_random_data(&mut cl, 4, 0, 256, 2, &mut rng);
+    }
+    testbuffer_list_store_populate(&mut bs, &mut cl);
+
+    let mut bs_fork = bs.fork();
+    // appending to chunks shared with the fork must not change the fork's data.
+    for tb in cl.iter().skip(1).step_by(2) {
+        bs.state_remove(tb.state.unwrap());
+    }
+    for (i, tb) in cl.iter().enumerate().filter(|&(i, _)| i % 2 == 0) {
+        let mut data = tb.data.clone();
+        data.extend_from_slice(&[i as u8; 12]);
+        bs.state_add(&data[..], tb.state);
+    }
+    bs.compact();
+    assert_eq!(Ok(()), bs.validate());
+
+    for tb in &cl {
+        assert_eq!(&tb.data[..], &bs_fork.state_data_get_alloc(tb.state.unwrap())[..]);
+    }
+    bs_fork.clear();
+    for tb in cl.iter().step_by(2) {
+        assert_eq!(&tb.data[..], &bs.state_data_get_alloc(tb.state.unwrap())[..]);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn chunk_pool() {
+    let pool = BArrayChunkPool::new(1, 8);
+    let (data_a, data_b, data_c, data_d) = (b"aaaaaaaa", b"bbbbbbbb", b"cccccccc", b"dddddddd");
+    let data_abc: Vec<u8> = [&data_a[..], &data_b[..], &data_c[..]].concat();
+    let data_cad: Vec<u8> = [&data_c[..], &data_a[..], &data_d[..]].concat();
+
+    let mut bs_a = BArrayStore::new(1, 8);
+    bs_a.chunk_pool_set(Some(pool.clone()));
+    let state_abc = bs_a.state_add(&data_abc[..], None);
+
+    // existing chunks are added when the pool is set.
+    let mut bs_b = BArrayStore::new(1, 8);
+    let state_cad = bs_b.state_add(&data_cad[..], None);
+    bs_b.chunk_pool_set(Some(pool.clone()));
+    assert!(bs_b.chunk_pool_get().is_some());
+
+    // identical chunks are stored once, each store's statistics are unchanged.
+    assert_eq!(4, pool.len());
+    assert_eq!(32, pool.calc_size_get());
+    assert_eq!(24, bs_a.calc_size_compacted_get());
+    assert_eq!(24, bs_b.calc_size_compacted_get());
+
+    // new states use the pool too.
+    let state_dd = bs_a.state_add(&[&data_d[..], &data_d[..]].concat()[..], Some(state_abc));
+    assert_eq!(4, pool.len());
+    assert_eq!(40, bs_a.calc_size_compacted_get());
+
+    // clearing a store only frees chunks no other store uses.
+    bs_a.clear();
+    assert!(!bs_a.contains(state_dd));
+    assert_eq!(3, pool.len());
+    assert_eq!(24, pool.calc_size_get());
+    assert_eq!(&data_cad[..], &bs_b.state_data_get_alloc(state_cad)[..]);
+    assert_eq!(Ok(()), bs_b.validate());
+
+    bs_b.state_remove(state_cad);
+    assert!(pool.is_empty());
+    assert_eq!(0, pool.calc_size_get());
+
+    // data imported from a store using another pool is added to this pool too.
+    let pool_other = BArrayChunkPool::new(1, 8);
+    let mut bs_other = BArrayStore::new(1, 8);
+    bs_other.chunk_pool_set(Some(pool_other.clone()));
+    let state_other = bs_other.state_add(&data_abc[..], None);
+    let state_abc = bs_a.state_import(&bs_other, state_other, None);
+    assert_eq!(3, pool.len());
+    assert_eq!(3, pool_other.len());
+    assert_eq!(&data_abc[..], &bs_a.state_data_get_alloc(state_abc)[..]);
+    assert_eq!(Ok(()), bs_a.validate());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn chunk_pool_random_data() {
+    let pool = BArrayChunkPool::new(4, 16);
+    let mut stores: Vec<BArrayStore> = (0..3).map(|_| BArrayStore::new(4, 16)).collect();
+    let mut cl: Vec<TestBuffer> = Vec::new();
+    {
+        let mut rng = rand::Rng::new(9779);
+        for _ in 0..16 {
+            testbuffer_list_state_random_data(&mut cl, 4, 0, 256, 2, &mut rng);
+        }
+    }
+    // every store gets the same data, so each chunk is shared by all stores.
+    let mut states: Vec<Vec<StateId>> = Vec::new();
+    for bs in &mut stores {
+        bs.chunk_pool_set(Some(pool.clone()));
+        testbuffer_list_store_populate(bs, &mut cl);
+        states.push(cl.iter().map(|tb| tb.state.unwrap()).collect());
+    }
+    assert_eq!(stores[0].calc_size_compacted_get(), pool.calc_size_get());
+
+    // editing one store doesn't change the others.
+    for &state in states[0].iter().step_by(2) {
+        stores[0].state_remove(state);
+    }
+    stores[0].compact();
+    // re-chunked data is only shared when it matches chunks in the other stores.
+    assert!(pool.calc_size_get() >= stores[1].calc_size_compacted_get());
+    assert!(pool.calc_size_get() <= stores[0].calc_size_compacted_get() + stores[1].calc_size_compacted_get());
+    for (bs, states_store) in stores.iter().zip(&states).skip(1) {
+        assert_eq!(Ok(()), bs.validate());
+        for (tb, &state) in cl.iter().zip(states_store) {
+            assert_eq!(&tb.data[..], &bs.state_data_get_alloc(state)[..]);
+        }
+    }
+    stores[1].clear();
+    stores[2].clear();
+    assert_eq!(stores[0].calc_size_compacted_get(), pool.calc_size_get());
+}
+
+#[test]
+fn state_import() {
+    let mut rng = rand::Rng::new(7117);
+    let mut cl: Vec<TestBuffer> = Vec::new();
+    let mut bs_src = BArrayStore::new(4, 16);
+    for _ in 0..24 {
+        testbuffer_list_state_random_data(&mut cl, 4, 0, 512, 2, &mut rng);
+    }
+    testbuffer_list_store_populate(&mut bs_src, &mut cl);
+    let state_src_first = cl[0].state.unwrap();
+    bs_src.state_metadata_set(state_src_first, b"first".to_vec());
+
+    // chunks are shared the same way as in the source store.
+    let mut bs_dst = BArrayStore::new(4, 16);
+    bs_dst.parent_tracking_set(true);
+    let mut states_dst: Vec<StateId> = Vec::new();
+    let mut state_prev: Option<StateId> = None;
+    for tb in &cl {
+        let state = bs_dst.state_import(&bs_src, tb.state.unwrap(), state_prev);
+        states_dst.push(state);
+        state_prev = Some(state);
+    }
+    assert_eq!(Ok(()), bs_dst.validate());
+    assert_eq!(bs_src.calc_size_expanded_get(), bs_dst.calc_size_expanded_get());
+    assert_eq!(bs_src.calc_size_compacted_get(), bs_dst.calc_size_compacted_get());
+    assert_eq!(b"first", bs_dst.state_metadata_get(states_dst[0]));
+    assert_eq!(Some(states_dst[0]), bs_dst.state_parent(states_dst[1]));
+
+    // move, the data is still available after removing the source states.
+    bs_src.clear();
+    for (tb, &state) in cl.iter().zip(&states_dst) {
+        assert_eq!(&tb.data[..], &bs_dst.state_data_get_alloc(state)[..]);
+    }
+
+    // importing identical data re-uses the reference chunks.
+    let size_compacted = bs_dst.calc_size_compacted_get();
+    let state_src = bs_src.state_add(&cl.last().unwrap().data[..], None);
+    let state = bs_dst.state_import(&bs_src, state_src, state_prev);
+    assert_eq!(size_compacted, bs_dst.calc_size_compacted_get());
+    assert_eq!(&cl.last().unwrap().data[..], &bs_dst.state_data_get_alloc(state)[..]);
+    assert_eq!(Ok(()), bs_dst.validate());
+}
+
+#[test]
+fn state_import_delta() {
+    let mut bs_src = BArrayStore::new(1, 8);
+    bs_src.delta_strides_max_set(2);
+    let state_a = bs_src.state_add(b"aaaaaaaabbbbbbbbcccccccc", None);
+    let state_b = bs_src.state_add(b"aaaaaaaabbbb##bbcccccccc", Some(state_a));
+
+    // delta chunks are expanded.
+    let mut bs_dst = BArrayStore::new(1, 8);
+    let state_dst_a = bs_dst.state_import(&bs_src, state_a, None);
+    let state_dst_b = bs_dst.state_import(&bs_src, state_b, Some(state_dst_a));
+    assert_eq!(Ok(()), bs_dst.validate());
+    assert_eq!(32, bs_dst.calc_size_compacted_get());
+    assert_eq!(&b"aaaaaaaabbbb##bbcccccccc"[..], &bs_dst.state_data_get_alloc(state_dst_b)[..]);
+
+    // a different chunk size is supported (the data is re-chunked).
+    let mut bs_dst = BArrayStore::new(1, 4);
+    let state_dst_a = bs_dst.state_import(&bs_src, state_a, None);
+    let state_dst_b = bs_dst.state_import(&bs_src, state_b, Some(state_dst_a));
+    assert_eq!(Ok(()), bs_dst.validate());
+    assert_eq!(&b"aaaaaaaabbbbbbbbcccccccc"[..], &bs_dst.state_data_get_alloc(state_dst_a)[..]);
+    assert_eq!(&b"aaaaaaaabbbb##bbcccccccc"[..], &bs_dst.state_data_get_alloc(state_dst_b)[..]);
+}
+
+#[test]
+fn state_ref() {
+    let mut bs = BArrayStore::new(1, 8);
+    bs.delta_strides_max_set(2);
+    let data_a: &[u8] = b"aaaaaaaabbbbbbbbcccccccc";
+    let data_b: &[u8] = b"aaaaaaaabbbb##bbcccccccc";
+    let state_a = bs.state_add(data_a, None);
+    let state_b = bs.state_add(data_b, Some(state_a));
+    bs.state_metadata_set(state_b, b"b".to_vec());
+
+    let state_ref = bs.state_ref(state_b);
+    assert_eq!(state_b, state_ref.id());
+    assert_eq!(data_b.len(), state_ref.len());
+    assert!(!state_ref.is_empty());
+    assert_eq!(data_b, &state_ref.to_vec()[..]);
+    assert_eq!(b"b", state_ref.metadata());
+
+    // chunks (including delta chunks) make up the whole array.
+    assert_eq!(data_b, &state_ref.chunks().collect::<Vec<_>>().concat()[..]);
+
+    let mut data = vec![0; state_ref.len()];
+    state_ref.copy_to(&mut data[..]);
+    assert_eq!(data_b, &data[..]);
+    let mut data = vec![0; 6];
+    state_ref.read_range(10, &mut data[..]);
+    assert_eq!(&data_b[10..16], &data[..]);
+
+    let state_refs: Vec<StateRef> = bs.state_refs().collect();
+    assert_eq!(vec![state_a, state_b], state_refs.iter().map(|s| s.id()).collect::<Vec<StateId>>());
+    assert_eq!(data_a, &state_refs[0].to_vec()[..]);
+
+    // views must be dropped before the store can be changed.
+    bs.state_remove(state_a);
+    assert_eq!(data_b, &bs.state_ref(state_b).to_vec()[..]);
+    assert!(bs.state_ref(state_b).chunks().count() > 1);
+}
+
+#[test]
+fn state_metadata() {
+    let mut bs = BArrayStore::new(1, 8);
+    let state_a = bs.state_add(b"aaaabbbbccccdddd", None);
+    let state_b = bs.state_add(b"aaaabbbb####dddd", Some(state_a));
+    let state_c = bs.state_add(b"aaaabbbb####dddd", Some(state_b));
+    assert_eq!(b"", bs.state_metadata_get(state_a));
+
+    bs.state_metadata_set(state_a, b"step 1".to_vec());
+    bs.state_metadata_set(state_b, b"step 2".to_vec());
+    bs.state_metadata_set(state_b, b"step 2 (replaced)".to_vec());
+    assert_eq!(b"step 1", bs.state_metadata_get(state_a));
+    assert_eq!(b"step 2 (replaced)", bs.state_metadata_get(state_b));
+    assert_eq!(b"", bs.state_metadata_get(state_c));
+
+    bs.state_remove(state_a);
+    let metadata: Vec<(StateId, Vec<u8>)> =
+        bs.state_metadata_iter().map(|(state, metadata)| (state, metadata.to_vec())).collect();
+    assert_eq!(vec![(state_b, b"step 2 (replaced)".to_vec()), (state_c, Vec::new())], metadata);
+    assert_eq!(Ok(()), bs.validate());
+}
+
+#[test]
+fn calc_size_unique() {
+    let stride = 4;
+    let chunk_count = 16;
+    let mut cl: Vec<TestBuffer> = Vec::new();
+    let mut rng = rand::Rng::new(1234);
+    for _ in 0..32 {
+        testbuffer_list_state_random_data(&mut cl, stride, 0, 256 * stride, 4, &mut rng);
+    }
+    // small changes spread over many chunks (stored as deltas when enabled).
+    let mut data: Vec<u8> = (0..(stride * chunk_count * 16)).map(|i| (i % 251) as u8).collect();
+    for j in 0..8 {
+        for i in 0..8 {
+            let offset = (i * 2 * chunk_count * stride) + (stride * j);
+            data[offset] = !data[offset];
+        }
+        testbuffer_list_state_from_data(&mut cl, &data[..]);
+    }
+
+    for &delta_strides_max in &[0, 4] {
+        let mut bs = BArrayStore::new(stride, chunk_count);
+        bs.delta_strides_max_set(delta_strides_max);
+        testbuffer_list_store_populate(&mut bs, &mut cl);
+        // a state which shares its chunk list.
+        let tb_last = &cl[cl.len() - 1];
+        let state_dupe = bs.state_add(&tb_last.data[..], tb_last.state);
+        assert_eq!(0, bs.calc_size_unique_get(state_dupe));
+        assert_eq!(0, bs.calc_size_unique_get(tb_last.state.unwrap()));
+        bs.state_remove(state_dupe);
+
+        // remove states in an order which doesn't match the order they were added.
+        let mut states: Vec<StateId> = cl.iter().map(|tb| tb.state.unwrap()).collect();
+        for i in 0..states.len() {
+            let j = rng.get::<usize>() % states.len();
+            states.swap(i, j);
+        }
+        for state in states {
+            let size_unique = bs.calc_size_unique_get(state);
+            let size_compacted = bs.calc_size_compacted_get();
+            bs.state_remove(state);
+            assert_eq!(size_compacted - size_unique, bs.calc_size_compacted_get());
+        }
+        assert_eq!(0, bs.calc_size_compacted_get());
+        for tb in &mut cl {
+            tb.state = None;
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() {
+    let stride = 4;
+    let chunk_count = 16;
+    let data_src_a: Vec<u8> = (0..(stride * chunk_count * 16)).map(|i| (i % 251) as u8).collect();
+    let mut data_src_b: Vec<u8> = data_src_a.clone();
+    for i in 0..8 {
+        let offset = (i * 2 * chunk_count * stride) + (stride * 3);
+        data_src_b[offset] = !data_src_b[offset];
+    }
+    let data_src_c: Vec<u8> = data_src_b[(stride * chunk_count)..].to_vec();
+
+    let mut bs = BArrayStore::new(stride, chunk_count);
+    bs.delta_strides_max_set(4);
+    bs.parent_tracking_set(true);
+    let state_a = bs.state_add(&data_src_a[..], None);
+    let state_b = bs.state_add(&data_src_b[..], Some(state_a));
+    // leave an unused slot, ids must match after reading back.
+    let state_removed = bs.state_add(&data_src_a[..(stride * 8)], None);
+    let state_c = bs.state_add_deferred(&data_src_c[..], Some(state_b));
+    bs.state_remove(state_removed);
+    bs.state_metadata_set(state_b, b"state b".to_vec());
+    assert_eq!(Ok(()), bs.validate());
+
+    let text = serde_json::to_string(&bs).unwrap();
+    assert!(text.contains("Patch"));
+    let mut bs_copy: BArrayStore = serde_json::from_str(&text).unwrap();
+    assert_eq!(Ok(()), bs_copy.validate());
+    // chunks shared between states are still shared.
+    assert_eq!(bs.calc_size_compacted_get(), bs_copy.calc_size_compacted_get());
+    assert_eq!(bs.calc_size_expanded_get(), bs_copy.calc_size_expanded_get());
+    assert_eq!(bs.states().collect::<Vec<StateId>>(), bs_copy.states().collect::<Vec<StateId>>());
+    assert_eq!(bs_copy.state_data_get_alloc(state_a), data_src_a);
+    assert_eq!(bs_copy.state_data_get_alloc(state_b), data_src_b);
+    assert_eq!(bs_copy.state_data_get_alloc(state_c), data_src_c);
+    assert_eq!(b"state b", bs_copy.state_metadata_get(state_b));
+    assert_eq!(Some(state_a), bs_copy.state_parent(state_b));
+    assert_eq!(Some(state_b), bs_copy.state_parent(state_c));
+    assert_eq!(&[state_b], bs_copy.state_children(state_a));
+    assert_eq!(b"", bs_copy.state_metadata_get(state_a));
+    assert_eq!(1, bs_copy.dedup_pending_len());
+    bs_copy.dedup_pending(0);
+    assert_eq!(0, bs_copy.dedup_pending_len());
+    assert_eq!(Ok(()), bs_copy.validate());
+    assert_eq!(bs_copy.state_data_get_alloc(state_c), data_src_c);
+    assert_eq!(
+        bs.state_add(&data_src_a[..], None),
+        bs_copy.state_add(&data_src_a[..], None));
+
+    // a single state.
+    let mut data_state: Vec<u8> = Vec::new();
+    bs.state_serialize(state_b, &mut serde_json::Serializer::new(&mut data_state)).unwrap();
+    let size_compacted = bs_copy.calc_size_compacted_get();
+    let state_d = bs_copy.state_deserialize(
+        &mut serde_json::Deserializer::from_slice(&data_state[..]), Some(state_b)).unwrap();
+    assert_eq!(bs_copy.state_data_get_alloc(state_d), data_src_b);
+    assert_eq!(size_compacted, bs_copy.calc_size_compacted_get());
+    assert_eq!(Ok(()), bs_copy.validate());
+
+    // invalid input is an error.
+    assert!(bs_copy.state_deserialize(
+        &mut serde_json::Deserializer::from_str("[1, 2, 3]"), None).is_err());
+    assert!(serde_json::from_str::<BArrayStore>(
+        "{\"stride\": 4, \"chunk_count\": 16, \"delta_strides_max\": 0, \"delta_numeric\": null, \
+         \"chunks\": [], \"chunk_lists\": [[0]], \"states\": [[0, 0]], \"states_pending\": []}").is_err());
+    // sizes which overflow are an error.
+    assert!(serde_json::from_str::<BArrayStore>(
+        "{\"stride\": 4, \"chunk_count\": 18446744073709551615, \"delta_strides_max\": 0, \"delta_numeric\": null, \
+         \"parent_tracking\": false, \"chunks\": [], \"chunk_lists\": [], \
+         \"states\": [], \"states_order\": [], \"states_pending\": []}").is_err());
+    // the generation of retired slots can't be used by a state.
+    assert!(serde_json::from_str::<BArrayStore>(
+        "{\"stride\": 4, \"chunk_count\": 16, \"delta_strides_max\": 0, \"delta_numeric\": null, \
+         \"parent_tracking\": false, \"chunks\": [{\"Data\": [97, 97, 97, 97]}], \"chunk_lists\": [[0]], \
+         \"states\": [[4294967295, {\"chunk_list\": 0, \"metadata\": [], \"children\": []}]], \
+         \"states_order\": [[0, 4294967295]], \"states_pending\": []}").is_err());
+    // patch runs which aren't sorted, overlap, are empty or unaligned are an error.
+    {
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let patch_index = value["chunks"].as_array().unwrap().iter()
+            .position(|chunk| chunk.get("Patch").is_some()).unwrap();
+        let patch_runs_set = |runs: &[(usize, usize)]| {
+            let mut value = value.clone();
+            let patch = &mut value["chunks"][patch_index]["Patch"];
+            let data_len: usize = runs.iter().map(|run| run.1).sum();
+            patch["runs"] = serde_json::to_value(runs).unwrap();
+            patch["data"] = serde_json::to_value(vec![0_u8; data_len]).unwrap();
+            serde_json::from_value::<BArrayStore>(value)
+        };
+        let run = serde_json::from_value::<Vec<(usize, usize)>>(
+            value["chunks"][patch_index]["Patch"]["runs"].clone()).unwrap()[0];
+        let run_next = (run.0 + run.1 + stride, stride);
+        assert!(patch_runs_set(&[run, run_next]).is_ok());
+        assert!(patch_runs_set(&[run_next, run]).is_err());
+        assert!(patch_runs_set(&[run, run]).is_err());
+        assert!(patch_runs_set(&[run, (run_next.0, 0)]).is_err());
+        assert!(patch_runs_set(&[(run.0 + 1, run.1)]).is_err());
+        assert!(patch_runs_set(&[(run.0, run.1 + 1)]).is_err());
+    }
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli() {
+    use std::process::Command;
+    let dir = std::env::temp_dir().join(format!("bacow_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let data_src_a: Vec<u8> = (0..(4 * 4096)).map(|i| (i % 251) as u8).collect();
+    let mut data_src_b: Vec<u8> = data_src_a.clone();
+    data_src_b[1000] = !data_src_b[1000];
+    let files = [dir.join("a.bin"), dir.join("b.bin"), dir.join("c.bin")];
+    std::fs::write(&files[0], &data_src_a).unwrap();
+    std::fs::write(&files[1], &data_src_b).unwrap();
+    std::fs::write(&files[2], &data_src_a[..(4 * 100)]).unwrap();
+    let archive = dir.join("test.bacow");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+        .args(&["--stride", "4", "--chunk-count=32", "-o"]).arg(&archive).args(&files)
+        .output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(5, lines.len());
+    let total: Vec<&str> = lines[4].split_whitespace().collect();
+    assert_eq!((data_src_a.len() * 2 + 400).to_string(), total[0]);
+    assert!(total[1].parse::<usize>().unwrap() < data_src_a.len() + (data_src_a.len() / 4));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+        .arg("list").arg(&archive).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(5, lines.len());
+    // state, size, unique, name.
+    let state_c: Vec<&str> = lines[3].split_whitespace().collect();
+    assert_eq!(&["2", "400"], &state_c[..2]);
+    // only the last chunk isn't shared with the first state.
+    assert!(state_c[2].parse::<usize>().unwrap() < 4 * 32);
+
+    let extract = dir.join("b_extract.bin");
+    let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+        .arg("extract").arg(&archive).args(&["--state", "1", "-o"]).arg(&extract).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(data_src_b, std::fs::read(&extract).unwrap());
+    let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+        .arg("extract").arg(&archive).arg("--state=2").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(&data_src_a[..(4 * 100)], &output.stdout[..]);
+    let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+        .arg("extract").arg(&archive).args(&["--state", "3"]).output().unwrap();
+    assert!(!output.status.success());
+
+    // a file referring to a state which isn't in the store.
+    let mut archive_data = std::fs::read(&archive).unwrap();
+    let name = files[0].to_str().unwrap().as_bytes();
+    let offset = archive_data.windows(name.len()).position(|w| w == name).unwrap() + name.len();
+    // the state's (index, generation).
+    archive_data[offset + 4] += 1;
+    let archive_missing = dir.join("missing.bacow");
+    std::fs::write(&archive_missing, &archive_data).unwrap();
+    for command in &["list", "verify"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+            .arg(command).arg(&archive_missing).output().unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8(output.stderr).unwrap().contains("state 0 not found"));
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+        .arg("verify").arg(&archive).output().unwrap();
+    assert!(output.status.success());
+    // corrupt a chunk.
+    let mut archive_data = std::fs::read(&archive).unwrap();
+    let offset = archive_data.windows(64).position(|w| w == &data_src_a[..64]).unwrap();
+    archive_data[offset] ^= 0xff;
+    std::fs::write(&archive, &archive_data).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+        .arg("verify").arg(&archive).output().unwrap();
+    assert!(!output.status.success());
+    // the first chunk, shared by all states.
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    for i in 0..3 {
+        assert!(stdout.contains(&format!("state {}: chunk 0 checksum mismatch at offset 0", i)));
+    }
+    assert!(!stdout.contains("chunk 1 "));
+
+    // a length larger than the archive is an error (instead of allocating).
+    let mut archive_data = std::fs::read(&archive).unwrap();
+    let offset = archive_data.windows(name.len()).position(|w| w == name).unwrap();
+    archive_data[(offset - 8)..offset].copy_from_slice(&(1_u64 << 40).to_le_bytes());
+    std::fs::write(&archive_missing, &archive_data).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+        .arg("list").arg(&archive_missing).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("bacow: "));
+
+    // sizes must be a multiple of the stride.
+    let output = Command::new(env!("CARGO_BIN_EXE_bacow"))
+        .args(&["--stride", "3"]).args(&files).output().unwrap();
+    assert!(!output.status.success());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn at_size() {
+    let mut bs_stride = BArrayStoreAtSize::new();
+    assert!(bs_stride.is_empty());
+
+    let data_src_a = b"aaaabbbbccccdddd";
+    let data_src_b = b"aaaabbbb####dddd";
+
+    let state_a = bs_stride.ensure(1, 4).state_add(data_src_a, None);
+    let state_b = bs_stride.ensure(1, 4).state_add(data_src_b, Some(state_a));
+    let state_c = bs_stride.ensure(4, 1).state_add(data_src_a, None);
+    let state_d = bs_stride.ensure(1, 8).state_add(data_src_b, None);
+    assert_eq!(3, bs_stride.len());
+    assert!(bs_stride.get(2, 4).is_none());
+
+    assert_eq!(bs_stride.calc_size_expanded_get(), data_src_a.len() * 4);
+    assert_eq!(bs_stride.calc_size_compacted_get(), (data_src_a.len() * 3) + 4);
+    for (_, bs) in bs_stride.iter() {
+        assert_eq!(Ok(()), bs.validate());
+    }
+
+    assert_eq!(&bs_stride.get(1, 4).unwrap().state_data_get_alloc(state_b)[..], &data_src_b[..]);
+    assert_eq!(&bs_stride.get(4, 1).unwrap().state_data_get_alloc(state_c)[..], &data_src_a[..]);
+
+    bs_stride.get_mut(1, 4).unwrap().state_remove(state_a);
+    bs_stride.get_mut(1, 8).unwrap().state_remove(state_d);
+    assert_eq!(bs_stride.calc_size_expanded_get(), data_src_a.len() * 2);
+
+    bs_stride.clear();
+    assert!(bs_stride.is_empty());
+    assert_eq!(0, bs_stride.calc_size_compacted_get());
+}
+
+#[test]
+fn snapshot() {
+    let mut bs_stride = BArrayStoreAtSize::new();
+
+    let verts_a: Vec<u8> = (0..4092).map(|i| (i % 251) as u8).collect();
+    let mut verts_b = verts_a.clone();
+    verts_b[100] = 0xff;
+    let edges = b"aaaabbbbccccdddd";
+
+    let snapshot_a = bs_stride.snapshot_add(None, &[
+        BArraySnapshotArray { key: "mesh.verts", stride: 12, chunk_count: 32, data: &verts_a },
+        BArraySnapshotArray { key: "mesh.edges", stride: 8, chunk_count: 32, data: edges },
+    ]).unwrap();
+    let size_a = bs_stride.calc_size_compacted_get();
+    let snapshot_b = bs_stride.snapshot_add(Some(&snapshot_a), &[
+        BArraySnapshotArray { key: "mesh.verts", stride: 12, chunk_count: 32, data: &verts_b },
+        BArraySnapshotArray { key: "mesh.edges", stride: 8, chunk_count: 32, data: edges },
+    ]).unwrap();
+    // The second snapshot mostly de-duplicates against the first.
+    assert!(bs_stride.calc_size_compacted_get() - size_a < verts_a.len() / 2);
+    assert_eq!(2, bs_stride.len());
+    assert_eq!(2, snapshot_b.len());
+
+    {
+        let mut keys: Vec<&str> = snapshot_b.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["mesh.edges", "mesh.verts"]);
+    }
+    assert_eq!(bs_stride.snapshot_data_get_alloc(&snapshot_a, "mesh.verts").unwrap(), verts_a);
+    assert_eq!(bs_stride.snapshot_data_get_alloc(&snapshot_b, "mesh.verts").unwrap(), verts_b);
+    assert_eq!(&bs_stride.snapshot_data_get_alloc(&snapshot_b, "mesh.edges").unwrap()[..], &edges[..]);
+    assert!(snapshot_b.state_get("mesh.faces").is_none());
+
+    // Keys which changed stride are stored without a reference.
+    let snapshot_c: BArraySnapshot = bs_stride.snapshot_add(Some(&snapshot_b), &[
+        BArraySnapshotArray { key: "mesh.verts", stride: 4, chunk_count: 32, data: &verts_b },
+    ]).unwrap();
+    assert_eq!(3, bs_stride.len());
+
+    bs_stride.snapshot_remove(snapshot_a);
+    assert_eq!(bs_stride.snapshot_data_get_alloc(&snapshot_b, "mesh.verts").unwrap(), verts_b);
+    bs_stride.snapshot_remove(snapshot_c);
+    bs_stride.snapshot_remove(snapshot_b);
+    for (_, bs) in bs_stride.iter() {
+        assert_eq!(Ok(()), bs.validate());
+    }
+    assert_eq!(0, bs_stride.calc_size_expanded_get());
+    assert_eq!(0, bs_stride.calc_size_compacted_get());
+}
+
+#[test]
+fn snapshot_duplicate_key() {
+    let mut bs_stride = BArrayStoreAtSize::new();
+    let verts: Vec<u8> = (0..1200).map(|i| (i % 251) as u8).collect();
+
+    // nothing is added when a key is used more than once.
+    let snapshot = bs_stride.snapshot_add(None, &[
+        BArraySnapshotArray { key: "mesh.verts", stride: 12, chunk_count: 32, data: &verts },
+        BArraySnapshotArray { key: "mesh.edges", stride: 8, chunk_count: 32, data: b"aaaabbbb" },
+        BArraySnapshotArray { key: "mesh.verts", stride: 12, chunk_count: 32, data: &verts },
+    ]);
+    assert_eq!(Some("mesh.verts"), snapshot.err());
+    assert!(bs_stride.is_empty());
+    assert_eq!(0, bs_stride.calc_size_expanded_get());
+}
+
+#[test]
+#[should_panic(expected = "Snapshot not found in this store")]
+fn snapshot_remove_other_store() {
+    let mut bs_stride_a = BArrayStoreAtSize::new();
+    let mut bs_stride_b = BArrayStoreAtSize::new();
+    let snapshot = bs_stride_a.snapshot_add(None, &[
+        BArraySnapshotArray { key: "mesh.edges", stride: 8, chunk_count: 32, data: b"aaaabbbb" },
+    ]).unwrap();
+    bs_stride_b.snapshot_remove(snapshot);
+}
+
 #[test]
 fn text_mixed() {
     testbuffer_strings!(1, 4, vec![b""]);
@@ -351,7 +1478,7 @@ fn text_dupe_increase_decrease() {
 
     // forward
     testbuffer_list_store_populate(&mut bs, &mut cl);
-    assert!(testbuffer_list_validate(&mut cl));
+    assert!(testbuffer_list_validate(&bs, &mut cl));
     assert!(bs.is_valid());
     assert_eq!(bs.calc_size_compacted_get(), chunk_size);
 
@@ -360,7 +1487,7 @@ fn text_dupe_increase_decrease() {
 
     // backwards
     testbuffer_list_store_populate(&mut bs, &mut cl);
-    assert!(testbuffer_list_validate(&mut cl));
+    assert!(testbuffer_list_validate(&bs, &mut cl));
     assert!(bs.is_valid());
     // larger since first block doesn't de-duplicate
     assert_eq!(bs.calc_size_compacted_get(), chunk_size * 4);
@@ -456,21 +1583,11 @@ fn rand_range_i(rng: &mut rand::Rng, min_i: usize, max_i: usize, step: usize) ->
  * Access via #BLI_array_wrap
  */
 fn array_wrap(arr: &mut [u8], arr_len: usize, arr_stride: usize, reverse: bool) {
-    use ::std::ptr;
-    let arr_sub = arr_len - 1;
-    let mut buf: Vec<u8> = Vec::with_capacity(arr_stride);
-    unsafe { buf.set_len(arr_stride); }
-
-    unsafe {
-        if !reverse {
-            ptr::copy_nonoverlapping(&arr[0], &mut buf[0], arr_stride);
-            ptr::copy(&arr[arr_stride], &mut arr[0], arr_stride * arr_sub);
-            ptr::copy_nonoverlapping(&buf[0], &mut arr[arr_stride * arr_sub], arr_stride);
-        } else {
-            ptr::copy_nonoverlapping(&arr[arr_stride * arr_sub], &mut buf[0], arr_stride);
-            ptr::copy(&arr[0], &mut arr[arr_stride], arr_stride * arr_sub);
-            ptr::copy_nonoverlapping(&buf[0], &mut arr[0], arr_stride);
-        }
+    let arr = &mut arr[0..(arr_len * arr_stride)];
+    if !reverse {
+        arr.rotate_left(arr_stride);
+    } else {
+        arr.rotate_right(arr_stride);
     }
 }
 
@@ -480,10 +1597,8 @@ fn testbuffer_list_state_random_data(
     data_min_len: usize, data_max_len: usize,
     mutate: usize, rng: &mut rand::Rng,
 ) {
-    use ::std::ptr;
     let mut data_len: usize = rand_range_i(rng, data_min_len, data_max_len + stride, stride);
-    let mut data: Vec<u8> = Vec::with_capacity(data_len);
-    unsafe { data.set_len(data_len); }
+    let mut data: Vec<u8> = vec![0; data_len];
     if cl.is_empty() {
         rng.fill(&mut data[..]);
     } else {
@@ -509,18 +1624,9 @@ fn testbuffer_list_state_random_data(
                     let offset: usize = rand_range_i(rng, 0, data_len, stride);
                     if data_len < data_max_len {
                         data_len += stride;
-                        data.reserve(stride);
-                        unsafe {
-                            data.set_len(data_len);
-                        }
+                        data.resize(data_len, 0);
                         if offset + stride < data_len {
-                            unsafe {
-                                ptr::copy(
-                                    &data[offset],
-                                    &mut data[offset + stride],
-                                    data_len - (offset + stride),
-                                );
-                            }
+                            data.copy_within(offset..(data_len - stride), offset + stride);
                         }
                         rng.fill(&mut data[offset..(offset + stride)]);
                     }
@@ -529,13 +1635,7 @@ fn testbuffer_list_state_random_data(
                     let offset: usize = rand_range_i(rng, 0, data_len, stride);
                     if data_len > data_min_len {
                         if data_len > offset + stride {
-                            unsafe {
-                                ptr::copy(
-                                    &data[offset + stride],
-                                    &mut data[offset],
-                                    data_len - (offset + stride),
-                                );
-                            }
+                            data.copy_within((offset + stride)..data_len, offset);
                         }
                         data_len -= stride;
                         data.truncate(data_len);
@@ -562,6 +1662,33 @@ fn testbuffer_list_state_random_data(
     testbuffer_list_add(cl, data);
 }
 
+/// Add `items_total` buffers, each a mutated copy of the previous,
+/// see `testbuffer_list_state_random_data`.
+fn testbuffer_list_random_data(
+    cl: &mut Vec<TestBuffer>,
+    stride: usize,
+    data_min_len: usize, data_max_len: usize,
+    items_total: usize, random_seed: u32, mutate: usize,
+) {
+    let mut rng = rand::Rng::new(random_seed);
+    for _ in 0..items_total {
+        testbuffer_list_state_random_data(cl, stride, data_min_len, data_max_len, mutate, &mut rng);
+    }
+}
+
+/// Move every other buffer (starting with the first) out of `cl`.
+fn testbuffer_list_split_alternate(
+    cl: &mut Vec<TestBuffer>,
+) -> Vec<TestBuffer> {
+    let mut cl_remove: Vec<TestBuffer> = Vec::new();
+    let mut i = 0;
+    while i < cl.len() {
+        cl_remove.push(cl.remove(i));
+        i += 1;
+    }
+    cl_remove
+}
+
 fn random_data_mutate_helper(
     items_size_min: usize, items_size_max: usize, items_total: usize,
     stride: usize, chunk_count: usize,
@@ -583,12 +1710,138 @@ fn random_data_mutate_helper(
     testbuffer_run_tests_simple(&mut cl, stride, chunk_count);
 }
 
+fn random_data_mutate_delta_helper(
+    items_size_min: usize, items_size_max: usize, items_total: usize,
+    stride: usize, chunk_count: usize,
+    random_seed: u32, mutate: usize, delta_strides_max: usize)
+{
+    let mut cl: Vec<TestBuffer> = Vec::new();
+
+    let data_min_len = items_size_min * stride;
+    let data_max_len = items_size_max * stride;
+
+    testbuffer_list_random_data(&mut cl, stride, data_min_len, data_max_len, items_total, random_seed, mutate);
+
+    let mut bs = BArrayStore::new(stride, chunk_count);
+    bs.delta_strides_max_set(delta_strides_max);
+    testbuffer_run_tests(&mut bs, &mut cl);
+
+    for numeric_type in &[NumericType::Int32, NumericType::Int64, NumericType::Float64] {
+        if stride % numeric_type.size() == 0 {
+            let mut bs = BArrayStore::new(stride, chunk_count);
+            bs.delta_strides_max_set(delta_strides_max);
+            bs.delta_numeric_set(Some(*numeric_type));
+            testbuffer_run_tests(&mut bs, &mut cl);
+        }
+    }
+}
+
+/// Remove every other state, then compact the remaining states.
+fn random_data_compact_helper(
+    items_size_min: usize, items_size_max: usize, items_total: usize,
+    stride: usize, chunk_count: usize,
+    random_seed: u32, mutate: usize)
+{
+    let mut cl: Vec<TestBuffer> = Vec::new();
+
+    let data_min_len = items_size_min * stride;
+    let data_max_len = items_size_max * stride;
+
+    testbuffer_list_random_data(&mut cl, stride, data_min_len, data_max_len, items_total, random_seed, mutate);
+
+    let mut bs = BArrayStore::new(stride, chunk_count);
+    testbuffer_list_store_populate(&mut bs, &mut cl);
+
+    let mut cl_remove = testbuffer_list_split_alternate(&mut cl);
+    testbuffer_list_store_clear(&mut bs, &mut cl_remove);
+
+    let size_compacted = bs.calc_size_compacted_get();
+    bs.compact();
+    assert_eq!(Ok(()), bs.validate());
+    assert!(testbuffer_list_validate(&bs, &mut cl));
+    assert!(bs.calc_size_compacted_get() <= size_compacted);
+
+    testbuffer_list_store_clear(&mut bs, &mut cl);
+    assert_eq!(0, bs.calc_size_compacted_get());
+}
+
+fn random_data_deferred_helper(
+    items_size_min: usize, items_size_max: usize, items_total: usize,
+    stride: usize, chunk_count: usize,
+    random_seed: u32, mutate: usize)
+{
+    let mut cl: Vec<TestBuffer> = Vec::new();
+
+    let data_min_len = items_size_min * stride;
+    let data_max_len = items_size_max * stride;
+
+    testbuffer_list_random_data(&mut cl, stride, data_min_len, data_max_len, items_total, random_seed, mutate);
+
+    // store synchronously to compare against
+    let mut bs_sync = BArrayStore::new(stride, chunk_count);
+    testbuffer_list_store_populate(&mut bs_sync, &mut cl);
+    let size_compacted_sync = bs_sync.calc_size_compacted_get();
+    testbuffer_list_store_clear(&mut bs_sync, &mut cl);
+
+    let mut bs = BArrayStore::new(stride, chunk_count);
+    {
+        let mut state_prev: Option<StateId> = None;
+        for tb in &mut cl {
+            tb.state = Some(bs.state_add_deferred(&tb.data[..], state_prev));
+            state_prev = tb.state;
+        }
+    }
+    assert_eq!(cl.len() - 1, bs.dedup_pending_len());
+    assert!(testbuffer_list_validate(&bs, &mut cl));
+
+    // de-duplicate a little at a time, the contents must never change
+    let budget = data_max_len * 4;
+    while bs.dedup_pending(budget) != 0 {
+        assert_eq!(Ok(()), bs.validate());
+        assert!(testbuffer_list_validate(&bs, &mut cl));
+    }
+    assert_eq!(Ok(()), bs.validate());
+    assert!(testbuffer_list_validate(&bs, &mut cl));
+    assert_eq!(size_compacted_sync, bs.calc_size_compacted_get());
+    testbuffer_list_store_clear(&mut bs, &mut cl);
+
+    // remove reference states before de-duplicating
+    {
+        let mut state_prev: Option<StateId> = None;
+        for tb in &mut cl {
+            tb.state = Some(bs.state_add_deferred(&tb.data[..], state_prev));
+            state_prev = tb.state;
+        }
+    }
+    let mut cl_remove = testbuffer_list_split_alternate(&mut cl);
+    testbuffer_list_store_clear(&mut bs, &mut cl_remove);
+    assert_eq!(Ok(()), bs.validate());
+    assert_eq!(0, bs.dedup_pending(usize::max_value()));
+    assert_eq!(Ok(()), bs.validate());
+    assert!(testbuffer_list_validate(&bs, &mut cl));
+
+    testbuffer_list_store_clear(&mut bs, &mut cl);
+    assert_eq!(0, bs.calc_size_compacted_get());
+}
+
 #[test] fn rand_data_stride1_chunk32_mutate2()  { random_data_mutate_helper(0,   100,  400,  1,  32,  9779, 2); }
 #[test] fn rand_data_stride8_chunk512_mutate2() { random_data_mutate_helper(0,   128,  400,  8, 512,  1001, 2); }
 #[test] fn rand_data_stride12_chunk48_mutate2() { random_data_mutate_helper(200, 256,  400, 12,  48,  1331, 2); }
 #[test] fn rand_data_stride32_chunk64_mutate1() { random_data_mutate_helper(0,   256,  200, 32,  64,  3112, 1); }
 #[test] fn rand_data_stride32_chunk64_mutate8() { random_data_mutate_helper(0,   256,  200, 32,  64,  7117, 8); }
 
+#[test] fn rand_data_delta_stride1_chunk32_mutate2()  { random_data_mutate_delta_helper(0,   100,  400,  1,  32,  9779, 2, 2); }
+#[test] fn rand_data_delta_stride12_chunk48_mutate2() { random_data_mutate_delta_helper(200, 256,  400, 12,  48,  1331, 2, 4); }
+#[test] fn rand_data_delta_stride32_chunk64_mutate8() { random_data_mutate_delta_helper(0,   256,  200, 32,  64,  7117, 8, 8); }
+
+#[test] fn rand_data_compact_stride1_chunk32_mutate2()  { random_data_compact_helper(0,   100,  400,  1,  32,  9779, 2); }
+#[test] fn rand_data_compact_stride12_chunk48_mutate2() { random_data_compact_helper(200, 256,  400, 12,  48,  1331, 2); }
+#[test] fn rand_data_compact_stride32_chunk64_mutate8() { random_data_compact_helper(0,   256,  200, 32,  64,  7117, 8); }
+
+#[test] fn rand_data_deferred_stride1_chunk32_mutate2()  { random_data_deferred_helper(0,   100,  400,  1,  32,  9779, 2); }
+#[test] fn rand_data_deferred_stride12_chunk48_mutate2() { random_data_deferred_helper(200, 256,  400, 12,  48,  1331, 2); }
+#[test] fn rand_data_deferred_stride32_chunk64_mutate8() { random_data_deferred_helper(0,   256,  200, 32,  64,  7117, 8); }
+
 
 /* -------------------------------------------------------------------- */
 /* Randomized Chunks Test */
@@ -602,8 +1855,7 @@ fn random_chunk_generate(
     let mut rng = rand::Rng::new(random_seed);
     let chunk_size_bytes: usize = stride * chunk_count;
     for _ in 0..chunks_per_buffer {
-        let mut data_chunk: Vec<u8> = Vec::with_capacity(chunk_size_bytes);
-        unsafe { data_chunk.set_len(chunk_size_bytes); }
+        let mut data_chunk: Vec<u8> = vec![0; chunk_size_bytes];
         rng.fill(&mut data_chunk);
         testchunk_list_add(cl, data_chunk);
     }
@@ -653,4 +1905,3 @@ fn random_chunk_mutate_helper(
 #[test] fn rand_chunk_32_stride1_chunk64()  { random_chunk_mutate_helper(32, 100,  1, 64, 1331); }
 #[test] fn rand_chunk_64_stride8_chunk32()  { random_chunk_mutate_helper(64, 100,  8, 32, 2772); }
 #[test] fn rand_chunk_31_stride11_chunk21() { random_chunk_mutate_helper(31, 100, 11, 21, 7117); }
-